@@ -0,0 +1,70 @@
+//! compares full-size jpeg decoding with dct-scaled decoding (both followed by `thumbnail()`)
+//!
+//! usage: cargo run --release --example jpeg_decode_bench -- <fixture-dir> [max-x] [max-y]
+
+use std::{ env, fs, path::PathBuf, time::{ Duration, Instant } };
+
+const USAGE: &str = "usage: jpeg_decode_bench <fixture-dir> [max-x] [max-y]";
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let fixture_dir = PathBuf::from(args.next().expect(USAGE));
+    let max_x: u32 = args.next().map_or(128, |arg| arg.parse().expect(USAGE));
+    let max_y: u32 = args.next().map_or(128, |arg| arg.parse().expect(USAGE));
+
+    let mut fixtures: Vec<PathBuf> = fs
+        ::read_dir(&fixture_dir)
+        .expect("failed to read fixture directory")
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("jpg") || ext.eq_ignore_ascii_case("jpeg"))
+        })
+        .collect();
+    fixtures.sort();
+
+    let mut full_total = Duration::ZERO;
+    let mut scaled_total = Duration::ZERO;
+
+    for path in &fixtures {
+        let timer = Instant::now();
+        let full = match image::open(path) {
+            Ok(dyn_image) => dyn_image.thumbnail(max_x, max_y),
+            Err(err) => {
+                println!("skipping {} ({err})", path.display());
+                continue;
+            }
+        };
+        let full_dur = timer.elapsed();
+
+        let timer = Instant::now();
+        let scaled = match thumbnailed::decode_jpeg_scaled(path, max_x, max_y) {
+            Ok(dyn_image) => dyn_image.thumbnail(max_x, max_y),
+            Err(err) => {
+                println!("skipping {} ({err})", path.display());
+                continue;
+            }
+        };
+        let scaled_dur = timer.elapsed();
+
+        full_total += full_dur;
+        scaled_total += scaled_dur;
+
+        println!(
+            "{}: full {full_dur:.2?} ({}x{}), scaled {scaled_dur:.2?} ({}x{}) -> {:.1}x",
+            path.display(),
+            full.width(),
+            full.height(),
+            scaled.width(),
+            scaled.height(),
+            full_dur.as_secs_f64() / scaled_dur.as_secs_f64()
+        );
+    }
+
+    println!(
+        "{} files: full {full_total:.2?}, scaled {scaled_total:.2?} -> {:.1}x faster",
+        fixtures.len(),
+        full_total.as_secs_f64() / scaled_total.as_secs_f64().max(f64::EPSILON)
+    );
+}
@@ -3,6 +3,7 @@ use std::{
     num::NonZeroUsize,
     path::PathBuf,
     process::Command,
+    sync::{ mpsc, Arc },
     thread,
     time::{ Duration, Instant },
 };
@@ -13,7 +14,17 @@ use crate::*;
 
 pub struct ThumbnailedApp {
     pub thumbnail_paths: Vec<ThumbnailPaths>,
+    /// bumped on every change of `thumbnail_paths` (a removal and an insertion keep the length)
+    pub thumbnails_generation: u64,
+    /// all original paths per content hash (byte-identical files)
+    pub paths_by_content: HBHashMap<String, Vec<PathBuf>>,
+    /// (path, reason) of files, that exceeded the decoder limits
+    pub skipped_for_safety: Vec<(PathBuf, LimitExceeded)>,
     pub cached_thumbnails: HBHashMap<PathBuf, Option<egui::TextureHandle>>,
+    /// frames (+ delays) of animated previews, loaded on first hover
+    pub cached_animations: HBHashMap<PathBuf, Option<Vec<(egui::TextureHandle, Duration)>>>,
+    /// decoded blurhashes, shown while the thumbnail textures are loading
+    pub cached_placeholders: HBHashMap<String, Option<egui::TextureHandle>>,
 
     pub load_data: Option<LoadData>,
 
@@ -31,10 +42,143 @@ pub struct ThumbnailedApp {
     pub last_cache_size_update: Instant,
 
     pub show_path_on_hover: bool,
+    pub gallery_sort: GallerySort,
+    /// (thumbnails generation, sort) the gallery was sorted for, None forces a sort
+    pub sorted_for: Option<(u64, GallerySort)>,
+    pub color_filter: ColorFilter,
+    pub quality_filter: QualityFilter,
 
     pub timing_info: Timings,
 
     pub thumbnailer: Option<thumbnailer::SpawnedThumbnailer>,
+    /// providers used by every order of the thumbnailer
+    pub provider_registry: Arc<ProviderRegistry>,
+
+    pub duplicate_finder: DuplicateFinder,
+    pub similar_view: SimilarView,
+    pub stacks: BurstStacks,
+}
+
+/// state of the "duplicates" window
+pub struct DuplicateFinder {
+    pub open: bool,
+    /// maximum number of differing bits between the perceptual hashes of one group
+    pub max_distance: u32,
+    pub groups: Vec<DuplicateGroup>,
+    /// group, whose permanent deletion waits for confirmation
+    pub confirm_delete: Option<usize>,
+    /// running search (comparing all pairs of hashes is too slow for the ui thread)
+    pub search: Option<mpsc::Receiver<Vec<DuplicateGroup>>>,
+}
+
+impl Default for DuplicateFinder {
+    fn default() -> Self {
+        Self {
+            open: false,
+            max_distance: 6,
+            groups: Vec::new(),
+            confirm_delete: None,
+            search: None,
+        }
+    }
+}
+
+pub struct DuplicateGroup {
+    pub members: Vec<DuplicateMember>,
+    /// index of the member, that survives deleting / trashing the others
+    pub keep: usize,
+}
+
+pub struct DuplicateMember {
+    pub original: PathBuf,
+    pub thumbnail: PathBuf,
+    pub width: u32,
+    pub height: u32,
+    pub file_size: StorageSize,
+}
+
+/// state of the "similar images" window
+pub struct SimilarView {
+    pub open: bool,
+    /// number of neighbours shown
+    pub count: usize,
+    /// image, whose neighbours are shown
+    pub query: Option<SimilarImage>,
+    /// nearest first
+    pub results: Vec<SimilarImage>,
+}
+
+impl Default for SimilarView {
+    fn default() -> Self {
+        Self { open: false, count: 12, query: None, results: Vec::new() }
+    }
+}
+
+/// bursts, that the gallery collapses into one tile
+pub struct BurstStacks {
+    pub enabled: bool,
+    /// maximum time between two consecutive shots of a stack
+    pub max_gap_seconds: u32,
+    /// maximum feature distance between two consecutive shots of a stack
+    pub max_distance: f32,
+    /// original paths of the members, the representative (sharpest shot) first
+    pub members: Vec<Vec<PathBuf>>,
+    /// index into `members` per original path
+    pub stack_of: HBHashMap<PathBuf, usize>,
+    /// original paths of the members of expanded stacks (survives regrouping)
+    pub expanded: hashbrown::HashSet<PathBuf>,
+    /// (thumbnails generation, filters) the stacks were grouped for, None forces a regrouping
+    ///
+    /// only visible images are stacked, otherwise a hidden representative would hide the whole stack
+    pub grouped_for: Option<(u64, ColorFilter, QualityFilter)>,
+}
+
+impl Default for BurstStacks {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_gap_seconds: 3,
+            max_distance: 0.15,
+            members: Vec::new(),
+            stack_of: HBHashMap::new(),
+            expanded: hashbrown::HashSet::new(),
+            grouped_for: None,
+        }
+    }
+}
+
+impl BurstStacks {
+    /// (stack index, is the representative, is collapsed) of an image
+    pub fn state_of(&self, original: &Path) -> Option<(usize, bool, bool)> {
+        if !self.enabled {
+            return None;
+        }
+
+        self.stack_of.get(original).map(|stack| {
+            let members = &self.members[*stack];
+            let collapsed = !members.iter().any(|member| self.expanded.contains(member));
+            (*stack, members[0] == original, collapsed)
+        })
+    }
+
+    pub fn toggle(&mut self, stack: usize) {
+        let members = &self.members[stack];
+        match members.iter().any(|member| self.expanded.contains(member)) {
+            true => {
+                for member in members {
+                    self.expanded.remove(member);
+                }
+            }
+            false => self.expanded.extend(members.iter().cloned()),
+        }
+    }
+}
+
+pub struct SimilarImage {
+    pub original: PathBuf,
+    pub thumbnail: PathBuf,
+    /// feature distance to the query (0.0 = identical)
+    pub distance: f32,
 }
 
 impl ThumbnailedApp {
@@ -57,14 +201,440 @@ impl ThumbnailedApp {
         self.cache_size = StorageSize::from_dir(self.thumbnail_path.clone()).unwrap_or_default();
     }
 
+    /// groups the current thumbnails by their perceptual hashes (on a background thread),
+    /// the largest image of each group is kept by default
+    pub fn find_duplicates(&mut self) {
+        // (hash, member without file size), thumbnails without a hash can't be duplicates
+        let candidates: Vec<(Option<u64>, DuplicateMember)> = self.thumbnail_paths
+            .iter()
+            .filter(|thumbnail_paths| thumbnail_paths.perceptual_hash.is_some())
+            .map(|thumbnail_paths| {
+                (
+                    thumbnail_paths.perceptual_hash,
+                    DuplicateMember {
+                        original: thumbnail_paths.original.clone(),
+                        thumbnail: thumbnail_paths.thumbnail.clone(),
+                        width: thumbnail_paths.metadata.width,
+                        height: thumbnail_paths.metadata.height,
+                        file_size: StorageSize::default(),
+                    },
+                )
+            })
+            .collect();
+        let max_distance = self.duplicate_finder.max_distance;
+
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let hashes: Vec<Option<u64>> = candidates
+                .iter()
+                .map(|(hash, _)| *hash)
+                .collect();
+            let mut candidates: Vec<Option<DuplicateMember>> = candidates
+                .into_iter()
+                .map(|(_, member)| Some(member))
+                .collect();
+
+            let groups: Vec<DuplicateGroup> = group_duplicates(&hashes, max_distance)
+                .into_iter()
+                .map(|group| {
+                    let members: Vec<DuplicateMember> = group
+                        .into_iter()
+                        .filter_map(|i| candidates[i].take())
+                        .map(|mut member| {
+                            member.file_size = StorageSize::new(
+                                fs::metadata(&member.original)
+                                    .map(|metadata| metadata.len())
+                                    .unwrap_or_default()
+                            );
+                            member
+                        })
+                        .collect();
+
+                    let keep = members
+                        .iter()
+                        .enumerate()
+                        .max_by_key(|(_, member)| {
+                            (u64::from(member.width) * u64::from(member.height), member.file_size)
+                        })
+                        .map(|(i, _)| i)
+                        .unwrap_or_default();
+
+                    DuplicateGroup { members, keep }
+                })
+                .collect();
+
+            // fails, if the search was restarted or the cache cleared in the meantime
+            if sender.send(groups).is_err() {
+                log::trace!("duplicate search was cancelled");
+            }
+        });
+
+        self.duplicate_finder.search = Some(receiver);
+        self.duplicate_finder.groups.clear();
+        self.duplicate_finder.confirm_delete = None;
+    }
+
+    /// deletes (or moves to the trash) every image of the group except the one to keep
+    pub fn remove_duplicates(&mut self, group_index: usize, permanently: bool) {
+        let group = self.duplicate_finder.groups.remove(group_index);
+        self.duplicate_finder.confirm_delete = None;
+
+        for (i, member) in group.members.iter().enumerate() {
+            if i == group.keep {
+                continue;
+            }
+
+            let removed = match permanently {
+                true => fs::remove_file(&member.original).map_err(|err| err.to_string()),
+                false => trash::delete(&member.original).map_err(|err| err.to_string()),
+            };
+            match removed {
+                Ok(()) => {
+                    log::debug!("removed duplicate {}", member.original.display());
+                    self.thumbnail_paths.retain(|thumbnail_paths| {
+                        thumbnail_paths.original != member.original
+                    });
+                    self.thumbnails_generation += 1;
+                    for paths in self.paths_by_content.values_mut() {
+                        paths.retain(|path| *path != member.original);
+                    }
+
+                    // byte-identical files share their thumbnail
+                    let is_shared = self.thumbnail_paths
+                        .iter()
+                        .any(|thumbnail_paths| thumbnail_paths.thumbnail == member.thumbnail);
+                    if !is_shared {
+                        self.cached_thumbnails.remove(&member.thumbnail);
+                        if let Err(err) = fs::remove_file(&member.thumbnail) {
+                            log::debug!("failed to remove thumbnail of duplicate ({err})");
+                        }
+                    }
+                }
+                Err(err) =>
+                    log::warn!("failed to remove duplicate {} ({err})", member.original.display()),
+            }
+        }
+    }
+
+    /// groups bursts (shots within a few seconds, that look alike) into stacks
+    ///
+    /// images hidden by the color / quality filter aren't stacked
+    pub fn group_stacks(&mut self) {
+        let timestamps: Vec<Option<Timestamp>> = self.thumbnail_paths
+            .iter()
+            .map(|thumbnail_paths| {
+                match
+                    self.color_filter.matches(thumbnail_paths) &&
+                    self.quality_filter.matches(thumbnail_paths)
+                {
+                    true => thumbnail_paths.metadata.timestamp(),
+                    false => None,
+                }
+            })
+            .collect();
+        let features: Vec<&[f32]> = self.thumbnail_paths
+            .iter()
+            .map(|thumbnail_paths| thumbnail_paths.feature_vector.as_slice())
+            .collect();
+
+        let groups = group_stacks(
+            &timestamps,
+            &features,
+            i64::from(self.stacks.max_gap_seconds),
+            self.stacks.max_distance
+        );
+
+        self.stacks.stack_of.clear();
+        self.stacks.members = groups
+            .into_iter()
+            .enumerate()
+            .map(|(stack, group)| {
+                let mut members: Vec<PathBuf> = group
+                    .iter()
+                    .map(|i| self.thumbnail_paths[*i].original.clone())
+                    .collect();
+
+                // the sharpest shot represents the stack (the first one, if there are no metrics)
+                let sharpness = |i: usize| {
+                    self.thumbnail_paths[group[i]].quality
+                        .map(|quality| quality.sharpness)
+                        .unwrap_or_default()
+                };
+                let representative = (0..group.len())
+                    .max_by(|a, b| sharpness(*a).total_cmp(&sharpness(*b)).then(b.cmp(a)))
+                    .unwrap_or_default();
+                let representative = members.remove(representative);
+                members.insert(0, representative);
+
+                for member in &members {
+                    self.stacks.stack_of.insert(member.clone(), stack);
+                }
+                members
+            })
+            .collect();
+        self.stacks.grouped_for = Some(self.stacks_key());
+
+        log::trace!("grouped {} stacks", self.stacks.members.len());
+    }
+
+    /// changes of the thumbnails or filters, that require a regrouping of the stacks
+    fn stacks_key(&self) -> (u64, ColorFilter, QualityFilter) {
+        (self.thumbnails_generation, self.color_filter, self.quality_filter)
+    }
+
+    /// shows the images, whose feature vectors are nearest to the one of `original`
+    pub fn find_similar(&mut self, original: &Path) {
+        let query = match
+            self.thumbnail_paths
+                .iter()
+                .position(|thumbnail_paths| thumbnail_paths.original == original)
+        {
+            Some(query) => query,
+            None => {
+                return;
+            }
+        };
+
+        let features: Vec<&[f32]> = self.thumbnail_paths
+            .iter()
+            .map(|thumbnail_paths| thumbnail_paths.feature_vector.as_slice())
+            .collect();
+        let neighbours = most_similar(&features, query, self.similar_view.count);
+        log::trace!("found {} similar images for {}", neighbours.len(), original.display());
+
+        let similar_image = |i: usize, distance: f32| SimilarImage {
+            original: self.thumbnail_paths[i].original.clone(),
+            thumbnail: self.thumbnail_paths[i].thumbnail.clone(),
+            distance,
+        };
+        self.similar_view.query = Some(similar_image(query, 0.0));
+        self.similar_view.results = neighbours
+            .into_iter()
+            .map(|(i, distance)| similar_image(i, distance))
+            .collect();
+        self.similar_view.open = true;
+    }
+
+    fn show_similar_view(&mut self, ctx: &egui::Context) {
+        // original path of the next query (a clicked result or a changed count)
+        let mut next_query = None;
+
+        let view = &mut self.similar_view;
+        let cached_thumbnails = &self.cached_thumbnails;
+
+        egui::Window
+            ::new("similar images")
+            .open(&mut view.open)
+            .resizable(true)
+            .show(ctx, |ui| {
+                let query = match &view.query {
+                    Some(query) => query,
+                    None => {
+                        ui.label("right click an image and choose \"show similar\"");
+                        return;
+                    }
+                };
+
+                ui.horizontal(|ui| {
+                    match cached_thumbnails.get(&query.thumbnail) {
+                        Some(Some(texture_handle)) => {
+                            ui.image((texture_handle.id(), texture_handle.size_vec2()));
+                        }
+                        _ => {
+                            ui.label("not loaded");
+                        }
+                    }
+
+                    ui.vertical(|ui| {
+                        ui.label(query.original.display().to_string());
+                        ui.horizontal(|ui| {
+                            ui.label("count: ");
+                            let count = egui::DragValue::new(&mut view.count).clamp_range(1..=200);
+                            if ui.add(count).changed() {
+                                next_query = Some(query.original.clone());
+                            }
+                        });
+                    });
+                });
+
+                ui.separator();
+
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    ui.horizontal_wrapped(|ui| {
+                        for result in &view.results {
+                            ui.vertical(|ui| {
+                                match cached_thumbnails.get(&result.thumbnail) {
+                                    Some(Some(texture_handle)) => {
+                                        let response = ui
+                                            .add(
+                                                egui::Image
+                                                    ::new((
+                                                        texture_handle.id(),
+                                                        texture_handle.size_vec2(),
+                                                    ))
+                                                    .sense(egui::Sense::click())
+                                            )
+                                            .on_hover_text(
+                                                format!(
+                                                    "{}\nclick to show images similar to this one",
+                                                    result.original.display()
+                                                )
+                                            );
+                                        if response.clicked() {
+                                            next_query = Some(result.original.clone());
+                                        }
+                                    }
+                                    _ => {
+                                        ui.label("not loaded").on_hover_text(
+                                            result.original.display().to_string()
+                                        );
+                                    }
+                                }
+                                ui.label(format!("distance: {:.3}", result.distance));
+                            });
+                        }
+                    });
+                });
+            });
+
+        if let Some(original) = next_query {
+            self.find_similar(&original);
+        }
+    }
+
+    fn show_duplicate_finder(&mut self, ctx: &egui::Context) {
+        let mut search = false;
+        // (group index, permanently)
+        let mut removal = None;
+
+        if let Some(receiver) = &self.duplicate_finder.search {
+            match receiver.try_recv() {
+                Ok(groups) => {
+                    self.duplicate_finder.groups = groups;
+                    self.duplicate_finder.search = None;
+                }
+                Err(mpsc::TryRecvError::Empty) => ctx.request_repaint(),
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    log::warn!("duplicate search stopped without a result");
+                    self.duplicate_finder.search = None;
+                }
+            }
+        }
+
+        let finder = &mut self.duplicate_finder;
+        let cached_thumbnails = &self.cached_thumbnails;
+
+        egui::Window
+            ::new("duplicates")
+            .open(&mut finder.open)
+            .resizable(true)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("max distance: ");
+                    ui.add(
+                        egui::DragValue::new(&mut finder.max_distance).clamp_range(0..=32)
+                    ).on_hover_text(
+                        "differing bits of the 64 bit perceptual hashes (0 = visually identical)"
+                    );
+
+                    search = ui
+                        .add_enabled(finder.search.is_none(), egui::Button::new("search"))
+                        .clicked();
+                    match finder.search.is_some() {
+                        true => {
+                            ui.spinner();
+                        }
+                        false => {
+                            ui.label(format!("{} groups", finder.groups.len()));
+                        }
+                    }
+                });
+
+                ui.separator();
+
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    for (group_index, group) in finder.groups.iter_mut().enumerate() {
+                        ui.horizontal(|ui| {
+                            for (i, member) in group.members.iter().enumerate() {
+                                ui.vertical(|ui| {
+                                    match cached_thumbnails.get(&member.thumbnail) {
+                                        Some(Some(texture_handle)) => {
+                                            ui.image((texture_handle.id(), texture_handle.size_vec2()));
+                                        }
+                                        _ => {
+                                            ui.label("not loaded");
+                                        }
+                                    }
+
+                                    let file_name = member.original
+                                        .file_name()
+                                        .map(|name| name.to_string_lossy().into_owned())
+                                        .unwrap_or_default();
+                                    ui.radio_value(&mut group.keep, i, file_name).on_hover_text(
+                                        member.original.display().to_string()
+                                    );
+                                    ui.label(
+                                        format!(
+                                            "{}x{}, {:.2} MB",
+                                            member.width,
+                                            member.height,
+                                            member.file_size.as_megabytes()
+                                        )
+                                    );
+                                });
+                            }
+                        });
+
+                        let others = group.members.len() - 1;
+                        ui.horizontal(|ui| {
+                            match finder.confirm_delete == Some(group_index) {
+                                true => {
+                                    ui.label(format!("permanently delete {others} files?"));
+                                    if ui.button("yes").clicked() {
+                                        removal = Some((group_index, true));
+                                    }
+                                    if ui.button("no").clicked() {
+                                        finder.confirm_delete = None;
+                                    }
+                                }
+                                false => {
+                                    if ui.button(format!("move {others} others to trash")).clicked() {
+                                        removal = Some((group_index, false));
+                                    }
+                                    if ui.button(format!("delete {others} others")).clicked() {
+                                        finder.confirm_delete = Some(group_index);
+                                    }
+                                }
+                            }
+                        });
+
+                        ui.separator();
+                    }
+                });
+            });
+
+        if search {
+            self.find_duplicates();
+        }
+        if let Some((group_index, permanently)) = removal {
+            self.remove_duplicates(group_index, permanently);
+        }
+    }
+
     const CACHE_SIZE_UPDATE_INTERVAL: Duration = Duration::from_millis(250);
     const MAX_THUMBRECV_PER_FRAME: usize = 10;
+    const MAX_SKIPPED_IN_TOOLTIP: usize = 20;
+    /// the remaining tiles show their placeholder and are loaded in the following frames
+    const MAX_TEXTURE_LOADS_PER_FRAME: usize = 16;
 }
 
 impl Default for ThumbnailedApp {
     fn default() -> Self {
         Self {
             thumbnail_paths: Vec::new(),
+            thumbnails_generation: 0,
+            paths_by_content: HBHashMap::new(),
+            skipped_for_safety: Vec::new(),
             load_data: None,
             thumbnail_path: PathBuf::from("tmp/thumbs-cache"),
             load_dialouge_data: LoadDialougeData {
@@ -72,9 +642,20 @@ impl Default for ThumbnailedApp {
                 thread_count: 4,
                 max_x: 128,
                 max_y: 128,
+                apply_orientation: true,
+                convert_to_srgb: true,
+                embed_srgb_tag: false,
+                scaling: ScalingOptions::default(),
+                decode_limits: DecodeLimits::default(),
+                animated_previews: true,
+                storyboard_frames: 6,
             },
             show_load_dialouge: false,
             thumbnailer: None,
+            provider_registry: Arc::new(ProviderRegistry::default()),
+            duplicate_finder: DuplicateFinder::default(),
+            similar_view: SimilarView::default(),
+            stacks: BurstStacks::default(),
             // allowed_to_close: false,
             // show_close_dialouge: false,
             update_gallery: true,
@@ -82,8 +663,14 @@ impl Default for ThumbnailedApp {
             cache_size: StorageSize::new(0),
             last_cache_size_update: Instant::now(),
             show_path_on_hover: true,
+            gallery_sort: GallerySort::default(),
+            sorted_for: None,
+            color_filter: ColorFilter::default(),
+            quality_filter: QualityFilter::default(),
             timing_info: Timings::new(Duration::from_secs_f64(2.5)),
             cached_thumbnails: HBHashMap::new(),
+            cached_animations: HBHashMap::new(),
+            cached_placeholders: HBHashMap::new(),
         }
     }
 }
@@ -102,7 +689,7 @@ impl eframe::App for ThumbnailedApp {
 
         // making sure, that there is a Thumbnailer:
         if self.thumbnailer.is_none() {
-            match thumbnailer::spawn_thumbnailer_thread() {
+            match thumbnailer::spawn_thumbnailer_thread(Arc::clone(&self.provider_registry)) {
                 Ok(spwnd_thumbnailer) => {
                     self.thumbnailer = Some(spwnd_thumbnailer);
                 }
@@ -118,7 +705,17 @@ impl eframe::App for ThumbnailedApp {
                 while let Ok(msg) = thumbnailer.receiver.try_recv() {
                     match msg {
                         ThumbnailerToApp::CreatedThumbnail(data) => {
-                            self.thumbnail_paths.push(data);
+                            if let Some(content_hash) = &data.content_hash {
+                                self.paths_by_content
+                                    .entry(content_hash.clone())
+                                    .or_default()
+                                    .push(data.original.clone());
+                            }
+                            self.thumbnail_paths.push(*data);
+                            self.thumbnails_generation += 1;
+                        }
+                        ThumbnailerToApp::SkippedForSafety { path, reason } => {
+                            self.skipped_for_safety.push((path, reason));
                         }
                         ThumbnailerToApp::Status(status) => {
                             log::debug!("received status update from thumbnailer: {status:?}");
@@ -145,8 +742,18 @@ impl eframe::App for ThumbnailedApp {
                     recv_i += 1;
                 }
             }
+        }
+
+        // new thumbnails or another sort (also while the gallery isn't updated)
+        let sort_key = (self.thumbnails_generation, self.gallery_sort);
+        if self.sorted_for != Some(sort_key) {
+            let gallery_sort = self.gallery_sort;
+            self.thumbnail_paths.sort_by(|a, b| { gallery_sort.compare(a, b) });
+            self.sorted_for = Some(sort_key);
+        }
 
-            self.thumbnail_paths.sort_by(|a, b| { a.original.cmp(&b.original) });
+        if self.stacks.enabled && self.stacks.grouped_for != Some(self.stacks_key()) {
+            self.group_stacks();
         }
 
         // TopPanel:
@@ -164,6 +771,105 @@ impl eframe::App for ThumbnailedApp {
                         ui.close_menu();
                     }
 
+                    ui.menu_button("sort by", |ui| {
+                        if ui.radio_value(&mut self.gallery_sort, GallerySort::Path, "path").clicked() {
+                            ui.close_menu();
+                        }
+                        if
+                            ui
+                                .radio_value(
+                                    &mut self.gallery_sort,
+                                    GallerySort::CaptureDate,
+                                    "capture date"
+                                )
+                                .clicked()
+                        {
+                            ui.close_menu();
+                        }
+                        if ui.radio_value(&mut self.gallery_sort, GallerySort::Hue, "hue").clicked() {
+                            ui.close_menu();
+                        }
+                    });
+
+                    ui.menu_button("filter by color", |ui| {
+                        let filter = &mut self.color_filter;
+                        ui.checkbox(&mut filter.enabled, "only images with a color near");
+                        ui.horizontal(|ui| {
+                            egui::color_picker::color_edit_button_srgb(ui, &mut filter.color);
+                            ui.label("tolerance: ");
+                            ui.add(egui::Slider::new(&mut filter.tolerance, 2.0..=60.0)).on_hover_text(
+                                "perceptual distance (cie76 delta e) to the colors of the image palette"
+                            );
+                        });
+                    });
+
+                    ui.menu_button("filter by quality", |ui| {
+                        let filter = &mut self.quality_filter;
+                        ui.radio_value(&mut filter.show, QualityShow::All, "show all");
+                        ui.radio_value(&mut filter.show, QualityShow::OnlyBlurry, "show only blurry");
+                        ui.radio_value(
+                            &mut filter.show,
+                            QualityShow::OnlyBadlyExposed,
+                            "show only under- / overexposed"
+                        );
+                        ui.radio_value(&mut filter.show, QualityShow::OnlyBlank, "show only blank");
+                        ui.separator();
+                        ui.checkbox(&mut filter.hide_blurry, "hide blurry");
+                        ui.checkbox(&mut filter.hide_badly_exposed, "hide under- / overexposed");
+                        ui.checkbox(&mut filter.hide_blank, "hide blank");
+                        ui.separator();
+                        ui.horizontal(|ui| {
+                            ui.label("blurry below: ");
+                            ui.add(
+                                egui::Slider::new(&mut filter.blur_threshold, 1.0..=2000.0).logarithmic(true)
+                            ).on_hover_text(
+                                "variance of the laplacian of the thumbnail, depends on the thumbnail size"
+                            );
+                        });
+                    });
+
+                    ui.menu_button("stacks", |ui| {
+                        let stacks = &mut self.stacks;
+                        let mut changed = ui
+                            .checkbox(&mut stacks.enabled, "stack bursts")
+                            .on_hover_text("collapse shots taken within a few seconds, that look alike")
+                            .changed();
+                        ui.horizontal(|ui| {
+                            ui.label("max gap: ");
+                            changed |= ui
+                                .add(
+                                    egui::DragValue
+                                        ::new(&mut stacks.max_gap_seconds)
+                                        .clamp_range(0..=120)
+                                        .suffix(" s")
+                                )
+                                .on_hover_text(
+                                    "between two consecutive shots (capture date, or modification time)"
+                                )
+                                .changed();
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label("max distance: ");
+                            changed |= ui
+                                .add(egui::Slider::new(&mut stacks.max_distance, 0.01..=0.5))
+                                .on_hover_text("between the feature vectors of two consecutive shots")
+                                .changed();
+                        });
+                        if ui.button("collapse all").clicked() {
+                            stacks.expanded.clear();
+                            ui.close_menu();
+                        }
+                        if changed {
+                            stacks.grouped_for = None;
+                        }
+                    });
+
+                    if ui.button("find duplicates").clicked() {
+                        self.duplicate_finder.open = true;
+                        self.find_duplicates();
+                        ui.close_menu();
+                    }
+
                     if ui.button("clear cache").clicked() {
                         match fs::remove_dir_all(&self.thumbnail_path) {
                             Ok(_) => log::debug!("cleared cache"),
@@ -176,7 +882,20 @@ impl eframe::App for ThumbnailedApp {
                         }
 
                         self.cached_thumbnails.clear();
+                        self.cached_animations.clear();
+                        self.cached_placeholders.clear();
                         self.thumbnail_paths.clear();
+                        self.thumbnails_generation += 1;
+                        self.paths_by_content.clear();
+                        self.duplicate_finder.groups.clear();
+                        self.duplicate_finder.search = None;
+                        self.similar_view.query = None;
+                        self.similar_view.results.clear();
+                        self.stacks.members.clear();
+                        self.stacks.stack_of.clear();
+                        self.stacks.expanded.clear();
+                        self.stacks.grouped_for = None;
+                        self.skipped_for_safety.clear();
 
                         ui.close_menu();
                     }
@@ -186,7 +905,7 @@ impl eframe::App for ThumbnailedApp {
                             let _ = thumbnailer.send(AppToThumbnailer::KillCmd);
                             let _ = thumbnailer.join();
                         }
-                        match thumbnailer::spawn_thumbnailer_thread() {
+                        match thumbnailer::spawn_thumbnailer_thread(Arc::clone(&self.provider_registry)) {
                             Ok(spwnd_thumbnailer) => {
                                 self.thumbnailer = Some(spwnd_thumbnailer);
                             }
@@ -228,12 +947,56 @@ impl eframe::App for ThumbnailedApp {
         egui::TopBottomPanel::new(TopBottomSide::Bottom, "BottomPanel").show(ctx, |ui| {
             egui::menu::bar(ui, |ui| {
                 ui.with_layout(Layout::left_to_right(egui::Align::Center), |ui| {
-                    ui.label(format!("{} items", self.thumbnail_paths.len()));
+                    match self.color_filter.enabled || self.quality_filter.is_active() {
+                        true => {
+                            let shown = self.thumbnail_paths
+                                .iter()
+                                .filter(|thumbnail_paths| {
+                                    self.color_filter.matches(thumbnail_paths) &&
+                                        self.quality_filter.matches(thumbnail_paths)
+                                })
+                                .count();
+                            ui.label(format!("{shown} of {} items", self.thumbnail_paths.len()));
+                        }
+                        false => {
+                            ui.label(format!("{} items", self.thumbnail_paths.len()));
+                        }
+                    }
 
                     ui.separator();
 
+                    if self.stacks.enabled {
+                        ui.label(format!("{} stacks", self.stacks.members.len()));
+                        ui.separator();
+                    }
+
                     ui.label(format!("cache: {:.2} MB", self.cache_size.as_megabytes()));
 
+                    let identical_copies: usize = self.paths_by_content
+                        .values()
+                        .map(|paths| paths.len().saturating_sub(1))
+                        .sum();
+                    if identical_copies > 0 {
+                        ui.separator();
+                        ui.label(format!("{identical_copies} identical copies")).on_hover_text(
+                            "byte-identical files, that share one thumbnail"
+                        );
+                    }
+
+                    if !self.skipped_for_safety.is_empty() {
+                        ui.separator();
+
+                        let skipped_list: Vec<String> = self.skipped_for_safety
+                            .iter()
+                            .take(Self::MAX_SKIPPED_IN_TOOLTIP)
+                            .map(|(path, reason)| format!("{}: {reason}", path.display()))
+                            .collect();
+
+                        ui.label(
+                            format!("{} skipped for safety", self.skipped_for_safety.len())
+                        ).on_hover_text(skipped_list.join("\n"));
+                    }
+
                     // ui.separator();
                     // ui.add(egui::ProgressBar::new(0.45).desired_height(12.0))
 
@@ -251,12 +1014,32 @@ impl eframe::App for ThumbnailedApp {
             })
         });
 
+        // original path of the image, whose "show similar" was chosen
+        let mut similar_query = None;
+        // stack, that was expanded / collapsed
+        let mut toggled_stack = None;
+
         // GalleryView
         egui::CentralPanel::default().show(ctx, |ui| {
             egui::ScrollArea::vertical().show(ui, |ui| {
                 ui.horizontal_wrapped(|ui| {
+                    let mut texture_loads = 0;
+
                     // iter over created thumbnails (thumb_path, original_path):
                     for thumbnail_paths in self.thumbnail_paths.iter() {
+                        if
+                            !self.color_filter.matches(thumbnail_paths) ||
+                            !self.quality_filter.matches(thumbnail_paths)
+                        {
+                            continue;
+                        }
+
+                        let stack_state = self.stacks.state_of(&thumbnail_paths.original);
+                        // only the representative of a collapsed stack is shown
+                        if let Some((_, false, true)) = stack_state {
+                            continue;
+                        }
+
                         if let Some(thumb_path_str) = thumbnail_paths.thumbnail.to_str() {
                             let (max_x, max_y) = match &self.load_data {
                                 Some(load_data) => (load_data.max_x, load_data.max_y),
@@ -281,7 +1064,11 @@ impl eframe::App for ThumbnailedApp {
                             //         })
                             // );
 
-                            if self.cached_thumbnails.get(&thumbnail_paths.thumbnail).is_none() {
+                            if
+                                self.cached_thumbnails.get(&thumbnail_paths.thumbnail).is_none() &&
+                                texture_loads < Self::MAX_TEXTURE_LOADS_PER_FRAME
+                            {
+                                texture_loads += 1;
                                 let texture: Option<egui::TextureHandle> = {
                                     match
                                         image::DynamicImage::load_from_path(
@@ -343,27 +1130,186 @@ impl eframe::App for ThumbnailedApp {
                                             // })
                                         );
 
+                                        if let Some(storyboard) = &thumbnail_paths.storyboard {
+                                            if let Some(pointer) = thumb_resp.hover_pos() {
+                                                let texture = self.cached_thumbnails
+                                                    .entry(storyboard.path.clone())
+                                                    .or_insert_with(|| {
+                                                        load_storyboard_texture(ctx, &storyboard.path)
+                                                    });
+                                                if let Some(texture) = texture {
+                                                    paint_storyboard_frame(
+                                                        ui,
+                                                        thumb_resp.rect,
+                                                        texture,
+                                                        storyboard.frame_count,
+                                                        pointer.x
+                                                    );
+                                                }
+                                            }
+                                        }
+
+                                        if let Some(animation) = &thumbnail_paths.animation {
+                                            if thumb_resp.hovered() {
+                                                if let Some(preview_path) = &animation.preview {
+                                                    let frames = self.cached_animations
+                                                        .entry(preview_path.clone())
+                                                        .or_insert_with(|| {
+                                                            load_animated_preview(ctx, preview_path)
+                                                        });
+                                                    if let Some(frames) = frames {
+                                                        paint_animation_frame(
+                                                            ui,
+                                                            thumb_resp.rect,
+                                                            frames
+                                                        );
+                                                    }
+                                                }
+                                            }
+                                            draw_badge(
+                                                ui,
+                                                thumb_resp.rect,
+                                                "animated",
+                                                egui::Align2::LEFT_TOP
+                                            );
+                                        }
+
+                                        if thumbnail_paths.archive_entry.is_some() {
+                                            draw_badge(
+                                                ui,
+                                                thumb_resp.rect,
+                                                "archive",
+                                                egui::Align2::LEFT_TOP
+                                            );
+                                        }
+
+                                        if let Some((stack, _, collapsed)) = stack_state {
+                                            let count = self.stacks.members[stack].len();
+                                            let badge = match collapsed {
+                                                true => format!("{count}"),
+                                                false => format!("stack of {count}"),
+                                            };
+                                            draw_badge(
+                                                ui,
+                                                thumb_resp.rect,
+                                                &badge,
+                                                egui::Align2::RIGHT_TOP
+                                            );
+                                        }
+
                                         if thumb_resp.clicked() {
+                                            match stack_state {
+                                                // expands the stack instead
+                                                Some((stack, _, true)) => {
+                                                    toggled_stack = Some(stack);
+                                                }
+                                                _ => {
+                                                    if
+                                                        let Some(orig_path_str) =
+                                                            thumbnail_paths.original.to_str()
+                                                    {
+                                                        #[cfg(target_os = "windows")]
+                                                        {
+                                                            Command::new("explorer")
+                                                                .arg("/select,")
+                                                                .arg(orig_path_str)
+                                                                .spawn()
+                                                                .unwrap();
+                                                        }
+                                                    }
+                                                }
+                                            }
+                                        }
+
+                                        thumb_resp.context_menu(|ui| {
+                                            let has_features =
+                                                !thumbnail_paths.feature_vector.is_empty();
                                             if
-                                                let Some(orig_path_str) =
-                                                    thumbnail_paths.original.to_str()
+                                                ui
+                                                    .add_enabled(
+                                                        has_features,
+                                                        egui::Button::new("show similar")
+                                                    )
+                                                    .on_disabled_hover_text(
+                                                        "created without features, clear the cache to recreate it"
+                                                    )
+                                                    .clicked()
                                             {
-                                                #[cfg(target_os = "windows")]
-                                                {
-                                                    Command::new("explorer")
-                                                        .arg("/select,")
-                                                        .arg(orig_path_str)
-                                                        .spawn()
-                                                        .unwrap();
+                                                similar_query = Some(thumbnail_paths.original.clone());
+                                                ui.close_menu();
+                                            }
+
+                                            if let Some((stack, _, collapsed)) = stack_state {
+                                                let label = match collapsed {
+                                                    true => "expand stack",
+                                                    false => "collapse stack",
+                                                };
+                                                if ui.button(label).clicked() {
+                                                    toggled_stack = Some(stack);
+                                                    ui.close_menu();
                                                 }
                                             }
-                                        }
+                                        });
 
                                         if self.show_path_on_hover {
                                             thumb_resp.on_hover_text_at_pointer(
-                                                thumbnail_paths.original
-                                                    .to_str()
-                                                    .unwrap_or("unknown")
+                                                format!(
+                                                    "{}\n{}{}{}{}{}{}",
+                                                    thumbnail_paths.original
+                                                        .to_str()
+                                                        .unwrap_or("unknown"),
+                                                    thumbnail_paths.metadata.summary(),
+                                                    match &thumbnail_paths.animation {
+                                                        Some(animation) =>
+                                                            format!(
+                                                                "\nanimated: {}{} frames, {:.2?}",
+                                                                animation.frame_count,
+                                                                match animation.truncated {
+                                                                    true => "+",
+                                                                    false => "",
+                                                                },
+                                                                animation.duration
+                                                            ),
+                                                        None => String::new(),
+                                                    },
+                                                    match &thumbnail_paths.archive_entry {
+                                                        Some(entry) => format!("\ncover: {entry}"),
+                                                        None => String::new(),
+                                                    },
+                                                    match thumbnail_paths.tone_mapped {
+                                                        true => "\ntone mapped (hdr source)",
+                                                        false => "",
+                                                    },
+                                                    match
+                                                        thumbnail_paths.content_hash
+                                                            .as_ref()
+                                                            .and_then(|content_hash| {
+                                                                self.paths_by_content.get(content_hash)
+                                                            })
+                                                    {
+                                                        Some(paths) if paths.len() > 1 =>
+                                                            format!(
+                                                                "\nidentical copies: {}",
+                                                                paths.len() - 1
+                                                            ),
+                                                        _ => String::new(),
+                                                    },
+                                                    match &thumbnail_paths.quality {
+                                                        Some(quality) => {
+                                                            let problems =
+                                                                self.quality_filter.describe(quality);
+                                                            format!(
+                                                                "\nsharpness: {:.0}{}",
+                                                                quality.sharpness,
+                                                                match problems.is_empty() {
+                                                                    true => String::new(),
+                                                                    false => format!(" ({problems})"),
+                                                                }
+                                                            )
+                                                        }
+                                                        None => String::new(),
+                                                    }
+                                                )
                                             );
                                         }
                                     }
@@ -374,6 +1320,37 @@ impl eframe::App for ThumbnailedApp {
                                         );
                                     }
                                 }
+                            } else {
+                                // not loaded yet, only a few textures are loaded per frame
+                                let placeholder = thumbnail_paths.blurhash
+                                    .as_ref()
+                                    .and_then(|blurhash| {
+                                        self.cached_placeholders
+                                            .entry(blurhash.clone())
+                                            .or_insert_with(|| load_placeholder_texture(ctx, blurhash))
+                                            .clone()
+                                    });
+                                match placeholder {
+                                    Some(texture_handle) => {
+                                        ui.add_sized(
+                                            [max_x as f32, max_y as f32],
+                                            egui::Image::new((
+                                                texture_handle.id(),
+                                                placeholder_size(
+                                                    thumbnail_paths.thumbnail_size,
+                                                    max_x,
+                                                    max_y
+                                                ),
+                                            ))
+                                        );
+                                    }
+                                    None => {
+                                        ui.allocate_exact_size(
+                                            egui::vec2(max_x as f32, max_y as f32),
+                                            egui::Sense::hover()
+                                        );
+                                    }
+                                }
                             }
                         }
                     }
@@ -389,6 +1366,16 @@ impl eframe::App for ThumbnailedApp {
                 ctx.texture_ui(ui);
             });
 
+        if let Some(original) = similar_query {
+            self.find_similar(&original);
+        }
+        if let Some(stack) = toggled_stack {
+            self.stacks.toggle(stack);
+        }
+
+        self.show_duplicate_finder(ctx);
+        self.show_similar_view(ctx);
+
         // LoadDialouge:
         if self.show_load_dialouge {
             egui::Window
@@ -433,6 +1420,73 @@ impl eframe::App for ThumbnailedApp {
                         });
                     });
 
+                    ui.checkbox(
+                        &mut self.load_dialouge_data.apply_orientation,
+                        "apply exif orientation"
+                    ).on_hover_text("rotate / flip thumbnails like other image viewers do");
+
+                    ui.checkbox(
+                        &mut self.load_dialouge_data.convert_to_srgb,
+                        "convert icc profiles to sRGB"
+                    ).on_hover_text("adobe rgb / display p3 photos would look washed out otherwise");
+
+                    ui.checkbox(
+                        &mut self.load_dialouge_data.embed_srgb_tag,
+                        "embed sRGB tag"
+                    ).on_hover_text("marks the thumbnail pngs as sRGB for other programs");
+
+                    ui.horizontal(|ui| {
+                        let scaling = &mut self.load_dialouge_data.scaling;
+                        ui.label("scaling: ");
+                        ui.radio_value(&mut scaling.quality, ScalingQuality::Fast, "fast");
+                        ui.radio_value(
+                            &mut scaling.quality,
+                            ScalingQuality::LinearLight,
+                            "linear light"
+                        ).on_hover_text("keeps fine high-contrast detail from getting darker (slower)");
+                        ui.checkbox(&mut scaling.sharpen, "sharpen").on_hover_text(
+                            "unsharp mask, that gets stronger the more an image is reduced"
+                        );
+                    });
+
+                    ui.collapsing("decoder limits", |ui| {
+                        let limits = &mut self.load_dialouge_data.decode_limits;
+                        optional_limit_ui(ui, "max width (px)", &mut limits.max_width, 16384, 1);
+                        optional_limit_ui(ui, "max height (px)", &mut limits.max_height, 16384, 1);
+                        optional_limit_ui(
+                            ui,
+                            "max allocation (MiB)",
+                            &mut limits.max_alloc,
+                            512,
+                            1024 * 1024
+                        );
+                        optional_limit_ui(
+                            ui,
+                            "max file size (MiB)",
+                            &mut limits.max_file_size,
+                            256,
+                            1024 * 1024
+                        );
+                    }).header_response.on_hover_text(
+                        "files exceeding these limits are skipped for safety"
+                    );
+
+                    ui.checkbox(
+                        &mut self.load_dialouge_data.animated_previews,
+                        "animated previews"
+                    ).on_hover_text("play gifs, apngs and animated webps on hover");
+
+                    ui.horizontal(|ui| {
+                        ui.label("storyboard frames: ");
+                        ui.add(
+                            egui::DragValue
+                                ::new(&mut self.load_dialouge_data.storyboard_frames)
+                                .clamp_range(0..=16)
+                        );
+                    }).response.on_hover_text(
+                        "frames sampled from videos to scrub through on hover (0 = off)"
+                    );
+
                     ui.separator();
 
                     ui.horizontal(|ui| {
@@ -455,20 +1509,36 @@ impl eframe::App for ThumbnailedApp {
                                     thread_count,
                                     max_x: self.load_dialouge_data.max_x,
                                     max_y: self.load_dialouge_data.max_y,
+                                    apply_orientation: self.load_dialouge_data.apply_orientation,
+                                    convert_to_srgb: self.load_dialouge_data.convert_to_srgb,
+                                    embed_srgb_tag: self.load_dialouge_data.embed_srgb_tag,
+                                    scaling: self.load_dialouge_data.scaling,
+                                    decode_limits: self.load_dialouge_data.decode_limits,
+                                    animated_previews: self.load_dialouge_data.animated_previews,
+                                    storyboard_frames: self.load_dialouge_data.storyboard_frames,
                                 });
 
                                 if let Some(spawned_thumbnailer) = &self.thumbnailer {
                                     match
                                         spawned_thumbnailer.send(
-                                            AppToThumbnailer::ThumbnailOrder(LoadData {
-                                                path: PathBuf::from(
-                                                    self.load_dialouge_data.path.clone()
-                                                ),
-                                                target_path: self.thumbnail_path.clone(),
-                                                thread_count,
-                                                max_x: self.load_dialouge_data.max_x,
-                                                max_y: self.load_dialouge_data.max_y,
-                                            })
+                                            AppToThumbnailer::ThumbnailOrder(
+                                                Box::new(LoadData {
+                                                    path: PathBuf::from(
+                                                        self.load_dialouge_data.path.clone()
+                                                    ),
+                                                    target_path: self.thumbnail_path.clone(),
+                                                    thread_count,
+                                                    max_x: self.load_dialouge_data.max_x,
+                                                    max_y: self.load_dialouge_data.max_y,
+                                                    apply_orientation: self.load_dialouge_data.apply_orientation,
+                                                    convert_to_srgb: self.load_dialouge_data.convert_to_srgb,
+                                                    embed_srgb_tag: self.load_dialouge_data.embed_srgb_tag,
+                                                    scaling: self.load_dialouge_data.scaling,
+                                                    decode_limits: self.load_dialouge_data.decode_limits,
+                                                    animated_previews: self.load_dialouge_data.animated_previews,
+                                                    storyboard_frames: self.load_dialouge_data.storyboard_frames,
+                                                })
+                                            )
                                         )
                                     {
                                         Ok(_) => log::debug!("sent thumbnail order to thumbnailer"),
@@ -529,6 +1599,7 @@ impl eframe::App for ThumbnailedApp {
             for (_, texture_handle) in self.cached_thumbnails.iter_mut() {
                 texture_handle.take();
             }
+            self.cached_animations.clear();
 
             if let Some(thumbnailer) = &mut self.thumbnailer {
                 thumbnailer.send(AppToThumbnailer::KillCmd).unwrap();
@@ -539,3 +1610,233 @@ impl eframe::App for ThumbnailedApp {
         ctx.request_repaint();
     }
 }
+
+/// checkbox + drag value for a limit, that can be disabled (None)
+///
+/// the value is shown divided by `unit` (e.g. bytes as MiB)
+fn optional_limit_ui<T>(ui: &mut egui::Ui, label: &str, limit: &mut Option<T>, default: T, unit: T)
+    where T: egui::emath::Numeric + std::ops::Mul<Output = T> + std::ops::Div<Output = T>
+{
+    ui.horizontal(|ui| {
+        let mut enabled = limit.is_some();
+        ui.checkbox(&mut enabled, label);
+
+        match (enabled, limit.as_mut()) {
+            (true, Some(value)) => {
+                let mut shown = *value / unit;
+                if ui.add(egui::DragValue::new(&mut shown).clamp_range(1..=u32::MAX)).changed() {
+                    *value = shown * unit;
+                }
+            }
+            (true, None) => {
+                *limit = Some(default * unit);
+            }
+            (false, _) => {
+                *limit = None;
+            }
+        }
+    });
+}
+
+/// small label in the top left corner of a gallery tile
+fn draw_badge(ui: &egui::Ui, tile_rect: egui::Rect, text: &str, corner: egui::Align2) {
+    let painter = ui.painter();
+    let galley = painter.layout_no_wrap(
+        String::from(text),
+        egui::FontId::proportional(10.0),
+        egui::Color32::WHITE
+    );
+    let badge_rect = corner.align_size_within_rect(galley.size() + egui::vec2(4.0, 4.0), tile_rect);
+
+    painter.rect_filled(badge_rect, 2.0, egui::Color32::from_black_alpha(160));
+    painter.galley(badge_rect.min + egui::vec2(2.0, 2.0), galley, egui::Color32::WHITE);
+}
+
+fn load_animated_preview(
+    ctx: &egui::Context,
+    preview_path: &Path
+) -> Option<Vec<(egui::TextureHandle, Duration)>> {
+    use image::AnimationDecoder;
+
+    let file = match fs::File::open(preview_path) {
+        Ok(file) => file,
+        Err(err) => {
+            log::warn!("failed to open animated preview ({err})");
+            return None;
+        }
+    };
+
+    let frames = image::codecs::gif::GifDecoder
+        ::new(std::io::BufReader::new(file))
+        .and_then(|decoder| decoder.into_frames().collect_frames());
+
+    match frames {
+        Ok(frames) => {
+            let name = preview_path.to_string_lossy();
+            Some(
+                frames
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, frame)| {
+                        let delay = Duration::from(frame.delay());
+                        let buffer = frame.into_buffer();
+                        let texture = ctx.load_texture(
+                            format!("{name}#{i}"),
+                            egui::ColorImage::from_rgba_unmultiplied(
+                                [buffer.width() as usize, buffer.height() as usize],
+                                buffer.as_raw()
+                            ),
+                            Default::default()
+                        );
+                        (texture, delay)
+                    })
+                    .collect()
+            )
+        }
+        Err(err) => {
+            log::warn!("failed to decode animated preview ({err})");
+            None
+        }
+    }
+}
+
+/// paints the current frame (depending on the time) centered over the tile
+fn paint_animation_frame(
+    ui: &egui::Ui,
+    tile_rect: egui::Rect,
+    frames: &[(egui::TextureHandle, Duration)]
+) {
+    let total: Duration = frames
+        .iter()
+        .map(|(_, delay)| *delay)
+        .sum();
+    if total.is_zero() {
+        return;
+    }
+
+    let mut time = Duration::from_secs_f64(ui.input(|i| i.time) % total.as_secs_f64());
+    let texture = frames
+        .iter()
+        .find(|(_, delay)| {
+            if time < *delay {
+                return true;
+            }
+            time -= *delay;
+            false
+        })
+        .or(frames.last())
+        .map(|(texture, _)| texture);
+
+    if let Some(texture) = texture {
+        let frame_rect = egui::Rect::from_center_size(tile_rect.center(), texture.size_vec2());
+        let painter = ui.painter();
+        painter.rect_filled(frame_rect, 0.0, ui.visuals().panel_fill);
+        painter.image(
+            texture.id(),
+            frame_rect,
+            egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)),
+            egui::Color32::WHITE
+        );
+    }
+}
+
+/// blurhashes have no size of their own, so they are decoded small and stretched by the gallery
+fn load_placeholder_texture(ctx: &egui::Context, blurhash: &str) -> Option<egui::TextureHandle> {
+    const SIZE: u32 = 32;
+
+    match blurhash::decode(blurhash, SIZE, SIZE, 1.0) {
+        Ok(pixels) =>
+            Some(
+                ctx.load_texture(
+                    format!("blurhash-{blurhash}"),
+                    egui::ColorImage::from_rgba_unmultiplied(
+                        [SIZE as usize, SIZE as usize],
+                        &pixels
+                    ),
+                    Default::default()
+                )
+            ),
+        Err(err) => {
+            log::warn!("failed to decode blurhash ({err})");
+            None
+        }
+    }
+}
+
+/// size of the thumbnail, that will replace the placeholder (the whole tile, if its size is unknown)
+///
+/// the size of the written thumbnail is used, the source size doesn't know about the orientation
+fn placeholder_size(thumbnail_size: Option<(u32, u32)>, max_x: u32, max_y: u32) -> egui::Vec2 {
+    let (max_x, max_y) = (max_x as f32, max_y as f32);
+    match thumbnail_size {
+        None | Some((0, _)) | Some((_, 0)) => egui::vec2(max_x, max_y),
+        Some((width, height)) => {
+            let (width, height) = (width as f32, height as f32);
+            let scale = (max_x / width).min(max_y / height);
+            egui::vec2(width * scale, height * scale)
+        }
+    }
+}
+
+fn load_storyboard_texture(ctx: &egui::Context, path: &Path) -> Option<egui::TextureHandle> {
+    // storyboards are pngs without ".png" extension
+    let storyboard = image::ImageReader
+        ::open(path)
+        .and_then(|reader| reader.with_guessed_format())
+        .map_err(image::ImageError::from)
+        .and_then(|reader| reader.decode());
+
+    match storyboard {
+        Ok(storyboard) => {
+            let buffer = storyboard.into_rgba8();
+            Some(
+                ctx.load_texture(
+                    path.to_string_lossy(),
+                    egui::ColorImage::from_rgba_unmultiplied(
+                        [buffer.width() as usize, buffer.height() as usize],
+                        buffer.as_raw()
+                    ),
+                    Default::default()
+                )
+            )
+        }
+        Err(err) => {
+            log::warn!("failed to read/decode storyboard ({err})");
+            None
+        }
+    }
+}
+
+/// paints the storyboard frame under the pointer (left edge = first frame) centered over the tile
+fn paint_storyboard_frame(
+    ui: &egui::Ui,
+    tile_rect: egui::Rect,
+    storyboard: &egui::TextureHandle,
+    frame_count: u32,
+    pointer_x: f32
+) {
+    if frame_count == 0 {
+        return;
+    }
+
+    let progress = ((pointer_x - tile_rect.left()) / tile_rect.width()).clamp(0.0, 1.0);
+    let frame = ((progress * (frame_count as f32)) as u32).min(frame_count - 1);
+
+    let frame_size = storyboard.size_vec2() / egui::vec2(frame_count as f32, 1.0);
+    let frame_rect = egui::Rect::from_center_size(tile_rect.center(), frame_size);
+    let uv = egui::Rect::from_min_max(
+        egui::pos2((frame as f32) / (frame_count as f32), 0.0),
+        egui::pos2(((frame + 1) as f32) / (frame_count as f32), 1.0)
+    );
+
+    let painter = ui.painter();
+    painter.rect_filled(frame_rect, 0.0, ui.visuals().panel_fill);
+    painter.image(storyboard.id(), frame_rect, uv, egui::Color32::WHITE);
+
+    // position indicator at the bottom of the tile
+    let indicator = egui::Rect::from_min_size(
+        egui::pos2(tile_rect.left() + tile_rect.width() * progress - 1.0, tile_rect.bottom() - 3.0),
+        egui::vec2(2.0, 3.0)
+    );
+    painter.rect_filled(indicator, 0.0, ui.visuals().selection.bg_fill);
+}
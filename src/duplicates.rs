@@ -0,0 +1,118 @@
+use image::imageops::FilterType;
+
+use crate::*;
+
+/// 64 bit difference hash (dhash) of a thumbnail
+///
+/// the image is reduced to 9 x 8 gray pixels, every bit tells whether a pixel is brighter than
+/// its right neighbour. survives rescaling and recompression, but not crops or rotations
+pub fn dhash(image: &image::RgbaImage) -> u64 {
+    let gray = image::imageops::grayscale(image);
+    let small = image::imageops::resize(&gray, 9, 8, FilterType::Triangle);
+
+    let mut hash = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            let brighter = small.get_pixel(x, y)[0] > small.get_pixel(x + 1, y)[0];
+            hash = (hash << 1) | u64::from(brighter);
+        }
+    }
+
+    hash
+}
+
+/// number of differing bits
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// groups of indices into `hashes`, that are at most `max_distance` bits apart
+///
+/// the grouping is transitive (a chain of similar images forms one group), images without
+/// a near duplicate are left out
+pub fn group_duplicates(hashes: &[Option<u64>], max_distance: u32) -> Vec<Vec<usize>> {
+    // union-find over all pairs, fast enough for a few ten thousand images
+    let mut parents: Vec<usize> = (0..hashes.len()).collect();
+
+    fn root(parents: &mut [usize], mut i: usize) -> usize {
+        while parents[i] != i {
+            parents[i] = parents[parents[i]];
+            i = parents[i];
+        }
+        i
+    }
+
+    for (a, hash_a) in hashes.iter().enumerate() {
+        let hash_a = match hash_a {
+            Some(hash) => *hash,
+            None => {
+                continue;
+            }
+        };
+        for (b, hash_b) in hashes.iter().enumerate().skip(a + 1) {
+            if let Some(hash_b) = hash_b {
+                if hamming_distance(hash_a, *hash_b) <= max_distance {
+                    let (root_a, root_b) = (root(&mut parents, a), root(&mut parents, b));
+                    parents[root_b] = root_a;
+                }
+            }
+        }
+    }
+
+    let mut groups: HBHashMap<usize, Vec<usize>> = HBHashMap::new();
+    for i in 0..hashes.len() {
+        let group_root = root(&mut parents, i);
+        groups.entry(group_root).or_default().push(i);
+    }
+
+    let mut groups: Vec<Vec<usize>> = groups
+        .into_values()
+        .filter(|group| group.len() > 1)
+        .collect();
+    groups.sort_by_key(|group| group[0]);
+
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn groups_at_exactly_the_maximum_distance() {
+        // 0b111 differs from 0 in 3 bits
+        let hashes = [Some(0), Some(0b111)];
+
+        assert_eq!(group_duplicates(&hashes, 3), vec![vec![0, 1]]);
+        assert!(group_duplicates(&hashes, 2).is_empty());
+    }
+
+    #[test]
+    fn chains_similar_hashes_into_one_group() {
+        // 0 and 0b1111 are 4 bits apart, but both are 2 bits away from 0b11
+        let hashes = [Some(0b1111), Some(u64::MAX), Some(0), None, Some(0b11), Some(u64::MAX - 1)];
+
+        assert_eq!(group_duplicates(&hashes, 2), vec![vec![0, 2, 4], vec![1, 5]]);
+    }
+
+    #[test]
+    fn leaves_out_unique_and_unhashed_images() {
+        let hashes = [None, Some(0), None, Some(u64::MAX)];
+
+        assert!(group_duplicates(&hashes, 8).is_empty());
+        assert!(group_duplicates(&[None, None], 64).is_empty());
+        assert!(group_duplicates(&[], 8).is_empty());
+    }
+
+    #[test]
+    fn hash_survives_rescaling() {
+        let gradient = image::RgbaImage::from_fn(256, 192, |x, y| {
+            let value = ((x * 7 + y * 3) % 256) as u8;
+            image::Rgba([value, value / 2, 255 - value, 255])
+        });
+        let smaller = image::imageops::resize(&gradient, 128, 96, FilterType::Triangle);
+
+        assert!(hamming_distance(dhash(&gradient), dhash(&smaller)) <= 4);
+        assert_eq!(hamming_distance(0b1010, 0b0110), 2);
+    }
+}
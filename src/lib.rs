@@ -1,4 +1,11 @@
 mod app;
+mod duplicates;
+mod metadata;
+mod palette;
+mod provider;
+mod quality;
+mod similarity;
+mod stacks;
 mod thumbnailer;
 
 use std::{
@@ -13,10 +20,40 @@ use std::{
 
 use hashbrown::HashMap as HBHashMap;
 pub use app::ThumbnailedApp;
+pub use duplicates::{ dhash, group_duplicates, hamming_distance };
+pub use metadata::{ CaptureDate, GpsCoordinates, ImageMetadata, Timestamp };
+pub use palette::{ color_distance, extract_palette, hsl, PaletteColor, PALETTE_SIZE };
+pub use provider::{
+    has_extension,
+    ArchiveProvider,
+    AudioProvider,
+    FontProvider,
+    ImageProvider,
+    ProvidedThumbnail,
+    ProviderRegistry,
+    RawProvider,
+    SvgProvider,
+    TextProvider,
+    ThumbnailProvider,
+    ThumbnailRequest,
+    VideoError,
+    VideoProvider,
+};
+#[cfg(feature = "heif")]
+pub use provider::HeifProvider;
+pub use quality::{ quality_metrics, QualityMetrics };
+pub use similarity::{ feature_distance, feature_vector, most_similar, FEATURE_LEN };
+pub use stacks::group_stacks;
+pub use thumbnailer::{ compute_blurhash, decode_jpeg_scaled, AnimationWithPreview };
 
 #[derive(Debug)]
 pub enum ThumbnailerToApp {
-    CreatedThumbnail(ThumbnailPaths),
+    CreatedThumbnail(Box<ThumbnailPaths>),
+    /// the file exceeded the `DecodeLimits` of the order and wasn't decoded
+    SkippedForSafety {
+        path: PathBuf,
+        reason: LimitExceeded,
+    },
     Status(ThumbnailerStatus),
 }
 
@@ -24,14 +61,235 @@ unsafe impl Send for ThumbnailerToApp {}
 
 #[derive(Debug)]
 pub enum AppToThumbnailer {
-    ThumbnailOrder(LoadData),
+    /// boxed, so that sending kill commands doesn't move a whole order
+    ThumbnailOrder(Box<LoadData>),
     KillCmd,
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
 pub struct ThumbnailPaths {
     pub thumbnail: PathBuf,
     pub original: PathBuf,
+    /// (width, height) of the written thumbnail, i.e. after orientation was applied
+    #[serde(default)]
+    pub thumbnail_size: Option<(u32, u32)>,
+    #[serde(default)]
+    pub metadata: ImageMetadata,
+    /// only set for animated gifs, apngs and webps
+    #[serde(default)]
+    pub animation: Option<AnimationInfo>,
+    /// only set for videos
+    #[serde(default)]
+    pub storyboard: Option<Storyboard>,
+    /// only set for archives (zip / cbz / epub): the entry used as cover
+    #[serde(default)]
+    pub archive_entry: Option<String>,
+    /// only set for float sources (exr / hdr), whose thumbnail was tone mapped
+    #[serde(default)]
+    pub tone_mapped: bool,
+    /// blurhash of the thumbnail, drawn by the gallery until the texture is loaded
+    #[serde(default)]
+    pub blurhash: Option<String>,
+    /// dhash of the thumbnail, used to find (near) duplicates
+    #[serde(default)]
+    pub perceptual_hash: Option<u64>,
+    /// blake3 hash of the file content (hex), byte-identical files share one thumbnail
+    #[serde(default)]
+    pub content_hash: Option<String>,
+    /// most common colors of the thumbnail (the first one is the dominant color)
+    #[serde(default)]
+    pub palette: Vec<PaletteColor>,
+    /// color histogram + edge descriptors for "similar images" searches
+    #[serde(default)]
+    pub feature_vector: Vec<f32>,
+    /// sharpness / exposure / contrast, for hiding bad frames
+    #[serde(default)]
+    pub quality: Option<QualityMetrics>,
+}
+
+impl ThumbnailPaths {
+    pub fn dominant_color(&self) -> Option<[u8; 3]> {
+        self.palette.first().map(|color| color.rgb)
+    }
+}
+
+/// horizontal strip of evenly spaced (equally sized) video frames
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Storyboard {
+    pub path: PathBuf,
+    pub frame_count: u32,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AnimationInfo {
+    pub frame_count: u32,
+    /// length of one loop
+    pub duration: Duration,
+    /// small looping gif, if animated previews were enabled for the order
+    pub preview: Option<PathBuf>,
+    /// decoding stopped after the maximum number of frames, count and duration are lower bounds
+    #[serde(default)]
+    pub truncated: bool,
+}
+
+/// order in which the gallery shows the thumbnails
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GallerySort {
+    #[default]
+    Path,
+    /// images without a capture date are shown last
+    CaptureDate,
+    /// hue of the dominant color, grayish images are shown last (from dark to light)
+    Hue,
+}
+
+impl GallerySort {
+    pub fn compare(&self, a: &ThumbnailPaths, b: &ThumbnailPaths) -> std::cmp::Ordering {
+        match self {
+            GallerySort::Path => a.original.cmp(&b.original),
+            GallerySort::CaptureDate => {
+                let a_date = (a.metadata.capture_date.is_none(), a.metadata.capture_date);
+                let b_date = (b.metadata.capture_date.is_none(), b.metadata.capture_date);
+                a_date.cmp(&b_date).then_with(|| a.original.cmp(&b.original))
+            }
+            GallerySort::Hue => {
+                // (no color, grayish, hue or lightness)
+                let key = |thumbnail_paths: &ThumbnailPaths| {
+                    match thumbnail_paths.dominant_color().map(hsl) {
+                        Some((hue, saturation, lightness)) =>
+                            match saturation < Self::GRAY_SATURATION {
+                                true => (false, true, lightness),
+                                false => (false, false, hue),
+                            }
+                        None => (true, true, 0.0),
+                    }
+                };
+                let (a_key, b_key) = (key(a), key(b));
+                a_key.0
+                    .cmp(&b_key.0)
+                    .then(a_key.1.cmp(&b_key.1))
+                    .then(a_key.2.total_cmp(&b_key.2))
+                    .then_with(|| a.original.cmp(&b.original))
+            }
+        }
+    }
+
+    /// dominant colors below this saturation don't have a meaningful hue
+    const GRAY_SATURATION: f32 = 0.15;
+}
+
+/// shows only images, that contain a color near `color`
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct ColorFilter {
+    pub enabled: bool,
+    pub color: [u8; 3],
+    /// maximum distance (cie76 delta e) between `color` and a palette color
+    pub tolerance: f32,
+}
+
+impl Default for ColorFilter {
+    fn default() -> Self {
+        Self { enabled: false, color: [200, 40, 40], tolerance: 20.0 }
+    }
+}
+
+impl ColorFilter {
+    /// palette colors covering less of the image are ignored (specks of color don't count)
+    const MIN_SHARE: f32 = 0.05;
+
+    pub fn matches(&self, thumbnail_paths: &ThumbnailPaths) -> bool {
+        !self.enabled ||
+            thumbnail_paths.palette
+                .iter()
+                .any(|palette_color| {
+                    palette_color.share >= Self::MIN_SHARE &&
+                        color_distance(palette_color.rgb, self.color) <= self.tolerance
+                })
+    }
+}
+
+/// which images the gallery shows, based on their quality metrics
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QualityShow {
+    #[default]
+    All,
+    OnlyBlurry,
+    OnlyBadlyExposed,
+    OnlyBlank,
+}
+
+/// hides (or shows only) blurry, badly exposed or blank frames
+///
+/// images without metrics (indexed before they existed) are never hidden, but don't match "only"
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct QualityFilter {
+    pub show: QualityShow,
+    pub hide_blurry: bool,
+    pub hide_badly_exposed: bool,
+    pub hide_blank: bool,
+    /// laplacian variance below which an image is blurry
+    pub blur_threshold: f32,
+}
+
+impl Default for QualityFilter {
+    fn default() -> Self {
+        Self {
+            show: QualityShow::All,
+            hide_blurry: false,
+            hide_badly_exposed: false,
+            hide_blank: false,
+            blur_threshold: 100.0,
+        }
+    }
+}
+
+impl QualityFilter {
+    pub fn is_active(&self) -> bool {
+        self.show != QualityShow::All ||
+            self.hide_blurry ||
+            self.hide_badly_exposed ||
+            self.hide_blank
+    }
+
+    pub fn matches(&self, thumbnail_paths: &ThumbnailPaths) -> bool {
+        let quality = match &thumbnail_paths.quality {
+            Some(quality) => quality,
+            None => {
+                return self.show == QualityShow::All;
+            }
+        };
+
+        let blurry = quality.is_blurry(self.blur_threshold);
+        let badly_exposed = quality.is_badly_exposed();
+        let blank = quality.is_blank();
+
+        let shown = match self.show {
+            QualityShow::All => true,
+            QualityShow::OnlyBlurry => blurry,
+            QualityShow::OnlyBadlyExposed => badly_exposed,
+            QualityShow::OnlyBlank => blank,
+        };
+
+        shown &&
+            !(self.hide_blurry && blurry) &&
+            !(self.hide_badly_exposed && badly_exposed) &&
+            !(self.hide_blank && blank)
+    }
+
+    /// short description of the quality problems ("blurry, underexposed"), empty if there are none
+    pub fn describe(&self, quality: &QualityMetrics) -> String {
+        [
+            (quality.is_blurry(self.blur_threshold), "blurry"),
+            (quality.is_underexposed(), "underexposed"),
+            (quality.is_overexposed(), "overexposed"),
+            (quality.is_blank(), "blank"),
+        ]
+            .iter()
+            .filter(|(applies, _)| *applies)
+            .map(|(_, problem)| *problem)
+            .collect::<Vec<&str>>()
+            .join(", ")
+    }
 }
 
 // preparation for planned ProgressBar
@@ -81,8 +339,163 @@ pub struct LoadData {
     thread_count: NonZeroUsize,
     max_x: u32,
     max_y: u32,
+    /// rotate / flip images according to their exif orientation tag
+    apply_orientation: bool,
+    /// convert images with an embedded icc profile to srgb
+    convert_to_srgb: bool,
+    /// mark the thumbnails as srgb (png `sRGB` chunk)
+    embed_srgb_tag: bool,
+    scaling: ScalingOptions,
+    decode_limits: DecodeLimits,
+    /// additionally create a small looping gif for animated images
+    animated_previews: bool,
+    /// frames of the storyboard strip, that is created for videos (0 = no storyboard)
+    storyboard_frames: u32,
+}
+
+/// how sources are resampled to the thumbnail size
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(default)]
+pub struct ScalingOptions {
+    pub quality: ScalingQuality,
+    /// unsharp mask after resampling, that gets stronger the more the source is reduced
+    pub sharpen: bool,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScalingQuality {
+    /// resamples the srgb values (fine high-contrast detail gets darker)
+    #[default]
+    Fast,
+    /// converts to linear light, resamples and converts back
+    LinearLight,
 }
 
+/// protects the workers against decompression bombs (None = no limit)
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default)]
+pub struct DecodeLimits {
+    pub max_width: Option<u32>,
+    pub max_height: Option<u32>,
+    /// maximum bytes a decoder is allowed to allocate at once
+    pub max_alloc: Option<u64>,
+    pub max_file_size: Option<u64>,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        Self {
+            max_width: Some(16384),
+            max_height: Some(16384),
+            max_alloc: Some(512 * 1024 * 1024),
+            max_file_size: Some(256 * 1024 * 1024),
+        }
+    }
+}
+
+impl DecodeLimits {
+    pub fn no_limits() -> Self {
+        Self { max_width: None, max_height: None, max_alloc: None, max_file_size: None }
+    }
+
+    pub fn to_image_limits(&self) -> image::Limits {
+        let mut limits = image::Limits::no_limits();
+        limits.max_image_width = self.max_width;
+        limits.max_image_height = self.max_height;
+        limits.max_alloc = self.max_alloc;
+        limits
+    }
+
+    pub fn check_file_size(&self, size: u64) -> Result<(), LimitExceeded> {
+        match self.max_file_size {
+            Some(max) if size > max => Err(LimitExceeded::FileSize { size, max }),
+            _ => Ok(()),
+        }
+    }
+
+    /// also estimates the memory needed for the decoded image (as rgba8)
+    pub fn check_dimensions(&self, width: u32, height: u32) -> Result<(), LimitExceeded> {
+        if
+            self.max_width.is_some_and(|max| width > max) ||
+            self.max_height.is_some_and(|max| height > max)
+        {
+            return Err(LimitExceeded::Dimensions {
+                width,
+                height,
+                max_width: self.max_width,
+                max_height: self.max_height,
+            });
+        }
+
+        let required = u64::from(width) * u64::from(height) * 4;
+        match self.max_alloc {
+            Some(max) if required > max => Err(LimitExceeded::Allocation { required, max }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// a file was skipped, because decoding it would have exceeded the `DecodeLimits`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LimitExceeded {
+    FileSize {
+        size: u64,
+        max: u64,
+    },
+    Dimensions {
+        width: u32,
+        height: u32,
+        max_width: Option<u32>,
+        max_height: Option<u32>,
+    },
+    Allocation {
+        required: u64,
+        max: u64,
+    },
+    /// too many elements in a vector document
+    Complexity {
+        /// (at least, parsing stops at the limit)
+        nodes: usize,
+        max_nodes: usize,
+    },
+    /// too deeply nested elements in a vector document
+    Nesting {
+        depth: usize,
+        max_depth: usize,
+    },
+    /// the decoder itself ran into a limit
+    Decoder(String),
+}
+
+impl std::fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LimitExceeded::FileSize { size, max } =>
+                write!(f, "file size of {size} bytes exceeds the limit of {max} bytes"),
+            LimitExceeded::Dimensions { width, height, max_width, max_height } => {
+                let fmt_max = |max: &Option<u32>| {
+                    max.map_or(String::from("any"), |max| max.to_string())
+                };
+                write!(
+                    f,
+                    "dimensions {width}x{height} exceed the limit of {}x{}",
+                    fmt_max(max_width),
+                    fmt_max(max_height)
+                )
+            }
+            LimitExceeded::Allocation { required, max } =>
+                write!(f, "decoding needs ~{required} bytes, but only {max} bytes are allowed"),
+            LimitExceeded::Complexity { nodes, max_nodes } =>
+                write!(f, "document has at least {nodes} nodes, but only {max_nodes} are allowed"),
+            LimitExceeded::Nesting { depth, max_depth } =>
+                write!(f, "document is nested {depth} levels deep, but only {max_depth} are allowed"),
+            LimitExceeded::Decoder(err) => write!(f, "decoder limit exceeded ({err})"),
+        }
+    }
+}
+
+impl Error for LimitExceeded {}
+
 #[derive(serde::Serialize, serde::Deserialize)]
 #[serde(default)]
 pub struct LoadDialougeData {
@@ -90,11 +503,30 @@ pub struct LoadDialougeData {
     thread_count: usize,
     max_x: u32,
     max_y: u32,
+    apply_orientation: bool,
+    convert_to_srgb: bool,
+    embed_srgb_tag: bool,
+    scaling: ScalingOptions,
+    decode_limits: DecodeLimits,
+    animated_previews: bool,
+    storyboard_frames: u32,
 }
 
 impl Default for LoadDialougeData {
     fn default() -> Self {
-        Self { path: String::from(r"C:\"), thread_count: 8, max_x: 128, max_y: 128 }
+        Self {
+            path: String::from(r"C:\"),
+            thread_count: 8,
+            max_x: 128,
+            max_y: 128,
+            apply_orientation: true,
+            convert_to_srgb: true,
+            embed_srgb_tag: false,
+            scaling: ScalingOptions::default(),
+            decode_limits: DecodeLimits::default(),
+            animated_previews: true,
+            storyboard_frames: 6,
+        }
     }
 }
 
@@ -248,7 +680,7 @@ pub trait LoadFromPath {
 
 impl LoadFromPath for image::DynamicImage {
     fn load_from_path<P>(path: P) -> Result<Self, Box<dyn Error>> where P: AsRef<Path> {
-        Ok(image::io::Reader::open(&path)?.decode()?)
+        Ok(image::ImageReader::open(&path)?.decode()?)
     }
 }
 
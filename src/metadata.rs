@@ -0,0 +1,642 @@
+use std::{ fmt::Display, fs, io::BufReader, path::Path, time::Duration };
+
+/// local date and time at which a picture was taken (exif: DateTimeOriginal)
+#[derive(
+    serde::Serialize,
+    serde::Deserialize,
+    Debug,
+    Clone,
+    Copy,
+    Hash,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord
+)]
+pub struct CaptureDate {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    /// offset to utc in minutes (exif: OffsetTimeOriginal), older cameras don't store it
+    #[serde(default)]
+    pub offset_minutes: Option<i16>,
+}
+
+impl Display for CaptureDate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year,
+            self.month,
+            self.day,
+            self.hour,
+            self.minute,
+            self.second
+        )
+    }
+}
+
+impl CaptureDate {
+    /// seconds since 1970-01-01 00:00:00 of the local time (as if it was utc)
+    pub fn local_timestamp(&self) -> i64 {
+        // days from civil (proleptic gregorian calendar)
+        let year = i64::from(self.year) - i64::from(self.month <= 2);
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        // the year starts in march, so the leap day is the last day of the year
+        let month = match self.month > 2 {
+            true => i64::from(self.month) - 3,
+            false => i64::from(self.month) + 9,
+        };
+        let day_of_year = (153 * month + 2) / 5 + i64::from(self.day) - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146_097 + day_of_era - 719_468;
+
+        days * 86_400 +
+            i64::from(self.hour) * 3_600 +
+            i64::from(self.minute) * 60 +
+            i64::from(self.second)
+    }
+
+    /// seconds since 1970-01-01 00:00:00 utc, if the camera stored its utc offset
+    pub fn utc_timestamp(&self) -> Option<i64> {
+        self.offset_minutes.map(|offset| self.local_timestamp() - i64::from(offset) * 60)
+    }
+}
+
+/// point in time of a shot in seconds since 1970
+///
+/// exif dates without utc offset are local time, while file modification times are utc.
+/// the time zone of the local time is unknown, so timestamps of different clocks can be hours
+/// apart and must not be compared
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timestamp {
+    pub seconds: i64,
+    /// local time of the camera instead of utc
+    pub local: bool,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct GpsCoordinates {
+    /// decimal degrees, north is positive
+    pub latitude: f64,
+    /// decimal degrees, east is positive
+    pub longitude: f64,
+}
+
+/// metadata from the exif data and xmp packet of the source (sidecar files aren't read)
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct ImageMetadata {
+    pub capture_date: Option<CaptureDate>,
+    pub camera_model: Option<String>,
+    pub lens: Option<String>,
+    /// in seconds
+    pub exposure_time: Option<f64>,
+    pub f_number: Option<f64>,
+    pub iso: Option<u32>,
+    pub gps: Option<GpsCoordinates>,
+    /// dimensions of the original image in pixels (as stored, without exif orientation)
+    pub width: u32,
+    pub height: u32,
+    pub orientation: Option<u32>,
+    /// length of videos
+    pub duration: Option<Duration>,
+    /// only set for font files
+    pub font_family: Option<String>,
+    pub font_style: Option<String>,
+    /// modification time of the file in seconds since 1970, used if there is no capture date
+    pub modified: Option<u64>,
+}
+
+impl ImageMetadata {
+    /// reads the image dimensions and (if available) the exif data and xmp packet of a file
+    ///
+    /// missing or unreadable metadata is not an error, the fields just stay None
+    pub fn from_path<P>(path: P) -> Self where P: AsRef<Path> {
+        use image::ImageDecoder;
+
+        let decoder = image::ImageReader
+            ::open(&path)
+            .ok()
+            .and_then(|reader| reader.with_guessed_format().ok())
+            .and_then(|reader| reader.into_decoder().ok());
+        let (dimensions, xmp) = match decoder {
+            Some(mut decoder) => (decoder.dimensions(), decoder.xmp_metadata().ok().flatten()),
+            None => ((0, 0), None),
+        };
+
+        // the exif data of raws is found by the container parser only
+        let exif = read_exif(&path);
+
+        let mut metadata = Self::from_exif(exif.as_ref(), xmp.as_deref());
+        (metadata.width, metadata.height) = dimensions;
+        metadata
+    }
+
+    /// like `from_exif`, but for raw exif data (starting at the tiff header), as decoders return it
+    pub(crate) fn from_raw(exif: Option<&[u8]>, xmp: Option<&[u8]>) -> Self {
+        // some webp and heif writers keep the "Exif\0\0" prefix of the jpeg segment
+        let exif = exif.map(|exif| exif.strip_prefix(b"Exif\0\0").unwrap_or(exif));
+        let exif = match exif.map(|exif| exif::Reader::new().read_raw(exif.to_vec())) {
+            Some(Ok(exif)) => Some(exif),
+            Some(Err(err)) => {
+                log::trace!("invalid exif data ({err})");
+                None
+            }
+            None => None,
+        };
+
+        Self::from_exif(exif.as_ref(), xmp)
+    }
+
+    /// like `from_path`, but for exif data and an xmp packet, that were read from somewhere else
+    /// (e.g. by a decoder or from raw containers)
+    ///
+    /// the dimensions stay 0
+    pub(crate) fn from_exif(exif: Option<&exif::Exif>, xmp: Option<&[u8]>) -> Self {
+        let mut metadata = Self::default();
+        if let Some(exif) = exif {
+            metadata.read_exif_fields(exif);
+        }
+        if let Some(xmp) = xmp {
+            metadata.read_xmp_fields(xmp);
+        }
+        metadata
+    }
+
+    fn read_exif_fields(&mut self, exif: &exif::Exif) {
+        let offset = ascii_field(exif, exif::Tag::OffsetTimeOriginal).or_else(|| {
+            ascii_field(exif, exif::Tag::OffsetTime)
+        });
+        self.capture_date = ascii_field(exif, exif::Tag::DateTimeOriginal)
+            .or_else(|| ascii_field(exif, exif::Tag::DateTime))
+            .and_then(|value| exif::DateTime::from_ascii(value.as_bytes()).ok())
+            .map(|mut date_time| {
+                if let Some(offset) = &offset {
+                    if date_time.parse_offset(offset.as_bytes()).is_err() {
+                        log::trace!("invalid exif utc offset ({offset})");
+                    }
+                }
+                CaptureDate {
+                    year: date_time.year,
+                    month: date_time.month,
+                    day: date_time.day,
+                    hour: date_time.hour,
+                    minute: date_time.minute,
+                    second: date_time.second,
+                    offset_minutes: date_time.offset,
+                }
+            });
+
+        self.camera_model = camera_model(
+            ascii_field(exif, exif::Tag::Make),
+            ascii_field(exif, exif::Tag::Model)
+        );
+
+        self.lens = ascii_field(exif, exif::Tag::LensModel);
+        self.exposure_time = rational_field(exif, exif::Tag::ExposureTime, 0);
+        self.f_number = rational_field(exif, exif::Tag::FNumber, 0);
+        self.iso = exif
+            .get_field(exif::Tag::PhotographicSensitivity, exif::In::PRIMARY)
+            .and_then(|field| field.value.get_uint(0));
+        self.gps = gps_coordinates(exif);
+        self.orientation = exif
+            .get_field(exif::Tag::Orientation, exif::In::PRIMARY)
+            .and_then(|field| field.value.get_uint(0));
+    }
+
+    /// fills the fields, that the exif data didn't set, from an xmp packet
+    fn read_xmp_fields(&mut self, xmp: &[u8]) {
+        // packets are padded with whitespace (and sometimes zeros) to be edited in place
+        let text = match std::str::from_utf8(xmp) {
+            Ok(text) =>
+                text.trim_matches(|c: char| c.is_whitespace() || c == '\0' || c == '\u{feff}'),
+            Err(err) => {
+                log::trace!("xmp packet isn't utf-8 ({err})");
+                return;
+            }
+        };
+        let document = match roxmltree::Document::parse(text) {
+            Ok(document) => document,
+            Err(err) => {
+                log::trace!("invalid xmp packet ({err})");
+                return;
+            }
+        };
+        let property = |namespace: &str, name: &str| xmp_property(&document, namespace, name);
+
+        if self.capture_date.is_none() {
+            self.capture_date = property(XMP_EXIF, "DateTimeOriginal")
+                .or_else(|| property(XMP_PHOTOSHOP, "DateCreated"))
+                .or_else(|| property(XMP_BASIC, "CreateDate"))
+                .and_then(|value| parse_xmp_date(&value));
+        }
+        if self.camera_model.is_none() {
+            self.camera_model = camera_model(
+                property(XMP_TIFF, "Make"),
+                property(XMP_TIFF, "Model")
+            );
+        }
+        if self.lens.is_none() {
+            self.lens = property(XMP_EXIF_EX, "LensModel").or_else(|| property(XMP_AUX, "Lens"));
+        }
+        if self.exposure_time.is_none() {
+            self.exposure_time = property(XMP_EXIF, "ExposureTime").and_then(|value| {
+                parse_xmp_rational(&value)
+            });
+        }
+        if self.f_number.is_none() {
+            self.f_number = property(XMP_EXIF, "FNumber").and_then(|value| {
+                parse_xmp_rational(&value)
+            });
+        }
+        if self.iso.is_none() {
+            self.iso = property(XMP_EXIF_EX, "PhotographicSensitivity")
+                .or_else(|| property(XMP_EXIF, "ISOSpeedRatings"))
+                .and_then(|value| value.parse().ok());
+        }
+        if self.gps.is_none() {
+            let degrees = |name: &str| {
+                property(XMP_EXIF, name).and_then(|value| parse_xmp_gps(&value))
+            };
+            self.gps = degrees("GPSLatitude").zip(degrees("GPSLongitude")).map(
+                |(latitude, longitude)| GpsCoordinates { latitude, longitude }
+            );
+        }
+    }
+
+    /// capture date, or the modification time of the file
+    pub fn timestamp(&self) -> Option<Timestamp> {
+        match &self.capture_date {
+            Some(capture_date) =>
+                match capture_date.utc_timestamp() {
+                    Some(seconds) => Some(Timestamp { seconds, local: false }),
+                    None =>
+                        Some(Timestamp { seconds: capture_date.local_timestamp(), local: true }),
+                }
+            None =>
+                self.modified
+                    .and_then(|modified| i64::try_from(modified).ok())
+                    .map(|seconds| Timestamp { seconds, local: false }),
+        }
+    }
+
+    /// short multi-line description for tooltips / info panels
+    pub fn summary(&self) -> String {
+        let mut lines = Vec::new();
+
+        // unknown for fonts, text files, ...
+        if self.width != 0 && self.height != 0 {
+            lines.push(format!("{} x {} px", self.width, self.height));
+        }
+
+        if let Some(duration) = &self.duration {
+            lines.push(format!("duration: {duration:.1?}"));
+        }
+
+        match (&self.font_family, &self.font_style) {
+            (Some(family), Some(style)) => lines.push(format!("font: {family} {style}")),
+            (Some(family), None) => lines.push(format!("font: {family}")),
+            _ => (),
+        }
+
+        if let Some(capture_date) = &self.capture_date {
+            lines.push(format!("captured: {capture_date}"));
+        }
+        if let Some(camera_model) = &self.camera_model {
+            lines.push(format!("camera: {camera_model}"));
+        }
+        if let Some(lens) = &self.lens {
+            lines.push(format!("lens: {lens}"));
+        }
+
+        let mut exposure = Vec::<String>::new();
+        if let Some(exposure_time) = self.exposure_time {
+            if exposure_time > 0.0 && exposure_time < 1.0 {
+                exposure.push(format!("1/{:.0} s", 1.0 / exposure_time));
+            } else {
+                exposure.push(format!("{exposure_time} s"));
+            }
+        }
+        if let Some(f_number) = self.f_number {
+            exposure.push(format!("f/{f_number:.1}"));
+        }
+        if let Some(iso) = self.iso {
+            exposure.push(format!("ISO {iso}"));
+        }
+        if !exposure.is_empty() {
+            lines.push(exposure.join(", "));
+        }
+
+        if let Some(gps) = &self.gps {
+            lines.push(format!("gps: {:.5}, {:.5}", gps.latitude, gps.longitude));
+        }
+
+        lines.join("\n")
+    }
+}
+
+/// reads the exif data from jpeg, tiff, heif, png or webp files
+pub fn read_exif<P>(path: P) -> Option<exif::Exif> where P: AsRef<Path> {
+    let file = fs::File::open(path).ok()?;
+    exif::Reader::new().read_from_container(&mut BufReader::new(file)).ok()
+}
+
+/// make and model of the camera
+fn camera_model(make: Option<String>, model: Option<String>) -> Option<String> {
+    match (make, model) {
+        // most vendors already repeat the make in the model ("Canon EOS R6")
+        (Some(make), Some(model)) if !model.starts_with(&make) => Some(format!("{make} {model}")),
+        (_, Some(model)) => Some(model),
+        (make, None) => make,
+    }
+}
+
+fn ascii_field(exif: &exif::Exif, tag: exif::Tag) -> Option<String> {
+    let field = exif.get_field(tag, exif::In::PRIMARY)?;
+    match &field.value {
+        exif::Value::Ascii(values) => {
+            let value = String::from_utf8_lossy(values.first()?);
+            let value = value.trim_matches(|c: char| c.is_whitespace() || c == '\0');
+            if value.is_empty() {
+                None
+            } else {
+                Some(String::from(value))
+            }
+        }
+        _ => None,
+    }
+}
+
+fn rational_field(exif: &exif::Exif, tag: exif::Tag, index: usize) -> Option<f64> {
+    let field = exif.get_field(tag, exif::In::PRIMARY)?;
+    match &field.value {
+        exif::Value::Rational(values) => {
+            let value = values.get(index)?;
+            if value.denom == 0 {
+                None
+            } else {
+                Some(value.to_f64())
+            }
+        }
+        _ => None,
+    }
+}
+
+/// converts degrees, minutes and seconds (+ N/S/E/W reference) to decimal degrees
+fn gps_degrees(exif: &exif::Exif, tag: exif::Tag, ref_tag: exif::Tag) -> Option<f64> {
+    let degrees = rational_field(exif, tag, 0)?;
+    let minutes = rational_field(exif, tag, 1).unwrap_or(0.0);
+    let seconds = rational_field(exif, tag, 2).unwrap_or(0.0);
+    let decimal = degrees + minutes / 60.0 + seconds / 3600.0;
+
+    match ascii_field(exif, ref_tag).as_deref() {
+        Some("S") | Some("W") => Some(-decimal),
+        _ => Some(decimal),
+    }
+}
+
+fn gps_coordinates(exif: &exif::Exif) -> Option<GpsCoordinates> {
+    Some(GpsCoordinates {
+        latitude: gps_degrees(exif, exif::Tag::GPSLatitude, exif::Tag::GPSLatitudeRef)?,
+        longitude: gps_degrees(exif, exif::Tag::GPSLongitude, exif::Tag::GPSLongitudeRef)?,
+    })
+}
+
+/// namespaces of the read xmp properties
+const XMP_EXIF: &str = "http://ns.adobe.com/exif/1.0/";
+const XMP_EXIF_EX: &str = "http://cipa.jp/exif/1.0/";
+const XMP_AUX: &str = "http://ns.adobe.com/exif/1.0/aux/";
+const XMP_TIFF: &str = "http://ns.adobe.com/tiff/1.0/";
+const XMP_BASIC: &str = "http://ns.adobe.com/xap/1.0/";
+const XMP_PHOTOSHOP: &str = "http://ns.adobe.com/photoshop/1.0/";
+
+/// value of an xmp property, written as attribute of a description or as element
+/// (for arrays the first item)
+fn xmp_property(document: &roxmltree::Document, namespace: &str, name: &str) -> Option<String> {
+    document
+        .descendants()
+        .filter(|node| node.is_element())
+        .find_map(|node| {
+            if let Some(value) = node.attribute((namespace, name)) {
+                return Some(value);
+            }
+            match node.tag_name().namespace() == Some(namespace) && node.tag_name().name() == name {
+                true =>
+                    node
+                        .descendants()
+                        .filter(|node| node.is_text())
+                        .filter_map(|node| node.text())
+                        .find(|text| !text.trim().is_empty()),
+                false => None,
+            }
+        })
+        .map(|value| String::from(value.trim()))
+}
+
+/// iso 8601 dates ("2023-06-01T14:30:05.25+02:00"), the time and utc offset are optional
+fn parse_xmp_date(value: &str) -> Option<CaptureDate> {
+    let (date, time) = value.split_once('T').unwrap_or((value, ""));
+
+    let mut date = date.split('-');
+    let year = date.next()?.parse().ok()?;
+    let month = date.next().map_or(Some(1), |month| month.parse().ok())?;
+    let day = date.next().map_or(Some(1), |day| day.parse().ok())?;
+
+    let (time, offset_minutes) = match time.find(['Z', '+', '-']) {
+        Some(i) => (&time[..i], Some(parse_utc_offset(&time[i..])?)),
+        None => (time, None),
+    };
+    let mut time = time.split(':').filter(|part| !part.is_empty());
+    let hour = time.next().map_or(Some(0), |hour| hour.parse().ok())?;
+    let minute = time.next().map_or(Some(0), |minute| minute.parse().ok())?;
+    // fractions of seconds are dropped
+    let second = time
+        .next()
+        .map_or(Some(0), |second| second.split('.').next().and_then(|second| second.parse().ok()))?;
+
+    let is_valid =
+        (1..=12).contains(&month) &&
+        (1..=31).contains(&day) &&
+        hour < 24 &&
+        minute < 60 &&
+        second < 61;
+
+    match is_valid {
+        true => Some(CaptureDate { year, month, day, hour, minute, second, offset_minutes }),
+        false => None,
+    }
+}
+
+/// "Z", "+02:00" or "-0530" in minutes
+fn parse_utc_offset(offset: &str) -> Option<i16> {
+    let (sign, digits) = match offset.split_at(1) {
+        ("Z", "") => {
+            return Some(0);
+        }
+        ("+", digits) => (1, digits.replace(':', "")),
+        ("-", digits) => (-1, digits.replace(':', "")),
+        _ => {
+            return None;
+        }
+    };
+    let hours: i16 = digits.get(..2)?.parse().ok()?;
+    let minutes: i16 = match digits.get(2..) {
+        Some("") | None => 0,
+        Some(minutes) => minutes.parse().ok()?,
+    };
+
+    match hours <= 14 && minutes < 60 {
+        true => Some(sign * (hours * 60 + minutes)),
+        false => None,
+    }
+}
+
+/// "1/250" or "2.8"
+fn parse_xmp_rational(value: &str) -> Option<f64> {
+    let value = match value.split_once('/') {
+        Some((numerator, denominator)) => {
+            let denominator: f64 = denominator.parse().ok()?;
+            if denominator == 0.0 {
+                return None;
+            }
+            numerator.parse::<f64>().ok()? / denominator
+        }
+        None => value.parse().ok()?,
+    };
+
+    match value.is_finite() {
+        true => Some(value),
+        false => None,
+    }
+}
+
+/// "48,51.30N" or "2,17,40.5E" (degrees, minutes, optional seconds and direction) to decimal
+/// degrees, north and east are positive
+fn parse_xmp_gps(value: &str) -> Option<f64> {
+    let direction = value.chars().last()?;
+    let mut parts = value[..value.len() - direction.len_utf8()].split(',');
+
+    let degrees: f64 = parts.next()?.trim().parse().ok()?;
+    let minutes: f64 = parts.next().map_or(Some(0.0), |minutes| minutes.trim().parse().ok())?;
+    let seconds: f64 = parts.next().map_or(Some(0.0), |seconds| seconds.trim().parse().ok())?;
+    let decimal = degrees + minutes / 60.0 + seconds / 3600.0;
+
+    match direction.to_ascii_uppercase() {
+        'N' | 'E' => Some(decimal),
+        'S' | 'W' => Some(-decimal),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const XMP: &str = r#"<?xpacket begin="" id="W5M0MpCehiHzreSzNTczkc9d"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/">
+  <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+    <rdf:Description rdf:about=""
+        xmlns:exif="http://ns.adobe.com/exif/1.0/"
+        xmlns:exifEX="http://cipa.jp/exif/1.0/"
+        xmlns:tiff="http://ns.adobe.com/tiff/1.0/"
+        exif:DateTimeOriginal="2023-06-01T14:30:05.25+02:00"
+        exif:ExposureTime="1/250"
+        exif:FNumber="28/10"
+        exif:GPSLatitude="48,51.3N"
+        exif:GPSLongitude="2,17,40.5W"
+        tiff:Make="Canon">
+      <tiff:Model>Canon EOS R6</tiff:Model>
+      <exifEX:LensModel>RF24-105mm F4 L IS USM</exifEX:LensModel>
+      <exif:ISOSpeedRatings>
+        <rdf:Seq><rdf:li>400</rdf:li></rdf:Seq>
+      </exif:ISOSpeedRatings>
+    </rdf:Description>
+  </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end="w"?>"#;
+
+    #[test]
+    fn reads_xmp_attributes_and_elements() {
+        let mut padded = String::from(XMP).into_bytes();
+        padded.extend_from_slice(&[b' '; 64]);
+        padded.push(0);
+        let metadata = ImageMetadata::from_raw(None, Some(&padded));
+
+        assert_eq!(metadata.capture_date, Some(CaptureDate {
+            year: 2023,
+            month: 6,
+            day: 1,
+            hour: 14,
+            minute: 30,
+            second: 5,
+            offset_minutes: Some(120),
+        }));
+        assert_eq!(metadata.camera_model.as_deref(), Some("Canon EOS R6"));
+        assert_eq!(metadata.lens.as_deref(), Some("RF24-105mm F4 L IS USM"));
+        assert_eq!(metadata.exposure_time, Some(1.0 / 250.0));
+        assert_eq!(metadata.f_number, Some(2.8));
+        assert_eq!(metadata.iso, Some(400));
+
+        let gps = metadata.gps.unwrap();
+        assert!((gps.latitude - 48.855).abs() < 1e-9);
+        assert!((gps.longitude + (2.0 + 17.0 / 60.0 + 40.5 / 3600.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn prefers_exif_over_xmp() {
+        let mut metadata = ImageMetadata {
+            camera_model: Some(String::from("NIKON D750")),
+            iso: Some(100),
+            ..Default::default()
+        };
+        metadata.read_xmp_fields(XMP.as_bytes());
+
+        assert_eq!(metadata.camera_model.as_deref(), Some("NIKON D750"));
+        assert_eq!(metadata.iso, Some(100));
+        // only missing fields are taken from the packet
+        assert_eq!(metadata.f_number, Some(2.8));
+    }
+
+    #[test]
+    fn ignores_broken_xmp() {
+        for xmp in [&b"<x:xmpmeta"[..], b"\xff\xfe", b""] {
+            assert_eq!(ImageMetadata::from_raw(None, Some(xmp)), ImageMetadata::default());
+        }
+        assert_eq!(ImageMetadata::from_raw(Some(b"Exif\0\0II*\0"), None), ImageMetadata::default());
+    }
+
+    #[test]
+    fn parses_xmp_dates() {
+        let date = |value: &str| {
+            parse_xmp_date(value).map(|date| {
+                let CaptureDate { year, month, day, hour, minute, second, offset_minutes } = date;
+                (year, month, day, hour, minute, second, offset_minutes)
+            })
+        };
+
+        assert_eq!(date("2019-08-23T14:30:05Z"), Some((2019, 8, 23, 14, 30, 5, Some(0))));
+        assert_eq!(date("2019-08-23T14:30-05:30"), Some((2019, 8, 23, 14, 30, 0, Some(-330))));
+        assert_eq!(date("2019-08-23T14:30:05"), Some((2019, 8, 23, 14, 30, 5, None)));
+        assert_eq!(date("2019-08"), Some((2019, 8, 1, 0, 0, 0, None)));
+        assert_eq!(date("2019-13-01"), None);
+        assert_eq!(date("2019-08-23T25:00"), None);
+        assert_eq!(date("2019-08-23T14:30+0x:00"), None);
+        assert_eq!(date("yesterday"), None);
+    }
+
+    #[test]
+    fn parses_xmp_numbers() {
+        assert_eq!(parse_xmp_rational("1/0"), None);
+        assert_eq!(parse_xmp_rational("inf"), None);
+        assert_eq!(parse_xmp_rational("4"), Some(4.0));
+        assert_eq!(parse_xmp_gps("10,30S"), Some(-10.5));
+        assert_eq!(parse_xmp_gps("10,30"), None);
+        assert_eq!(parse_xmp_gps(""), None);
+    }
+}
@@ -0,0 +1,259 @@
+/// one color of an image palette
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct PaletteColor {
+    pub rgb: [u8; 3],
+    /// fraction of the (opaque) pixels, that are closest to this color
+    pub share: f32,
+}
+
+/// number of colors extracted per thumbnail
+pub const PALETTE_SIZE: usize = 5;
+
+const KMEANS_ITERATIONS: usize = 8;
+
+/// the most common colors of the image, ordered by their share (the first one is the dominant color)
+///
+/// median cut finds the starting colors, k-means then moves them to the actual clusters,
+/// so that the shares are meaningful (median cut alone splits the pixels evenly)
+pub fn extract_palette(image: &image::RgbaImage, max_colors: usize) -> Vec<PaletteColor> {
+    // (mostly) transparent pixels don't count
+    let pixels: Vec<[u8; 3]> = image
+        .pixels()
+        .filter(|pixel| pixel[3] >= 128)
+        .map(|pixel| [pixel[0], pixel[1], pixel[2]])
+        .collect();
+    if pixels.is_empty() || max_colors == 0 {
+        return Vec::new();
+    }
+
+    let mut centroids: Vec<[f32; 3]> = median_cut(pixels.clone(), max_colors)
+        .iter()
+        .map(|colors| average(colors))
+        .collect();
+
+    let mut counts = vec![0; centroids.len()];
+    for _ in 0..KMEANS_ITERATIONS {
+        let mut sums = vec![[0.0f32; 3]; centroids.len()];
+        counts.iter_mut().for_each(|count| {
+            *count = 0;
+        });
+
+        for pixel in &pixels {
+            let nearest = nearest_centroid(&centroids, pixel);
+            for (sum, value) in sums[nearest].iter_mut().zip(pixel) {
+                *sum += f32::from(*value);
+            }
+            counts[nearest] += 1;
+        }
+
+        for ((centroid, sum), count) in centroids.iter_mut().zip(&sums).zip(&counts) {
+            if *count > 0 {
+                *centroid = sum.map(|sum| sum / (*count as f32));
+            }
+        }
+    }
+
+    let mut palette: Vec<PaletteColor> = centroids
+        .iter()
+        .zip(&counts)
+        .filter(|(_, count)| **count > 0)
+        .map(|(centroid, count)| PaletteColor {
+            rgb: centroid.map(|value| value.round().clamp(0.0, 255.0) as u8),
+            share: (*count as f32) / (pixels.len() as f32),
+        })
+        .collect();
+    palette.sort_by(|a, b| b.share.total_cmp(&a.share));
+
+    palette
+}
+
+/// splits the pixels into at most `max_boxes` boxes, always at the median of the widest channel
+fn median_cut(pixels: Vec<[u8; 3]>, max_boxes: usize) -> Vec<Vec<[u8; 3]>> {
+    let mut boxes = vec![pixels];
+
+    while boxes.len() < max_boxes {
+        // (box index, channel, range of the channel)
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, colors)| colors.len() > 1)
+            .flat_map(|(i, colors)| {
+                (0..3).map(move |channel| {
+                    let min = colors.iter().map(|color| color[channel]).min().unwrap_or(0);
+                    let max = colors.iter().map(|color| color[channel]).max().unwrap_or(0);
+                    (i, channel, max - min)
+                })
+            })
+            .max_by_key(|(_, _, range)| *range);
+
+        match widest {
+            Some((i, channel, range)) if range > 0 => {
+                let mut colors = boxes.swap_remove(i);
+                colors.sort_unstable_by_key(|color| color[channel]);
+                let upper = colors.split_off(colors.len() / 2);
+                boxes.push(colors);
+                boxes.push(upper);
+            }
+            // every box has a single color
+            _ => {
+                break;
+            }
+        }
+    }
+
+    boxes
+}
+
+fn average(colors: &[[u8; 3]]) -> [f32; 3] {
+    let mut sum = [0.0f32; 3];
+    for color in colors {
+        for (sum, value) in sum.iter_mut().zip(color) {
+            *sum += f32::from(*value);
+        }
+    }
+    sum.map(|sum| sum / (colors.len().max(1) as f32))
+}
+
+fn nearest_centroid(centroids: &[[f32; 3]], pixel: &[u8; 3]) -> usize {
+    let distance = |centroid: &[f32; 3]| -> f32 {
+        centroid
+            .iter()
+            .zip(pixel)
+            .map(|(centroid, value)| (centroid - f32::from(*value)).powi(2))
+            .sum()
+    };
+
+    centroids
+        .iter()
+        .enumerate()
+        .min_by(|(_, a), (_, b)| distance(a).total_cmp(&distance(b)))
+        .map(|(i, _)| i)
+        .unwrap_or_default()
+}
+
+/// (hue in degrees, saturation, lightness), saturation and lightness in 0.0..=1.0
+pub fn hsl(rgb: [u8; 3]) -> (f32, f32, f32) {
+    let [r, g, b] = rgb.map(|value| f32::from(value) / 255.0);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let lightness = (max + min) / 2.0;
+
+    let delta = max - min;
+    if delta == 0.0 {
+        return (0.0, 0.0, lightness);
+    }
+
+    let saturation = delta / (1.0 - (2.0 * lightness - 1.0).abs());
+    let hue = match max {
+        max if max == r => 60.0 * (((g - b) / delta).rem_euclid(6.0)),
+        max if max == g => 60.0 * ((b - r) / delta + 2.0),
+        _ => 60.0 * ((r - g) / delta + 4.0),
+    };
+
+    (hue, saturation.clamp(0.0, 1.0), lightness)
+}
+
+/// perceptual distance of two srgb colors (cie76 delta e, ~2.3 is barely noticeable)
+pub fn color_distance(a: [u8; 3], b: [u8; 3]) -> f32 {
+    let (a, b) = (to_lab(a), to_lab(b));
+    a.iter()
+        .zip(&b)
+        .map(|(a, b)| (a - b).powi(2))
+        .sum::<f32>()
+        .sqrt()
+}
+
+/// srgb to cie l*a*b* (d65 white point)
+fn to_lab(rgb: [u8; 3]) -> [f32; 3] {
+    let [r, g, b] = rgb.map(|value| {
+        let value = f32::from(value) / 255.0;
+        match value <= 0.04045 {
+            true => value / 12.92,
+            false => ((value + 0.055) / 1.055).powf(2.4),
+        }
+    });
+
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+
+    let f = |t: f32| {
+        match t > 0.008856 {
+            true => t.cbrt(),
+            false => 7.787 * t + 16.0 / 116.0,
+        }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: [u8; 3] = [200, 30, 30];
+    const GREEN: [u8; 3] = [30, 180, 60];
+    const BLUE: [u8; 3] = [20, 40, 210];
+
+    /// the first `counts[i]` pixels have `colors[i]`, the rest is transparent
+    fn image_of(colors: &[([u8; 3], u32)]) -> image::RgbaImage {
+        let mut pixels = colors
+            .iter()
+            .flat_map(|([r, g, b], count)| (0..*count).map(move |_| image::Rgba([*r, *g, *b, 255])));
+        image::RgbaImage::from_fn(10, 10, |_, _| {
+            pixels.next().unwrap_or(image::Rgba([255, 255, 255, 0]))
+        })
+    }
+
+    #[test]
+    fn median_cut_splits_at_the_widest_channel() {
+        let pixels = vec![RED, RED, BLUE, BLUE, BLUE, GREEN];
+
+        let mut boxes = median_cut(pixels.clone(), 3);
+        boxes.iter_mut().for_each(|colors| colors.sort_unstable());
+        boxes.sort();
+        // blue first (widest together with red, the later channel wins), then red
+        assert_eq!(boxes, vec![vec![BLUE; 3], vec![GREEN], vec![RED, RED]]);
+
+        // boxes with a single color aren't split any further
+        assert_eq!(median_cut(vec![RED; 4], 3), vec![vec![RED; 4]]);
+        assert_eq!(median_cut(pixels, 1).len(), 1);
+    }
+
+    #[test]
+    fn kmeans_finds_the_colors_and_their_shares() {
+        let palette = extract_palette(&image_of(&[(RED, 50), (GREEN, 30), (BLUE, 20)]), 3);
+
+        // the shares are the actual sizes of the clusters
+        assert_eq!(
+            palette,
+            vec![
+                PaletteColor { rgb: RED, share: 0.5 },
+                PaletteColor { rgb: GREEN, share: 0.3 },
+                PaletteColor { rgb: BLUE, share: 0.2 }
+            ]
+        );
+    }
+
+    #[test]
+    fn ignores_transparent_pixels_and_unused_colors() {
+        let palette = extract_palette(&image_of(&[(GREEN, 10)]), PALETTE_SIZE);
+
+        assert_eq!(palette, vec![PaletteColor { rgb: GREEN, share: 1.0 }]);
+        assert!(extract_palette(&image_of(&[]), PALETTE_SIZE).is_empty());
+        assert!(extract_palette(&image_of(&[(GREEN, 10)]), 0).is_empty());
+    }
+
+    #[test]
+    fn converts_to_hsl_and_lab() {
+        assert_eq!(hsl([255, 0, 0]), (0.0, 1.0, 0.5));
+        assert_eq!(hsl([0, 0, 255]), (240.0, 1.0, 0.5));
+        assert_eq!(hsl([128, 128, 128]).1, 0.0);
+
+        assert_eq!(color_distance(RED, RED), 0.0);
+        // black to white is 100 l*
+        assert!((color_distance([0, 0, 0], [255, 255, 255]) - 100.0).abs() < 0.5);
+        assert!(color_distance(RED, [201, 30, 30]) < 2.3);
+    }
+}
@@ -0,0 +1,220 @@
+mod archive;
+mod audio;
+mod bmff;
+mod font;
+#[cfg(feature = "heif")]
+mod heif;
+mod images;
+mod raw;
+mod svg;
+mod text;
+mod video;
+
+use std::{ fs, io::Read, path::Path };
+
+use crate::*;
+use crate::thumbnailer::AnimationWithPreview;
+
+pub use archive::ArchiveProvider;
+pub use audio::AudioProvider;
+pub use font::FontProvider;
+#[cfg(feature = "heif")]
+pub use heif::HeifProvider;
+pub use images::ImageProvider;
+pub use raw::RawProvider;
+pub use svg::SvgProvider;
+pub use text::TextProvider;
+pub use video::{ VideoError, VideoProvider };
+
+/// how many bytes of every file are read for `ThumbnailProvider::can_handle`
+const HEADER_LEN: usize = 512;
+
+/// per-order settings, that every provider gets together with the source file
+#[derive(Debug, Clone, Copy)]
+pub struct ThumbnailRequest {
+    pub max_x: u32,
+    pub max_y: u32,
+    pub apply_orientation: bool,
+    pub convert_to_srgb: bool,
+    pub embed_srgb_tag: bool,
+    pub scaling: ScalingOptions,
+    pub decode_limits: DecodeLimits,
+    pub animated_previews: bool,
+    pub storyboard_frames: u32,
+}
+
+impl From<&LoadData> for ThumbnailRequest {
+    fn from(order: &LoadData) -> Self {
+        Self {
+            max_x: order.max_x,
+            max_y: order.max_y,
+            apply_orientation: order.apply_orientation,
+            convert_to_srgb: order.convert_to_srgb,
+            embed_srgb_tag: order.embed_srgb_tag,
+            scaling: order.scaling,
+            decode_limits: order.decode_limits,
+            animated_previews: order.animated_previews,
+            storyboard_frames: order.storyboard_frames,
+        }
+    }
+}
+
+/// everything a provider created for one source file
+pub struct ProvidedThumbnail {
+    /// fits into max_x / max_y of the request
+    pub image: image::RgbaImage,
+    pub metadata: ImageMetadata,
+    pub animation: Option<AnimationWithPreview>,
+    /// evenly spaced frames of a video, composed into a `Storyboard` by the thumbnailer
+    pub storyboard_frames: Vec<image::RgbaImage>,
+    /// name of the archive entry, that was used as cover
+    pub archive_entry: Option<String>,
+    /// float (hdr) source, that was tone mapped instead of clamped
+    pub tone_mapped: bool,
+    /// only set for real image sources, rendered tiles (album covers, text, font specimens,
+    /// archive covers, video frames) would group whole albums or font families as duplicates
+    pub perceptual_hash: Option<u64>,
+    /// measured before sharpening, only set for real image sources and video frames (album
+    /// covers, text, font specimens and archive covers aren't what the quality filters are for)
+    pub quality: Option<QualityMetrics>,
+}
+
+impl ProvidedThumbnail {
+    pub fn new(image: image::RgbaImage) -> Self {
+        Self {
+            image,
+            metadata: ImageMetadata::default(),
+            animation: None,
+            storyboard_frames: Vec::new(),
+            archive_entry: None,
+            tone_mapped: false,
+            perceptual_hash: None,
+            quality: None,
+        }
+    }
+}
+
+pub trait ThumbnailProvider: Send + Sync {
+    /// used for logging
+    fn name(&self) -> &'static str;
+
+    /// cheap check by file extension and / or magic bytes (`header` holds the first bytes of the file)
+    fn can_handle(&self, path: &Path, header: &[u8]) -> bool;
+
+    fn provide(
+        &self,
+        path: &Path,
+        request: &ThumbnailRequest
+    ) -> Result<ProvidedThumbnail, Box<dyn Error>>;
+}
+
+/// case insensitive check of the file extension
+pub fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| extensions.iter().any(|allowed| ext.eq_ignore_ascii_case(allowed)))
+}
+
+/// picks the provider for a file
+///
+/// providers registered later take precedence, so that specialized providers
+/// can handle files before the more general ones (e.g. the built-in `ImageProvider`)
+pub struct ProviderRegistry {
+    providers: Vec<Box<dyn ThumbnailProvider>>,
+}
+
+impl ProviderRegistry {
+    /// a registry without any provider (see `ProviderRegistry::default` for the built-in ones)
+    pub fn empty() -> Self {
+        Self { providers: Vec::new() }
+    }
+
+    pub fn register<T>(&mut self, provider: T) where T: ThumbnailProvider + 'static {
+        self.providers.push(Box::new(provider));
+    }
+
+    pub fn with<T>(mut self, provider: T) -> Self where T: ThumbnailProvider + 'static {
+        self.register(provider);
+        self
+    }
+
+    pub fn providers(&self) -> impl Iterator<Item = &dyn ThumbnailProvider> {
+        self.providers
+            .iter()
+            .rev()
+            .map(|provider| provider.as_ref())
+    }
+
+    /// all providers, that can handle the file (highest priority first)
+    pub fn find<P>(&self, path: P) -> Result<Vec<&dyn ThumbnailProvider>, Box<dyn Error>>
+        where P: AsRef<Path>
+    {
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        fs::File
+            ::open(&path)?
+            .take(HEADER_LEN as u64)
+            .read_to_end(&mut header)?;
+
+        Ok(
+            self
+                .providers()
+                .filter(|provider| provider.can_handle(path.as_ref(), &header))
+                .collect()
+        )
+    }
+
+    /// tries every provider, that can handle the file, until one succeeds
+    ///
+    /// `LimitExceeded` errors are returned immediately, otherwise the error of
+    /// the provider with the highest priority is returned
+    pub fn provide<P>(
+        &self,
+        path: P,
+        request: &ThumbnailRequest
+    ) -> Result<ProvidedThumbnail, Box<dyn Error>>
+        where P: AsRef<Path>
+    {
+        let path = path.as_ref();
+        let mut first_err: Option<Box<dyn Error>> = None;
+
+        for provider in self.find(path)? {
+            match provider.provide(path, request) {
+                Ok(provided) => {
+                    return Ok(provided);
+                }
+                Err(err) => {
+                    if err.is::<LimitExceeded>() {
+                        return Err(err);
+                    }
+                    log::trace!(
+                        "[{}]: failed to create thumbnail for {} ({err})",
+                        provider.name(),
+                        path.display()
+                    );
+                    first_err.get_or_insert(err);
+                }
+            }
+        }
+
+        Err(first_err.unwrap_or_else(|| "no provider can handle this file".into()))
+    }
+}
+
+impl Default for ProviderRegistry {
+    fn default() -> Self {
+        let registry = Self::empty()
+            .with(ImageProvider)
+            .with(VideoProvider::default())
+            .with(SvgProvider::default())
+            .with(RawProvider)
+            .with(ArchiveProvider)
+            .with(AudioProvider)
+            .with(TextProvider::default())
+            .with(FontProvider);
+
+        #[cfg(feature = "heif")]
+        let registry = registry.with(HeifProvider);
+
+        registry
+    }
+}
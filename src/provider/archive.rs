@@ -0,0 +1,315 @@
+use std::{ fs, io::{ BufReader, Read, Seek }, path::Path };
+
+use crate::*;
+use crate::provider::{ has_extension, ProvidedThumbnail, ThumbnailProvider, ThumbnailRequest };
+use crate::thumbnailer::generate_thumbnail_from_memory;
+
+const ARCHIVE_EXTENSIONS: &[&str] = &["zip", "cbz", "epub"];
+
+/// first local file header of an epub: the uncompressed "mimetype" entry (ocf spec)
+const EPUB_HEADER: &[u8] = b"mimetypeapplication/epub+zip";
+
+/// larger xml files of epubs (container.xml / package document) are ignored
+const MAX_XML_LEN: u64 = 4 * 1024 * 1024;
+
+/// the declared entry size is up to the author of the file, so larger covers grow while reading
+const MAX_PREALLOCATION: u64 = 16 * 1024 * 1024;
+
+/// cover thumbnails of zip archives, comic books (cbz) and epubs
+///
+/// the cover is read into memory, nothing is extracted to disk
+pub struct ArchiveProvider;
+
+impl ThumbnailProvider for ArchiveProvider {
+    fn name(&self) -> &'static str {
+        "archive"
+    }
+
+    /// only by extension (and epubs by their mimetype entry), docx / odt / jar / apk / ...
+    /// are zips as well, but their images aren't covers
+    fn can_handle(&self, path: &Path, header: &[u8]) -> bool {
+        let is_epub = header.starts_with(b"PK\x03\x04") && header.get(30..58) == Some(EPUB_HEADER);
+
+        has_extension(path, ARCHIVE_EXTENSIONS) || is_epub
+    }
+
+    fn provide(
+        &self,
+        path: &Path,
+        request: &ThumbnailRequest
+    ) -> Result<ProvidedThumbnail, Box<dyn Error>> {
+        let mut archive = zip::ZipArchive::new(BufReader::new(fs::File::open(path)?))?;
+
+        let cover = match epub_cover(&mut archive) {
+            Some(cover) => cover,
+            None => first_image(&archive).ok_or("archive doesn't contain any images")?,
+        };
+
+        let data = {
+            let entry = archive.by_name(&cover)?;
+            // the declared size could be wrong, so reading is limited as well
+            let size = entry.size();
+            request.decode_limits.check_file_size(size)?;
+            let limit = size.min(request.decode_limits.max_file_size.unwrap_or(u64::MAX));
+
+            let mut data = Vec::with_capacity(limit.min(MAX_PREALLOCATION) as usize);
+            entry.take(limit).read_to_end(&mut data)?;
+            data
+        };
+
+        let mut provided = generate_thumbnail_from_memory(
+            &data,
+            request.max_x,
+            request.max_y,
+            request.apply_orientation,
+            request.convert_to_srgb,
+            request.scaling,
+            &request.decode_limits
+        )?;
+
+        // the dimensions of the cover are shown, its exif data isn't about the archive
+        let (width, height) = (provided.metadata.width, provided.metadata.height);
+        provided.metadata = ImageMetadata { width, height, ..Default::default() };
+        provided.archive_entry = Some(cover);
+        // only the cover was measured, not the archive
+        provided.quality = None;
+
+        Ok(provided)
+    }
+}
+
+/// first image in natural sort order ("page 2.jpg" before "page 10.jpg")
+fn first_image<R>(archive: &zip::ZipArchive<R>) -> Option<String> where R: Read + Seek {
+    archive
+        .file_names()
+        .filter(|name| !name.ends_with('/') && image::ImageFormat::from_path(name).is_ok())
+        .filter(|name| {
+            // resource forks / hidden files added by macos
+            let file_name = name.rsplit('/').next().unwrap_or(name);
+            !name.starts_with("__MACOSX/") && !file_name.starts_with('.')
+        })
+        .min_by(|a, b| natord::compare_ignore_case(a, b))
+        .map(String::from)
+}
+
+fn read_text_entry<R>(archive: &mut zip::ZipArchive<R>, name: &str) -> Option<String>
+    where R: Read + Seek
+{
+    let entry = archive.by_name(name).ok()?;
+    let mut text = String::new();
+    entry.take(MAX_XML_LEN).read_to_string(&mut text).ok()?;
+    Some(text)
+}
+
+/// the cover image declared in the package document of an epub
+///
+/// epub 3 marks it with `properties="cover-image"`, epub 2 with `<meta name="cover" content="[item id]">`
+fn epub_cover<R>(archive: &mut zip::ZipArchive<R>) -> Option<String> where R: Read + Seek {
+    let container = read_text_entry(archive, "META-INF/container.xml")?;
+    let container = roxmltree::Document::parse(&container).ok()?;
+    let package_path = container
+        .descendants()
+        .find(|node| node.has_tag_name("rootfile"))?
+        .attribute("full-path")?
+        .to_string();
+
+    let package = read_text_entry(archive, &package_path)?;
+    let package = roxmltree::Document::parse(&package).ok()?;
+    let items: Vec<roxmltree::Node> = package
+        .descendants()
+        .filter(|node| node.has_tag_name("item"))
+        .collect();
+
+    let declared_id = package
+        .descendants()
+        .find(|node| node.has_tag_name("meta") && node.attribute("name") == Some("cover"))
+        .and_then(|meta| meta.attribute("content"));
+
+    let cover = items
+        .iter()
+        .find(|item| {
+            item.attribute("properties").is_some_and(|properties|
+                properties.split_whitespace().any(|property| property == "cover-image")
+            )
+        })
+        .or_else(|| items.iter().find(|item| declared_id.is_some() && item.attribute("id") == declared_id))
+        .filter(|item| item.attribute("media-type").is_some_and(|media_type| media_type.starts_with("image/")))?;
+
+    // hrefs are relative to the package document
+    let package_dir = package_path.rsplit_once('/').map_or("", |(dir, _)| dir);
+    let cover_path = resolve_href(package_dir, cover.attribute("href")?);
+
+    archive.file_names().any(|name| name == cover_path).then_some(cover_path)
+}
+
+/// joins the (percent encoded) href with the directory and resolves "." / ".." segments
+fn resolve_href(dir: &str, href: &str) -> String {
+    let href = percent_decode(href.split('#').next().unwrap_or(href));
+    let mut segments: Vec<&str> = dir.split('/').filter(|segment| !segment.is_empty()).collect();
+
+    for segment in href.split('/') {
+        match segment {
+            "" | "." => (),
+            ".." => {
+                segments.pop();
+            }
+            segment => segments.push(segment),
+        }
+    }
+
+    segments.join("/")
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ Cursor, Write };
+
+    use super::*;
+
+    /// zip in memory with the entries in the given order (stored, like the epub mimetype has to be)
+    fn archive(entries: &[(&str, &[u8])]) -> zip::ZipArchive<Cursor<Vec<u8>>> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = zip::write::FileOptions
+            ::default()
+            .compression_method(zip::CompressionMethod::Stored);
+        for (name, data) in entries {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(data).unwrap();
+        }
+
+        zip::ZipArchive::new(writer.finish().unwrap()).unwrap()
+    }
+
+    const CONTAINER: &[u8] = br#"<?xml version="1.0"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>"#;
+
+    #[test]
+    fn picks_first_image_in_natural_order() {
+        let archive = archive(&[
+            ("page 10.jpg", b""),
+            ("__MACOSX/page 1.jpg", b""),
+            ("comic/.page 0.png", b""),
+            ("page 2.PNG", b""),
+            ("notes.txt", b""),
+            ("images/", b""),
+        ]);
+
+        assert_eq!(first_image(&archive).as_deref(), Some("page 2.PNG"));
+        assert_eq!(first_image(&self::archive(&[("readme.md", b"")])), None);
+    }
+
+    #[test]
+    fn finds_epub3_cover_image() {
+        let package = br#"<package xmlns="http://www.idpf.org/2007/opf" version="3.0">
+  <manifest>
+    <item id="page" href="text/page.xhtml" media-type="application/xhtml+xml"/>
+    <item id="img" href="../images/cover%20art.jpg" media-type="image/jpeg" properties="cover-image"/>
+  </manifest>
+</package>"#;
+        let mut archive = archive(&[
+            ("mimetype", b"application/epub+zip"),
+            ("META-INF/container.xml", CONTAINER),
+            ("OEBPS/content.opf", package),
+            ("images/cover art.jpg", b""),
+            ("OEBPS/a.jpg", b""),
+        ]);
+
+        assert_eq!(epub_cover(&mut archive).as_deref(), Some("images/cover art.jpg"));
+    }
+
+    #[test]
+    fn finds_epub2_cover_meta() {
+        let package = br#"<package xmlns="http://www.idpf.org/2007/opf" version="2.0">
+  <metadata><meta name="cover" content="cover-id"/></metadata>
+  <manifest>
+    <item id="first" href="a.jpg" media-type="image/jpeg"/>
+    <item id="cover-id" href="./img/cover.png" media-type="image/png"/>
+  </manifest>
+</package>"#;
+        let mut archive = archive(&[
+            ("META-INF/container.xml", CONTAINER),
+            ("OEBPS/content.opf", package),
+            ("OEBPS/a.jpg", b""),
+            ("OEBPS/img/cover.png", b""),
+        ]);
+
+        assert_eq!(epub_cover(&mut archive).as_deref(), Some("OEBPS/img/cover.png"));
+    }
+
+    #[test]
+    fn ignores_missing_or_non_image_epub_covers() {
+        let package = br#"<package xmlns="http://www.idpf.org/2007/opf" version="3.0">
+  <manifest>
+    <item id="c" href="cover.xhtml" media-type="application/xhtml+xml" properties="cover-image"/>
+  </manifest>
+</package>"#;
+        let mut not_an_image = archive(&[
+            ("META-INF/container.xml", CONTAINER),
+            ("OEBPS/content.opf", package),
+            ("OEBPS/cover.xhtml", b""),
+        ]);
+        assert_eq!(epub_cover(&mut not_an_image), None);
+
+        // the package document is declared, but missing
+        let mut broken = archive(&[("META-INF/container.xml", CONTAINER)]);
+        assert_eq!(epub_cover(&mut broken), None);
+        assert_eq!(epub_cover(&mut archive(&[("a.jpg", b"")])), None);
+    }
+
+    #[test]
+    fn resolves_hrefs() {
+        assert_eq!(resolve_href("OEBPS/text", "../images/a%20b.jpg#frag"), "OEBPS/images/a b.jpg");
+        assert_eq!(resolve_href("", "./cover.jpg"), "cover.jpg");
+        // can't escape the archive root
+        assert_eq!(resolve_href("a", "../../../b.jpg"), "b.jpg");
+        assert_eq!(percent_decode("100%"), "100%");
+    }
+
+    #[test]
+    fn handles_only_archives_and_epubs() {
+        let provider = ArchiveProvider;
+        let mut epub = archive(&[("mimetype", b"application/epub+zip"), ("a.jpg", b"")])
+            .into_inner()
+            .into_inner();
+        epub.truncate(512);
+        let docx = archive(&[("[Content_Types].xml", b""), ("word/media/image1.png", b"")])
+            .into_inner()
+            .into_inner();
+
+        assert!(provider.can_handle(Path::new("book"), &epub));
+        assert!(provider.can_handle(Path::new("comic.CBZ"), b""));
+        assert!(!provider.can_handle(Path::new("letter.docx"), &docx));
+        assert!(!provider.can_handle(Path::new("app.jar"), b"PK\x03\x04"));
+    }
+}
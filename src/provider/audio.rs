@@ -0,0 +1,812 @@
+use std::{ fs, io::{ self, BufReader, Read, Seek, SeekFrom }, path::{ Path, PathBuf } };
+
+use base64::Engine;
+
+use crate::*;
+use crate::provider::{ has_extension, ProvidedThumbnail, ThumbnailProvider, ThumbnailRequest };
+use crate::provider::bmff::{ find_box, read_boxes };
+use crate::thumbnailer::{ generate_thumbnail_from_image, generate_thumbnail_from_memory };
+
+const AUDIO_EXTENSIONS: &[&str] = &["mp3", "flac", "m4a", "m4b", "ogg", "oga", "opus"];
+
+/// cover images next to the audio files, that are used when nothing is embedded (compared case insensitive)
+const FOLDER_COVERS: &[&str] = &["cover.jpg", "folder.jpg", "cover.jpeg", "folder.jpeg", "cover.png", "folder.png"];
+
+/// id3 / flac picture type of the front cover
+const FRONT_COVER: u32 = 3;
+
+/// album art of audio files
+///
+/// embedded pictures are read from id3v2 (mp3), flac, mp4 (m4a) and vorbis comments (ogg / opus)
+pub struct AudioProvider;
+
+impl ThumbnailProvider for AudioProvider {
+    fn name(&self) -> &'static str {
+        "audio"
+    }
+
+    fn can_handle(&self, path: &Path, header: &[u8]) -> bool {
+        let is_m4a =
+            header.get(4..8) == Some(b"ftyp") &&
+            matches!(header.get(8..12), Some(b"M4A ") | Some(b"M4B "));
+
+        has_extension(path, AUDIO_EXTENSIONS) ||
+            header.starts_with(b"ID3") ||
+            header.starts_with(b"fLaC") ||
+            header.starts_with(b"OggS") ||
+            is_m4a
+    }
+
+    fn provide(
+        &self,
+        path: &Path,
+        request: &ThumbnailRequest
+    ) -> Result<ProvidedThumbnail, Box<dyn Error>> {
+        // the pictures are limited by the max file size of the request, not the (large) audio file
+        let max_len = request.decode_limits.max_file_size.unwrap_or(u64::MAX);
+
+        let embedded = match read_embedded_picture(path, max_len) {
+            Ok(picture) => picture,
+            Err(err) => {
+                log::trace!("failed to read embedded album art of {} ({err})", path.display());
+                None
+            }
+        };
+
+        let mut provided = match embedded {
+            Some(picture) =>
+                generate_thumbnail_from_memory(
+                    &picture,
+                    request.max_x,
+                    request.max_y,
+                    request.apply_orientation,
+                    request.convert_to_srgb,
+                    request.scaling,
+                    &request.decode_limits
+                )?,
+            None => {
+                let mut first_err: Option<Box<dyn Error>> = None;
+                let mut provided = None;
+
+                for cover in folder_covers(path) {
+                    match
+                        generate_thumbnail_from_image(
+                            cover,
+                            request.max_x,
+                            request.max_y,
+                            request.apply_orientation,
+                            request.convert_to_srgb,
+                            request.scaling,
+                            &request.decode_limits
+                        )
+                    {
+                        Ok(thumbnail) => {
+                            provided = Some(thumbnail);
+                            break;
+                        }
+                        Err(err) => {
+                            first_err.get_or_insert(err);
+                        }
+                    }
+                }
+
+                match (provided, first_err) {
+                    (Some(provided), _) => provided,
+                    (None, Some(err)) => {
+                        return Err(err);
+                    }
+                    (None, None) => {
+                        return Err("no embedded album art and no cover image in the folder".into());
+                    }
+                }
+            }
+        };
+        // the exif data and quality of the album cover say nothing about the audio file
+        provided.metadata = ImageMetadata::default();
+        provided.quality = None;
+
+        Ok(provided)
+    }
+}
+
+/// `cover.jpg`, `folder.jpg`, ... in the directory of the audio file (in the order of FOLDER_COVERS)
+fn folder_covers(path: &Path) -> Vec<PathBuf> {
+    let entries = match path.parent().map(fs::read_dir) {
+        Some(Ok(entries)) => entries,
+        _ => {
+            return Vec::new();
+        }
+    };
+
+    let mut covers: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|candidate| {
+            candidate
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| FOLDER_COVERS.iter().any(|cover| name.eq_ignore_ascii_case(cover)))
+        })
+        .collect();
+
+    covers.sort_by_key(|candidate| {
+        let name = candidate.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+        FOLDER_COVERS.iter().position(|cover| name.eq_ignore_ascii_case(cover))
+    });
+    covers
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// reads exactly `len` bytes, unless they exceed `max_len`
+fn read_limited<R>(reader: &mut R, len: u64, max_len: u64) -> io::Result<Vec<u8>> where R: Read {
+    if len > max_len {
+        return Err(invalid_data("embedded data exceeds the max file size"));
+    }
+    let mut data = Vec::new();
+    reader.take(len).read_to_end(&mut data)?;
+    if (data.len() as u64) < len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(data)
+}
+
+fn be_u32(bytes: &[u8]) -> Option<u32> {
+    Some(u32::from_be_bytes(bytes.get(..4)?.try_into().ok()?))
+}
+
+fn le_u32(bytes: &[u8]) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(..4)?.try_into().ok()?))
+}
+
+/// the front cover (or the first picture, if there is no front cover)
+fn read_embedded_picture(path: &Path, max_len: u64) -> io::Result<Option<Vec<u8>>> {
+    let mut reader = BufReader::new(fs::File::open(path)?);
+    let mut magic = [0u8; 12];
+    let magic_len = reader.read(&mut magic)?;
+    let magic = &magic[..magic_len];
+    reader.seek(SeekFrom::Start(0))?;
+
+    let pictures = if magic.starts_with(b"ID3") {
+        id3_pictures(&mut reader, max_len)?
+    } else if magic.starts_with(b"fLaC") {
+        flac_pictures(&mut reader, max_len)?
+    } else if magic.starts_with(b"OggS") {
+        ogg_pictures(&mut reader, max_len)?
+    } else if magic.get(4..8) == Some(b"ftyp") {
+        let file_len = reader.get_ref().metadata()?.len();
+        return mp4_cover(&mut reader, file_len, max_len);
+    } else {
+        Vec::new()
+    };
+
+    let front_cover = pictures.iter().position(|(picture_type, _)| *picture_type == FRONT_COVER);
+    Ok(
+        pictures
+            .into_iter()
+            .nth(front_cover.unwrap_or(0))
+            .map(|(_, data)| data)
+    )
+}
+
+/// (picture type, image data) of every APIC (id3v2.3 / 2.4) or PIC (id3v2.2) frame
+fn id3_pictures<R>(reader: &mut R, max_len: u64) -> io::Result<Vec<(u32, Vec<u8>)>> where R: Read {
+    let mut header = [0u8; 10];
+    reader.read_exact(&mut header)?;
+    let version = header[3];
+    let flags = header[5];
+    let tag_len = syncsafe(&header[6..10]);
+
+    let mut tag = read_limited(reader, u64::from(tag_len), max_len)?;
+    // unsynchronisation of the whole tag (v2.4 marks it per frame as well)
+    if flags & 0x80 != 0 && version < 4 {
+        tag = remove_unsynchronisation(&tag);
+    }
+
+    let mut pos = 0;
+    if flags & 0x40 != 0 && version >= 3 {
+        // extended header (v2.3: size without itself, v2.4: syncsafe size with itself)
+        let ext_len = match version {
+            3 => be_u32(&tag).map(|len| (len as usize) + 4),
+            _ => tag.get(..4).map(|bytes| syncsafe(bytes) as usize),
+        };
+        pos = ext_len.ok_or_else(|| invalid_data("truncated id3 extended header"))?;
+    }
+
+    let (id_len, header_len) = match version {
+        2 => (3, 6),
+        _ => (4, 10),
+    };
+
+    let mut pictures = Vec::new();
+    while pos + header_len <= tag.len() {
+        let frame_header = &tag[pos..pos + header_len];
+        // padding
+        if frame_header[0] == 0 {
+            break;
+        }
+
+        let id = &frame_header[..id_len];
+        let frame_len = match version {
+            2 => u32::from_be_bytes([0, frame_header[3], frame_header[4], frame_header[5]]),
+            3 => be_u32(&frame_header[4..8]).unwrap_or(0),
+            _ => syncsafe(&frame_header[4..8]),
+        } as usize;
+        let frame_start = pos + header_len;
+        let frame_end = frame_start.saturating_add(frame_len).min(tag.len());
+        let mut frame = tag[frame_start..frame_end].to_vec();
+        pos = frame_end;
+
+        if version >= 4 {
+            let format_flags = frame_header[9];
+            if format_flags & 0x0c != 0 {
+                // compressed / encrypted frames are skipped
+                continue;
+            }
+            if format_flags & 0x01 != 0 {
+                // data length indicator
+                frame = frame.split_off(4.min(frame.len()));
+            }
+            if format_flags & 0x02 != 0 || flags & 0x80 != 0 {
+                frame = remove_unsynchronisation(&frame);
+            }
+        }
+
+        let picture = match id {
+            b"APIC" => parse_apic(&frame, false),
+            b"PIC" => parse_apic(&frame, true),
+            _ => None,
+        };
+        pictures.extend(picture);
+    }
+
+    Ok(pictures)
+}
+
+/// 4 bytes with 7 bits each
+fn syncsafe(bytes: &[u8]) -> u32 {
+    bytes
+        .iter()
+        .take(4)
+        .fold(0, |value, byte| (value << 7) | u32::from(byte & 0x7f))
+}
+
+/// 0xff 0x00 -> 0xff
+fn remove_unsynchronisation(data: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len());
+    for (i, byte) in data.iter().enumerate() {
+        if *byte == 0x00 && i > 0 && data[i - 1] == 0xff {
+            continue;
+        }
+        result.push(*byte);
+    }
+    result
+}
+
+/// text encoding, mime type (or 3 byte image format for PIC), picture type, description, data
+fn parse_apic(frame: &[u8], is_pic: bool) -> Option<(u32, Vec<u8>)> {
+    let encoding = *frame.first()?;
+    let mut pos = 1;
+
+    if is_pic {
+        pos += 3;
+    } else {
+        pos += frame.get(pos..)?.iter().position(|byte| *byte == 0)? + 1;
+    }
+
+    let picture_type = u32::from(*frame.get(pos)?);
+    pos += 1;
+
+    // utf-16 descriptions end with two zero bytes (at an even position)
+    let description = frame.get(pos..)?;
+    pos += match encoding {
+        1 | 2 =>
+            description
+                .chunks_exact(2)
+                .position(|pair| pair == [0, 0])
+                .map(|index| index * 2 + 2)?,
+        _ => description.iter().position(|byte| *byte == 0)? + 1,
+    };
+
+    Some((picture_type, frame.get(pos..)?.to_vec()))
+}
+
+/// the PICTURE metadata blocks of a native flac file
+fn flac_pictures<R>(reader: &mut R, max_len: u64) -> io::Result<Vec<(u32, Vec<u8>)>> where R: Read + Seek {
+    reader.seek(SeekFrom::Start(4))?;
+    let mut pictures = Vec::new();
+
+    loop {
+        let mut block_header = [0u8; 4];
+        reader.read_exact(&mut block_header)?;
+        let is_last = block_header[0] & 0x80 != 0;
+        let block_type = block_header[0] & 0x7f;
+        let block_len = u32::from_be_bytes([0, block_header[1], block_header[2], block_header[3]]);
+
+        match block_type {
+            6 => {
+                let block = read_limited(reader, u64::from(block_len), max_len)?;
+                pictures.extend(parse_flac_picture(&block));
+            }
+            _ => {
+                reader.seek(SeekFrom::Current(i64::from(block_len)))?;
+            }
+        }
+
+        if is_last {
+            break;
+        }
+    }
+
+    Ok(pictures)
+}
+
+/// picture type, mime type, description, width, height, depth, colors, data (all lengths / numbers are big endian u32)
+fn parse_flac_picture(block: &[u8]) -> Option<(u32, Vec<u8>)> {
+    let picture_type = be_u32(block)?;
+    let mut pos = 4;
+
+    for _ in 0..2 {
+        // mime type and description
+        let len = be_u32(block.get(pos..)?)? as usize;
+        pos = pos.checked_add(4 + len)?;
+    }
+    pos += 16;
+
+    let data_len = be_u32(block.get(pos..)?)? as usize;
+    let data = block.get(pos + 4..pos.checked_add(4 + data_len)?)?;
+
+    Some((picture_type, data.to_vec()))
+}
+
+/// the base64 encoded flac pictures (METADATA_BLOCK_PICTURE) of the vorbis comments in ogg vorbis / opus files
+fn ogg_pictures<R>(reader: &mut R, max_len: u64) -> io::Result<Vec<(u32, Vec<u8>)>> where R: Read {
+    let comments = match ogg_comment_packet(reader, max_len)? {
+        Some(packet) => packet,
+        None => {
+            return Ok(Vec::new());
+        }
+    };
+
+    let comments = if let Some(comments) = comments.strip_prefix(b"\x03vorbis") {
+        comments
+    } else if let Some(comments) = comments.strip_prefix(b"OpusTags") {
+        comments
+    } else {
+        return Ok(Vec::new());
+    };
+
+    let vendor_len = le_u32(comments).ok_or_else(|| invalid_data("truncated vorbis comments"))? as usize;
+    let mut pos = 4 + vendor_len;
+    let comment_count = comments
+        .get(pos..)
+        .and_then(le_u32)
+        .ok_or_else(|| invalid_data("truncated vorbis comments"))?;
+    pos += 4;
+
+    let mut pictures = Vec::new();
+    for _ in 0..comment_count {
+        let comment = comments
+            .get(pos..)
+            .and_then(le_u32)
+            .and_then(|len| comments.get(pos + 4..(pos + 4).saturating_add(len as usize)));
+        let comment = match comment {
+            Some(comment) => comment,
+            None => {
+                break;
+            }
+        };
+        pos += 4 + comment.len();
+
+        // KEY=value
+        let (key, value) = match comment.iter().position(|byte| *byte == b'=') {
+            Some(separator) => (&comment[..separator], &comment[separator + 1..]),
+            None => {
+                continue;
+            }
+        };
+
+        if key.eq_ignore_ascii_case(b"METADATA_BLOCK_PICTURE") {
+            if let Ok(block) = base64::engine::general_purpose::STANDARD.decode(value) {
+                pictures.extend(parse_flac_picture(&block));
+            }
+        } else if key.eq_ignore_ascii_case(b"COVERART") {
+            // deprecated: the image itself
+            if let Ok(data) = base64::engine::general_purpose::STANDARD.decode(value) {
+                pictures.push((FRONT_COVER, data));
+            }
+        }
+    }
+
+    Ok(pictures)
+}
+
+/// the second packet of the first logical stream (the comment header of vorbis and opus)
+fn ogg_comment_packet<R>(reader: &mut R, max_len: u64) -> io::Result<Option<Vec<u8>>> where R: Read {
+    let mut serial = None;
+    let mut packet_index = 0;
+    let mut packet = Vec::new();
+
+    loop {
+        let mut page_header = [0u8; 27];
+        match reader.read_exact(&mut page_header) {
+            Ok(_) => (),
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                return Ok(None);
+            }
+            Err(err) => {
+                return Err(err);
+            }
+        }
+        if &page_header[..4] != b"OggS" {
+            return Err(invalid_data("broken ogg page"));
+        }
+
+        let page_serial = le_u32(&page_header[14..18]);
+        let mut segment_table = vec![0u8; usize::from(page_header[26])];
+        reader.read_exact(&mut segment_table)?;
+        let page_len: u64 = segment_table.iter().map(|len| u64::from(*len)).sum();
+        let page = read_limited(reader, page_len, max_len)?;
+
+        // pages of other (multiplexed) streams
+        if *serial.get_or_insert(page_serial) != page_serial {
+            continue;
+        }
+
+        let mut pos = 0;
+        for segment_len in segment_table {
+            let segment_len = usize::from(segment_len);
+            if packet_index == 1 {
+                packet.extend_from_slice(&page[pos..pos + segment_len]);
+                if (packet.len() as u64) > max_len {
+                    return Err(invalid_data("ogg comment header exceeds the max file size"));
+                }
+            }
+            pos += segment_len;
+
+            // segments shorter than 255 bytes end a packet
+            if segment_len < 255 {
+                if packet_index == 1 {
+                    return Ok(Some(packet));
+                }
+                packet_index += 1;
+            }
+        }
+    }
+}
+
+/// first `data` box of moov/udta/meta/ilst/covr (the data box starts with 4 bytes type and 4 bytes locale)
+fn mp4_cover<R>(reader: &mut R, file_len: u64, max_len: u64) -> io::Result<Option<Vec<u8>>>
+    where R: Read + Seek
+{
+    let mut data = match read_boxes(reader, 0, file_len)?.into_iter().find(|top| &top.kind == b"moov") {
+        Some(moov) => moov,
+        None => {
+            return Ok(None);
+        }
+    };
+    for kind in [b"udta", b"meta", b"ilst", b"covr", b"data"] {
+        data = match find_box(reader, &data, kind)? {
+            Some(child) => child,
+            None => {
+                return Ok(None);
+            }
+        };
+        if kind == b"meta" {
+            // full box (version and flags before the children)
+            data.offset += 4;
+            data.len = data.len.saturating_sub(4);
+        }
+    }
+    if data.len < 8 {
+        return Ok(None);
+    }
+
+    reader.seek(SeekFrom::Start(data.offset + 8))?;
+    Ok(Some(read_limited(reader, data.len - 8, max_len)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    const MAX_LEN: u64 = 64 * 1024;
+
+    fn syncsafe_bytes(value: u32) -> [u8; 4] {
+        [21, 14, 7, 0].map(|shift| ((value >> shift) & 0x7f) as u8)
+    }
+
+    /// frame of an id3v2.`version` tag (`format_flags` are only used by v2.4)
+    fn id3_frame(version: u8, id: &[u8], format_flags: u8, content: &[u8]) -> Vec<u8> {
+        let len = content.len() as u32;
+        let header = match version {
+            2 => [id, &len.to_be_bytes()[1..]].concat(),
+            3 => [id, &len.to_be_bytes(), &[0, 0]].concat(),
+            _ => [id, &syncsafe_bytes(len), &[0, format_flags]].concat(),
+        };
+        [header.as_slice(), content].concat()
+    }
+
+    fn id3_tag(version: u8, frames: &[Vec<u8>]) -> Vec<u8> {
+        let frames = frames.concat();
+        let tag_len = syncsafe_bytes(frames.len() as u32);
+        [b"ID3".as_slice(), &[version, 0, 0], &tag_len, &frames].concat()
+    }
+
+    /// latin-1 APIC frame content
+    fn apic(picture_type: u8, data: &[u8]) -> Vec<u8> {
+        [b"\0image/jpeg\0".as_slice(), &[picture_type], b"cover\0", data].concat()
+    }
+
+    fn id3_pictures_of(tag: &[u8], max_len: u64) -> io::Result<Vec<(u32, Vec<u8>)>> {
+        id3_pictures(&mut Cursor::new(tag), max_len)
+    }
+
+    #[test]
+    fn reads_apic_frames_of_all_id3_versions() {
+        let data = vec![0xab; 300];
+
+        let v23 = id3_tag(3, &[
+            id3_frame(3, b"TIT2", 0, b"\0title"),
+            id3_frame(3, b"APIC", 0, &apic(0, b"other")),
+            id3_frame(3, b"APIC", 0, &apic(3, &data)),
+        ]);
+        assert_eq!(
+            id3_pictures_of(&v23, MAX_LEN).unwrap(),
+            vec![(0, b"other".to_vec()), (3, data.clone())]
+        );
+
+        // the 300 byte frame has a different syncsafe and plain size
+        let v24 = id3_tag(4, &[id3_frame(4, b"APIC", 0, &apic(3, &data))]);
+        assert_eq!(id3_pictures_of(&v24, MAX_LEN).unwrap(), vec![(3, data.clone())]);
+
+        let pic = [b"\0JPG", &[3u8][..], b"\0", &data].concat();
+        let v22 = id3_tag(2, &[id3_frame(2, b"PIC", 0, &pic)]);
+        assert_eq!(id3_pictures_of(&v22, MAX_LEN).unwrap(), vec![(3, data)]);
+    }
+
+    #[test]
+    fn reads_id3v24_frame_flags_and_utf16_descriptions() {
+        // "A\u{4100}": the zero bytes at an odd position don't end the description
+        let utf16 = [b"\x01image/png\0\x03\xff\xfeA\0\0\x41\0\0", b"data".as_slice()].concat();
+        // data length indicator and unsynchronisation
+        let unsynchronised = [b"\0\0\0\x07".as_slice(), &apic(3, b"\xff\x00\xd8")].concat();
+        let tag = id3_tag(4, &[
+            id3_frame(4, b"APIC", 0, &utf16),
+            id3_frame(4, b"APIC", 0x03, &unsynchronised),
+            // compressed
+            id3_frame(4, b"APIC", 0x08, &apic(3, b"zlib")),
+        ]);
+
+        assert_eq!(
+            id3_pictures_of(&tag, MAX_LEN).unwrap(),
+            vec![(3, b"data".to_vec()), (3, b"\xff\xd8".to_vec())]
+        );
+    }
+
+    #[test]
+    fn rejects_broken_id3_lengths() {
+        let tag = id3_tag(3, &[id3_frame(3, b"APIC", 0, &apic(3, b"data"))]);
+
+        // tag length beyond the max length / the end of the file
+        assert!(id3_pictures_of(&tag, 8).is_err());
+        assert!(id3_pictures_of(&tag[..tag.len() - 1], MAX_LEN).is_err());
+
+        // frame length beyond the end of the tag
+        let mut oversized_frame = tag.clone();
+        oversized_frame[14..18].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert_eq!(
+            id3_pictures_of(&oversized_frame, MAX_LEN).unwrap(),
+            vec![(3, b"data".to_vec())]
+        );
+
+        // frame cut off within its header
+        let truncated = [id3_frame(3, b"APIC", 0, &apic(3, b"data")), b"APIC".to_vec()].concat();
+        let tag_len = syncsafe_bytes(truncated.len() as u32);
+        let truncated = [b"ID3\x03\0\0".as_slice(), &tag_len, &truncated].concat();
+        assert_eq!(id3_pictures_of(&truncated, MAX_LEN).unwrap(), vec![(3, b"data".to_vec())]);
+    }
+
+    fn flac_picture(picture_type: u32, data: &[u8]) -> Vec<u8> {
+        [
+            &picture_type.to_be_bytes(),
+            &10u32.to_be_bytes(),
+            b"image/jpeg".as_slice(),
+            &0u32.to_be_bytes(),
+            &[0; 16],
+            &(data.len() as u32).to_be_bytes(),
+            data,
+        ].concat()
+    }
+
+    fn flac_block(block_type: u8, is_last: bool, content: &[u8]) -> Vec<u8> {
+        let header = [block_type | (u8::from(is_last) << 7)];
+        [&header, &(content.len() as u32).to_be_bytes()[1..], content].concat()
+    }
+
+    fn flac_pictures_of(data: &[u8], max_len: u64) -> io::Result<Vec<(u32, Vec<u8>)>> {
+        flac_pictures(&mut Cursor::new(data), max_len)
+    }
+
+    #[test]
+    fn reads_flac_picture_blocks() {
+        let flac = [
+            b"fLaC".to_vec(),
+            flac_block(0, false, &[0; 34]),
+            flac_block(1, false, &[0; 100]),
+            flac_block(6, true, &flac_picture(3, b"cover")),
+            // audio frames
+            vec![0xff; 16],
+        ].concat();
+
+        assert_eq!(flac_pictures_of(&flac, MAX_LEN).unwrap(), vec![(3, b"cover".to_vec())]);
+    }
+
+    #[test]
+    fn rejects_broken_flac_lengths() {
+        let picture = flac_picture(3, b"cover");
+
+        // block length beyond the max length / the end of the file
+        let flac = [b"fLaC".to_vec(), flac_block(6, true, &picture)].concat();
+        assert!(flac_pictures_of(&flac, 8).is_err());
+        assert!(flac_pictures_of(&flac[..flac.len() - 1], MAX_LEN).is_err());
+        // no last block
+        let flac = [b"fLaC".to_vec(), flac_block(0, false, &[0; 34])].concat();
+        assert!(flac_pictures_of(&flac, MAX_LEN).is_err());
+
+        // data / mime type length beyond the end of the block
+        let mut oversized_data = picture.clone();
+        let data_len_pos = picture.len() - 5 - 4;
+        oversized_data[data_len_pos..data_len_pos + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+        let mut oversized_mime = picture;
+        oversized_mime[4..8].copy_from_slice(&u32::MAX.to_be_bytes());
+        for picture in [oversized_data, oversized_mime] {
+            let flac = [b"fLaC".to_vec(), flac_block(6, true, &picture)].concat();
+            assert!(flac_pictures_of(&flac, MAX_LEN).unwrap().is_empty());
+        }
+    }
+
+    /// ogg page with a part of a packet (`ends_packet`: the last segment is shorter than 255)
+    fn ogg_page(serial: u32, packet: &[u8], ends_packet: bool) -> Vec<u8> {
+        let mut segments = vec![255u8; packet.len() / 255];
+        if ends_packet {
+            segments.push((packet.len() % 255) as u8);
+        }
+        [
+            b"OggS\0\0".as_slice(),
+            &[0; 8],
+            &serial.to_le_bytes(),
+            &[0; 8],
+            &[segments.len() as u8],
+            &segments,
+            packet,
+        ].concat()
+    }
+
+    fn vorbis_comments(prefix: &[u8], comments: &[&[u8]]) -> Vec<u8> {
+        let mut packet = [prefix, &6u32.to_le_bytes(), b"vendor"].concat();
+        packet.extend((comments.len() as u32).to_le_bytes());
+        for comment in comments {
+            packet.extend((comment.len() as u32).to_le_bytes());
+            packet.extend_from_slice(comment);
+        }
+        packet
+    }
+
+    fn metadata_block_picture(data: &[u8]) -> Vec<u8> {
+        let picture = flac_picture(3, data);
+        let encoded = base64::engine::general_purpose::STANDARD.encode(picture);
+        [b"METADATA_BLOCK_PICTURE=".as_slice(), encoded.as_bytes()].concat()
+    }
+
+    fn ogg_pictures_of(data: &[u8], max_len: u64) -> io::Result<Vec<(u32, Vec<u8>)>> {
+        ogg_pictures(&mut Cursor::new(data), max_len)
+    }
+
+    #[test]
+    fn reads_pictures_of_vorbis_comments() {
+        // the comment header is split over two pages (255 bytes each), with a page of
+        // another stream in between
+        let comments = vorbis_comments(b"OpusTags", &[
+            b"TITLE=title",
+            &metadata_block_picture(&[0xab; 600]),
+        ]);
+        let ogg = [
+            ogg_page(1, b"OpusHead", true),
+            ogg_page(1, &comments[..510], false),
+            ogg_page(2, b"\x01vorbis", true),
+            ogg_page(1, &comments[510..], true),
+        ].concat();
+
+        assert_eq!(ogg_pictures_of(&ogg, MAX_LEN).unwrap(), vec![(3, vec![0xab; 600])]);
+
+        let comments = vorbis_comments(b"\x03vorbis", &[b"coverart=Y292ZXI="]);
+        let ogg = [ogg_page(7, b"\x01vorbis", true), ogg_page(7, &comments, true)].concat();
+        assert_eq!(ogg_pictures_of(&ogg, MAX_LEN).unwrap(), vec![(3, b"cover".to_vec())]);
+    }
+
+    #[test]
+    fn rejects_broken_vorbis_comment_lengths() {
+        let comments = vorbis_comments(b"\x03vorbis", &[&metadata_block_picture(b"cover")]);
+        let ogg = |comments: &[u8]| {
+            [ogg_page(1, b"\x01vorbis", true), ogg_page(1, comments, true)].concat()
+        };
+
+        // comment header beyond the max length
+        assert!(ogg_pictures_of(&ogg(&comments), 64).is_err());
+        // ends within the comment header
+        let complete = ogg(&comments);
+        assert!(ogg_pictures_of(&complete[..complete.len() - 1], MAX_LEN).is_err());
+
+        // comment / vendor length beyond the end of the packet
+        let mut oversized_comment = comments.clone();
+        oversized_comment[21..25].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(ogg_pictures_of(&ogg(&oversized_comment), MAX_LEN).unwrap().is_empty());
+        let mut oversized_vendor = comments;
+        oversized_vendor[7..11].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(ogg_pictures_of(&ogg(&oversized_vendor), MAX_LEN).is_err());
+    }
+
+    fn bmff_box(kind: &[u8; 4], content: &[u8]) -> Vec<u8> {
+        [&(8 + content.len() as u32).to_be_bytes(), kind.as_slice(), content].concat()
+    }
+
+    fn m4a(data_box: &[u8]) -> Vec<u8> {
+        let ilst = bmff_box(b"ilst", &bmff_box(b"covr", data_box));
+        // meta is a full box
+        let hdlr = bmff_box(b"hdlr", &[0; 24]);
+        let meta = bmff_box(b"meta", &[[0u8; 4].as_slice(), &hdlr, &ilst].concat());
+        [
+            bmff_box(b"ftyp", b"M4A \0\0\0\0"),
+            bmff_box(b"moov", &bmff_box(b"udta", &meta)),
+            bmff_box(b"mdat", &[0; 32]),
+        ].concat()
+    }
+
+    fn mp4_cover_of(data: &[u8], max_len: u64) -> io::Result<Option<Vec<u8>>> {
+        mp4_cover(&mut Cursor::new(data), data.len() as u64, max_len)
+    }
+
+    #[test]
+    fn reads_covr_of_m4a() {
+        // type (13 = jpeg) and locale precede the image
+        let covr = m4a(&bmff_box(b"data", b"\0\0\0\x0d\0\0\0\0cover"));
+
+        assert_eq!(mp4_cover_of(&covr, MAX_LEN).unwrap(), Some(b"cover".to_vec()));
+        assert!(mp4_cover_of(&covr, 4).is_err());
+        assert_eq!(mp4_cover_of(&m4a(&bmff_box(b"name", b"cover")), MAX_LEN).unwrap(), None);
+    }
+
+    #[test]
+    fn ignores_broken_covr_boxes() {
+        // data box larger than its parent
+        let mut oversized = bmff_box(b"data", b"\0\0\0\x0d\0\0\0\0cover");
+        oversized[..4].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert_eq!(mp4_cover_of(&m4a(&oversized), MAX_LEN).unwrap(), None);
+
+        // data box without type and locale
+        assert_eq!(mp4_cover_of(&m4a(&bmff_box(b"data", b"\0\0\0")), MAX_LEN).unwrap(), None);
+    }
+
+    #[test]
+    fn prefers_the_front_cover() {
+        let dir = std::env::temp_dir().join(format!("thumbnailed-audio-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("song.mp3");
+
+        let frames = [(8, b"artist"), (3, b"front "), (4, b"back  ")]
+            .map(|(picture_type, data)| id3_frame(3, b"APIC", 0, &apic(picture_type, data)));
+        fs::write(&path, id3_tag(3, &frames)).unwrap();
+        assert_eq!(read_embedded_picture(&path, MAX_LEN).unwrap(), Some(b"front ".to_vec()));
+
+        // the first picture without a front cover
+        fs::write(&path, id3_tag(3, &[frames[0].clone(), frames[2].clone()])).unwrap();
+        assert_eq!(read_embedded_picture(&path, MAX_LEN).unwrap(), Some(b"artist".to_vec()));
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
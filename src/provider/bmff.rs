@@ -0,0 +1,67 @@
+use std::io::{ self, Read, Seek, SeekFrom };
+
+/// iso-bmff box (offset and length of its content, without the header)
+pub(crate) struct BmffBox {
+    pub kind: [u8; 4],
+    pub offset: u64,
+    pub len: u64,
+}
+
+impl BmffBox {
+    pub fn end(&self) -> u64 {
+        self.offset + self.len
+    }
+}
+
+/// all boxes between `start` and `end`
+pub(crate) fn read_boxes<R>(reader: &mut R, start: u64, end: u64) -> io::Result<Vec<BmffBox>>
+    where R: Read + Seek
+{
+    let mut boxes = Vec::new();
+    let mut offset = start;
+
+    while offset + 8 <= end {
+        let mut header = [0u8; 8];
+        reader.seek(SeekFrom::Start(offset))?;
+        reader.read_exact(&mut header)?;
+
+        let mut size = u64::from(u32::from_be_bytes([header[0], header[1], header[2], header[3]]));
+        let mut header_len = 8;
+        match size {
+            // extends to the end
+            0 => {
+                size = end - offset;
+            }
+            // 64 bit size follows the type
+            1 => {
+                let mut large_size = [0u8; 8];
+                reader.read_exact(&mut large_size)?;
+                size = u64::from_be_bytes(large_size);
+                header_len = 16;
+            }
+            _ => (),
+        }
+        if size < header_len || size > end - offset {
+            break;
+        }
+
+        boxes.push(BmffBox {
+            kind: [header[4], header[5], header[6], header[7]],
+            offset: offset + header_len,
+            len: size - header_len,
+        });
+        offset += size;
+    }
+
+    Ok(boxes)
+}
+
+pub(crate) fn find_box<R>(reader: &mut R, parent: &BmffBox, kind: &[u8; 4]) -> io::Result<Option<BmffBox>>
+    where R: Read + Seek
+{
+    Ok(
+        read_boxes(reader, parent.offset, parent.end())?
+            .into_iter()
+            .find(|child| &child.kind == kind)
+    )
+}
@@ -0,0 +1,388 @@
+use std::{ fs, io::Read, path::Path };
+
+use ab_glyph::{ Font, FontRef, PxScale, ScaleFont };
+
+use crate::*;
+use crate::provider::{ has_extension, ProvidedThumbnail, ThumbnailProvider, ThumbnailRequest };
+use crate::provider::text::{ draw_line, line_width };
+
+const FONT_EXTENSIONS: &[&str] = &["ttf", "otf", "ttc", "otc", "woff"];
+
+const SPECIMEN: &str = "Aa Bb 123";
+
+const BACKGROUND: [u8; 3] = [255, 255, 255];
+const FOREGROUND: [u8; 3] = [20, 20, 20];
+const CAPTION: [u8; 3] = [110, 110, 110];
+
+/// specimens of font files ("Aa Bb 123" and the family name, both set in the font itself)
+///
+/// woff2 is not supported (it needs brotli and the glyf / loca transforms)
+pub struct FontProvider;
+
+impl ThumbnailProvider for FontProvider {
+    fn name(&self) -> &'static str {
+        "font"
+    }
+
+    fn can_handle(&self, path: &Path, header: &[u8]) -> bool {
+        let is_font = [
+            &[0x00, 0x01, 0x00, 0x00][..],
+            b"OTTO",
+            b"true",
+            b"ttcf",
+            b"wOFF",
+        ].iter().any(|magic| header.starts_with(magic));
+
+        has_extension(path, FONT_EXTENSIONS) || is_font
+    }
+
+    fn provide(
+        &self,
+        path: &Path,
+        request: &ThumbnailRequest
+    ) -> Result<ProvidedThumbnail, Box<dyn Error>> {
+        request.decode_limits.check_file_size(fs::metadata(path)?.len())?;
+
+        let mut data = fs::read(path)?;
+        if data.starts_with(b"wOFF") {
+            data = woff_to_sfnt(&data, request.decode_limits.max_alloc.unwrap_or(u64::MAX))?;
+        }
+
+        let face = ttf_parser::Face::parse(&data, 0)?;
+        let family = font_name(
+            &face,
+            &[ttf_parser::name_id::TYPOGRAPHIC_FAMILY, ttf_parser::name_id::FAMILY]
+        );
+        let style = font_name(
+            &face,
+            &[ttf_parser::name_id::TYPOGRAPHIC_SUBFAMILY, ttf_parser::name_id::SUBFAMILY]
+        );
+
+        let font = FontRef::try_from_slice(&data)?;
+        let caption = family.clone().unwrap_or_else(|| {
+            path.file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        });
+
+        let mut provided = ProvidedThumbnail::new(
+            render_specimen(&font, &caption, request.max_x, request.max_y)
+        );
+        provided.metadata.font_family = family;
+        provided.metadata.font_style = style;
+
+        Ok(provided)
+    }
+}
+
+/// first of the name ids, that is set (and decodable)
+fn font_name(face: &ttf_parser::Face, name_ids: &[u16]) -> Option<String> {
+    name_ids.iter().find_map(|name_id| {
+        face.names()
+            .into_iter()
+            .filter(|name| name.name_id == *name_id)
+            .find_map(|name| name.to_string())
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty())
+    })
+}
+
+/// the specimen is set as large as possible with the (smaller) caption below it
+fn render_specimen<F>(font: &F, caption: &str, max_x: u32, max_y: u32) -> image::RgbaImage where F: Font {
+    let mut image = image::RgbaImage::from_pixel(
+        max_x,
+        max_y,
+        image::Rgba([BACKGROUND[0], BACKGROUND[1], BACKGROUND[2], 255])
+    );
+
+    let padding = ((max_x.min(max_y) as f32) * 0.06).max(1.0);
+    let available_x = (max_x as f32) - 2.0 * padding;
+    let available_y = (max_y as f32) - 2.0 * padding;
+    if available_x < 1.0 || available_y < 1.0 {
+        return image;
+    }
+
+    // largest size (at most 45% of the height), at which the line fits into the width
+    let fitting_scale = |line: &str, max_height: f32| {
+        let width = line_width(font, PxScale::from(max_height), line);
+        match width > available_x {
+            true => PxScale::from((max_height * available_x) / width),
+            false => PxScale::from(max_height),
+        }
+    };
+
+    let specimen_scale = fitting_scale(SPECIMEN, available_y * 0.45);
+    let specimen = font.as_scaled(specimen_scale);
+    let caption_scale = fitting_scale(caption, available_y * 0.16);
+    let caption_font = font.as_scaled(caption_scale);
+
+    // both lines are centered vertically as one block
+    let block_height =
+        specimen.ascent() - specimen.descent() + caption_font.ascent() - caption_font.descent();
+    let specimen_baseline = padding + (available_y - block_height).max(0.0) / 2.0 + specimen.ascent();
+    let caption_baseline = specimen_baseline - specimen.descent() + caption_font.ascent();
+
+    draw_line(
+        &mut image,
+        font,
+        specimen_scale,
+        padding + (available_x - line_width(font, specimen_scale, SPECIMEN)) / 2.0,
+        specimen_baseline,
+        SPECIMEN,
+        FOREGROUND
+    );
+    draw_line(
+        &mut image,
+        font,
+        caption_scale,
+        padding + (available_x - line_width(font, caption_scale, caption)) / 2.0,
+        caption_baseline,
+        caption,
+        CAPTION
+    );
+
+    image
+}
+
+fn be_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(offset..offset + 2)?.try_into().ok()?))
+}
+
+fn be_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+/// rebuilds the sfnt (ttf / otf) from a woff 1.0 file (tables are zlib compressed, if they got smaller)
+fn woff_to_sfnt(woff: &[u8], max_len: u64) -> Result<Vec<u8>, Box<dyn Error>> {
+    const TRUNCATED: &str = "truncated woff file";
+
+    let flavor = be_u32(woff, 4).ok_or(TRUNCATED)?;
+    let table_count = be_u16(woff, 12).ok_or(TRUNCATED)?;
+    let sfnt_len = be_u32(woff, 16).ok_or(TRUNCATED)?;
+    if u64::from(sfnt_len) > max_len {
+        return Err(
+            LimitExceeded::Allocation { required: u64::from(sfnt_len), max: max_len }.into()
+        );
+    }
+
+    // the tables have to fit into the (checked) sfnt size, otherwise every table could be
+    // inflated up to its own declared length
+    let mut total_len = 12 + 16 * u64::from(table_count);
+
+    // the table directory has to be there, before anything is allocated for it
+    if woff.len() < 44 + 20 * usize::from(table_count) {
+        return Err(TRUNCATED.into());
+    }
+
+    // (tag, checksum, table data)
+    let mut tables = Vec::with_capacity(usize::from(table_count));
+    for i in 0..usize::from(table_count) {
+        let entry = 44 + i * 20;
+        let tag = woff.get(entry..entry + 4).ok_or(TRUNCATED)?;
+        let offset = be_u32(woff, entry + 4).ok_or(TRUNCATED)? as usize;
+        let compressed_len = be_u32(woff, entry + 8).ok_or(TRUNCATED)? as usize;
+        let original_len = be_u32(woff, entry + 12).ok_or(TRUNCATED)?;
+        let checksum = be_u32(woff, entry + 16).ok_or(TRUNCATED)?;
+
+        // tables are 4 byte aligned
+        total_len += (u64::from(original_len) + 3) & !3;
+        if total_len > u64::from(sfnt_len) {
+            return Err("woff tables are larger than the declared font size".into());
+        }
+
+        let stored = woff.get(offset..offset.saturating_add(compressed_len)).ok_or(TRUNCATED)?;
+        let table = match compressed_len < (original_len as usize) {
+            true => {
+                let mut table = Vec::new();
+                flate2::read::ZlibDecoder
+                    ::new(stored)
+                    .take(u64::from(original_len))
+                    .read_to_end(&mut table)?;
+                table
+            }
+            false => stored.to_vec(),
+        };
+        tables.push((tag, checksum, table));
+    }
+
+    // offset table with the binary search hints of the sfnt header
+    let entry_selector = (table_count.max(1) as f32).log2().floor() as u16;
+    let search_range = (1u32 << entry_selector) * 16;
+    let range_shift = (u32::from(table_count) * 16).saturating_sub(search_range);
+
+    // sized by the decoded tables, the declared sfnt size is only an upper bound
+    let len = 12 + tables.iter().map(|(_, _, table)| 16 + ((table.len() + 3) & !3)).sum::<usize>();
+    let mut sfnt = Vec::with_capacity(len);
+    sfnt.extend(flavor.to_be_bytes());
+    sfnt.extend(table_count.to_be_bytes());
+    sfnt.extend((search_range as u16).to_be_bytes());
+    sfnt.extend(entry_selector.to_be_bytes());
+    sfnt.extend((range_shift as u16).to_be_bytes());
+
+    let mut data_offset = 12 + tables.len() * 16;
+    for (tag, checksum, table) in &tables {
+        sfnt.extend_from_slice(tag);
+        sfnt.extend(checksum.to_be_bytes());
+        sfnt.extend((data_offset as u32).to_be_bytes());
+        sfnt.extend((table.len() as u32).to_be_bytes());
+        // tables are 4 byte aligned
+        data_offset += (table.len() + 3) & !3;
+    }
+    for (_, _, table) in &tables {
+        sfnt.extend_from_slice(table);
+        sfnt.resize((sfnt.len() + 3) & !3, 0);
+    }
+
+    Ok(sfnt)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    /// smallest head, hhea and maxp tables ttf-parser accepts + a name table with
+    /// (name id, name) records for windows (utf-16)
+    fn tables(names: &[(u16, &str)]) -> Vec<([u8; 4], Vec<u8>)> {
+        let mut head = vec![0; 54];
+        head[..4].copy_from_slice(&0x0001_0000u32.to_be_bytes());
+        head[12..16].copy_from_slice(&0x5f0f_3cf5u32.to_be_bytes());
+        // units per em
+        head[18..20].copy_from_slice(&1000u16.to_be_bytes());
+        let mut maxp = 0x0000_5000u32.to_be_bytes().to_vec();
+        // number of glyphs
+        maxp.extend(1u16.to_be_bytes());
+
+        let mut records = Vec::new();
+        let mut strings = Vec::new();
+        for (name_id, name) in names {
+            let utf16: Vec<u8> = name.encode_utf16().flat_map(u16::to_be_bytes).collect();
+            // platform (windows), encoding (unicode bmp), language (en-US)
+            for value in [3, 1, 0x0409, *name_id, utf16.len() as u16, strings.len() as u16] {
+                records.extend(value.to_be_bytes());
+            }
+            strings.extend(utf16);
+        }
+        let mut name = Vec::new();
+        for value in [0, names.len() as u16, 6 + records.len() as u16] {
+            name.extend(value.to_be_bytes());
+        }
+        name.extend(records);
+        name.extend(strings);
+
+        vec![(*b"head", head), (*b"hhea", vec![0; 36]), (*b"maxp", maxp), (*b"name", name)]
+    }
+
+    /// woff 1.0 file of the tables, `compress` zlib compresses them (if they get smaller)
+    fn woff(tables: &[([u8; 4], Vec<u8>)], compress: bool, sfnt_len: u32) -> Vec<u8> {
+        let mut directory = Vec::new();
+        let mut data = Vec::new();
+        let data_start = 44 + 20 * tables.len();
+        for (tag, table) in tables {
+            let stored = match compress {
+                true => {
+                    let mut encoder = flate2::write::ZlibEncoder::new(
+                        Vec::new(),
+                        flate2::Compression::best()
+                    );
+                    encoder.write_all(table).unwrap();
+                    let compressed = encoder.finish().unwrap();
+                    match compressed.len() < table.len() {
+                        true => compressed,
+                        false => table.clone(),
+                    }
+                }
+                false => table.clone(),
+            };
+            directory.extend(tag);
+            directory.extend(((data_start + data.len()) as u32).to_be_bytes());
+            directory.extend((stored.len() as u32).to_be_bytes());
+            directory.extend((table.len() as u32).to_be_bytes());
+            directory.extend(0u32.to_be_bytes());
+            data.extend(stored);
+            data.resize((data.len() + 3) & !3, 0);
+        }
+
+        let mut woff = b"wOFF".to_vec();
+        woff.extend(0x0001_0000u32.to_be_bytes());
+        woff.extend(((data_start + data.len()) as u32).to_be_bytes());
+        woff.extend((tables.len() as u16).to_be_bytes());
+        woff.extend([0; 2]);
+        woff.extend(sfnt_len.to_be_bytes());
+        woff.resize(44, 0);
+        woff.extend(directory);
+        woff.extend(data);
+        woff
+    }
+
+    fn sfnt_len(tables: &[([u8; 4], Vec<u8>)]) -> u32 {
+        let tables_len: usize = tables.iter().map(|(_, table)| 16 + ((table.len() + 3) & !3)).sum();
+        (12 + tables_len) as u32
+    }
+
+    #[test]
+    fn converts_woff_to_sfnt() {
+        let tables = tables(&[(1, "Family"), (2, "Bold")]);
+
+        for compress in [false, true] {
+            let sfnt = woff_to_sfnt(&woff(&tables, compress, sfnt_len(&tables)), u64::MAX).unwrap();
+
+            assert_eq!(sfnt.len() as u32, sfnt_len(&tables));
+            let face = ttf_parser::Face::parse(&sfnt, 0).unwrap();
+            for (tag, table) in &tables {
+                let tag = ttf_parser::Tag::from_bytes(tag);
+                assert_eq!(face.raw_face().table(tag), Some(&table[..]));
+            }
+            assert_eq!(font_name(&face, &[16, 1]).as_deref(), Some("Family"));
+            assert_eq!(font_name(&face, &[17, 2]).as_deref(), Some("Bold"));
+        }
+    }
+
+    #[test]
+    fn prefers_the_first_set_name() {
+        let tables = tables(&[(1, "Family"), (16, " Typographic "), (17, "  ")]);
+        let sfnt = woff_to_sfnt(&woff(&tables, false, sfnt_len(&tables)), u64::MAX).unwrap();
+        let face = ttf_parser::Face::parse(&sfnt, 0).unwrap();
+
+        assert_eq!(font_name(&face, &[16, 1]).as_deref(), Some("Typographic"));
+        // blank names are skipped
+        assert_eq!(font_name(&face, &[17, 2]), None);
+    }
+
+    #[test]
+    fn rejects_truncated_woff_files() {
+        let tables = tables(&[(1, "Family")]);
+        let woff = woff(&tables, true, sfnt_len(&tables));
+
+        // inside the header, the table directory and the table data
+        for len in [10, 44 + 20, woff.len() - 8] {
+            assert!(woff_to_sfnt(&woff[..len], u64::MAX).is_err(), "accepted {len} bytes");
+        }
+    }
+
+    #[test]
+    fn rejects_tables_larger_than_the_sfnt() {
+        let tables = tables(&[(1, "Family")]);
+        let woff = woff(&tables, false, sfnt_len(&tables) - 4);
+
+        let err = woff_to_sfnt(&woff, u64::MAX).unwrap_err();
+        assert!(err.to_string().contains("larger than the declared font size"), "{err}");
+    }
+
+    #[test]
+    fn limits_zlib_bombs() {
+        let bomb = vec![(*b"bomb", vec![0; 16 * 1024 * 1024])];
+        let mut woff = woff(&bomb, true, sfnt_len(&bomb));
+        assert!(woff.len() < 64 * 1024);
+
+        let err = woff_to_sfnt(&woff, 1024 * 1024).unwrap_err();
+        assert!(err.is::<LimitExceeded>(), "{err}");
+
+        // the declared length is a lie, only that much is inflated
+        woff[44 + 12..44 + 16].copy_from_slice(&(64 * 1024u32).to_be_bytes());
+        woff[16..20].copy_from_slice(&(12 + 16 + 64 * 1024u32).to_be_bytes());
+        let sfnt = woff_to_sfnt(&woff, 1024 * 1024).unwrap();
+        assert_eq!(sfnt.len(), 12 + 16 + 64 * 1024);
+    }
+}
@@ -0,0 +1,131 @@
+use std::{ fs, path::Path };
+
+use libheif_rs::{ ColorSpace, DecodingOptions, HeifContext, ImageHandle, ItemId, LibHeif, RgbChroma };
+
+use crate::*;
+use crate::provider::{ has_extension, ProvidedThumbnail, ThumbnailProvider, ThumbnailRequest };
+use crate::thumbnailer::resample;
+
+const HEIF_EXTENSIONS: &[&str] = &["heic", "heics", "heif", "heifs", "hif", "avif", "avifs"];
+
+/// iso-bmff brands of heif / avif images
+const HEIF_BRANDS: &[&[u8; 4]] = &[
+    b"heic", b"heix", b"heim", b"heis", b"hevc", b"hevx", b"mif1", b"msf1", b"avif", b"avis",
+];
+
+/// heif / heic and avif images, decoded with libheif (feature "heif")
+///
+/// grids, rotation and mirroring are resolved by libheif, so the exif orientation is not applied again
+pub struct HeifProvider;
+
+impl HeifProvider {
+    /// the smallest embedded thumbnail, that still covers max_x or max_y
+    fn embedded_thumbnail(handle: &ImageHandle, max_x: u32, max_y: u32) -> Option<ImageHandle> {
+        let mut ids: Vec<ItemId> = vec![0; handle.number_of_thumbnails()];
+        let count = handle.thumbnail_ids(&mut ids);
+
+        ids.into_iter()
+            .take(count)
+            .filter_map(|id| handle.thumbnail(id).ok())
+            .filter(|thumbnail| thumbnail.width() >= max_x || thumbnail.height() >= max_y)
+            .min_by_key(|thumbnail| u64::from(thumbnail.width()) * u64::from(thumbnail.height()))
+    }
+
+    /// reads the exif and xmp items of the image (the dimensions stay 0)
+    fn metadata(handle: &ImageHandle) -> ImageMetadata {
+        let items = handle.all_metadata();
+
+        let exif = items
+            .iter()
+            .find(|item| item.item_type.0 == *b"Exif")
+            .and_then(|item| {
+                // starts with the offset to the tiff header
+                let offset = u32::from_be_bytes(item.raw_data.get(..4)?.try_into().ok()?);
+                item.raw_data.get(usize::try_from(offset).ok()?.checked_add(4)?..)
+            });
+        let xmp = items
+            .iter()
+            .find(|item| {
+                item.item_type.0 == *b"mime" && item.content_type == "application/rdf+xml"
+            })
+            .map(|item| item.raw_data.as_slice());
+
+        ImageMetadata::from_raw(exif, xmp)
+    }
+}
+
+impl ThumbnailProvider for HeifProvider {
+    fn name(&self) -> &'static str {
+        "heif"
+    }
+
+    fn can_handle(&self, path: &Path, header: &[u8]) -> bool {
+        let is_heif =
+            header.get(4..8) == Some(b"ftyp") &&
+            header.get(8..12).is_some_and(|brand| HEIF_BRANDS.iter().any(|heif| &heif[..] == brand));
+
+        has_extension(path, HEIF_EXTENSIONS) || is_heif
+    }
+
+    fn provide(
+        &self,
+        path: &Path,
+        request: &ThumbnailRequest
+    ) -> Result<ProvidedThumbnail, Box<dyn Error>> {
+        request.decode_limits.check_file_size(fs::metadata(path)?.len())?;
+
+        let data = fs::read(path)?;
+        let lib_heif = LibHeif::new();
+        let context = HeifContext::read_from_bytes(&data)?;
+        let primary = context.primary_image_handle()?;
+        let metadata = Self::metadata(&primary);
+
+        // dimensions after rotation (and of the whole grid)
+        let (width, height) = (primary.width(), primary.height());
+        request.decode_limits.check_dimensions(width, height)?;
+        // as stored (the metadata dimensions are always without rotation)
+        let stored_width = u32::try_from(primary.ispe_width()).unwrap_or(width);
+        let stored_height = u32::try_from(primary.ispe_height()).unwrap_or(height);
+
+        let handle = match Self::embedded_thumbnail(&primary, request.max_x, request.max_y) {
+            Some(thumbnail) => thumbnail,
+            None => primary,
+        };
+
+        let mut options = DecodingOptions::new().ok_or("failed to allocate heif decoding options")?;
+        // 10 / 12 bit images
+        options.set_convert_hdr_to_8bit(true);
+        let decoded = lib_heif.decode(&handle, ColorSpace::Rgb(RgbChroma::Rgba), Some(options))?;
+
+        let plane = decoded.planes().interleaved.ok_or("decoded heif image has no interleaved plane")?;
+        let row_len = (plane.width as usize) * 4;
+        let pixels: Vec<u8> = plane.data
+            .chunks(plane.stride)
+            .take(plane.height as usize)
+            .flat_map(|row| &row[..row_len])
+            .copied()
+            .collect();
+        let dyn_image = image::RgbaImage
+            ::from_raw(plane.width, plane.height, pixels)
+            .map(image::DynamicImage::ImageRgba8)
+            .ok_or("decoded heif image doesn't match its dimensions")?;
+
+        let (image, quality) = resample(
+            dyn_image,
+            (stored_width, stored_height),
+            request.max_x,
+            request.max_y,
+            request.scaling
+        );
+        let mut provided = ProvidedThumbnail::new(image);
+        provided.metadata = ImageMetadata {
+            width: stored_width,
+            height: stored_height,
+            ..metadata
+        };
+        provided.perceptual_hash = Some(dhash(&provided.image));
+        provided.quality = Some(quality);
+
+        Ok(provided)
+    }
+}
@@ -0,0 +1,59 @@
+use std::path::Path;
+
+use crate::*;
+use crate::provider::{ ProvidedThumbnail, ThumbnailProvider, ThumbnailRequest };
+use crate::thumbnailer::{ generate_thumbnail_from_image, read_animation };
+
+/// everything the `image` crate can decode
+pub struct ImageProvider;
+
+impl ThumbnailProvider for ImageProvider {
+    fn name(&self) -> &'static str {
+        "image"
+    }
+
+    fn can_handle(&self, path: &Path, header: &[u8]) -> bool {
+        image::ImageFormat::from_path(path).is_ok() || image::guess_format(header).is_ok()
+    }
+
+    fn provide(
+        &self,
+        path: &Path,
+        request: &ThumbnailRequest
+    ) -> Result<ProvidedThumbnail, Box<dyn Error>> {
+        let thumbnail = generate_thumbnail_from_image(
+            path.to_path_buf(),
+            request.max_x,
+            request.max_y,
+            request.apply_orientation,
+            request.convert_to_srgb,
+            request.scaling,
+            &request.decode_limits
+        )?;
+
+        let animation = match
+            read_animation(
+                path,
+                request.max_x,
+                request.max_y,
+                request.animated_previews,
+                &request.decode_limits
+            )
+        {
+            Ok(animation) => animation,
+            Err(err) if err.is::<LimitExceeded>() => {
+                return Err(err);
+            }
+            Err(err) => {
+                log::trace!("failed to read animation frames of {} ({err})", path.display());
+                None
+            }
+        };
+
+        Ok(ProvidedThumbnail {
+            animation,
+            perceptual_hash: Some(dhash(&thumbnail.image)),
+            ..thumbnail
+        })
+    }
+}
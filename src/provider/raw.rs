@@ -0,0 +1,690 @@
+use std::{
+    collections::VecDeque,
+    fs,
+    io::{ self, BufReader, Cursor, Read, Seek, SeekFrom },
+    path::Path,
+};
+
+use crate::*;
+use crate::provider::{ has_extension, ProvidedThumbnail, ThumbnailProvider, ThumbnailRequest };
+use crate::provider::bmff::{ find_box, read_boxes, BmffBox };
+use crate::thumbnailer::{ apply_exif_orientation, decode_jpeg_scaled_from_reader, resample };
+
+const RAW_EXTENSIONS: &[&str] = &["cr2", "cr3", "nef", "nrw", "arw", "srf", "sr2", "dng", "pef"];
+
+/// uuid box in the moov box of cr3 files, that holds the tiff structures CMT1..CMT4
+const CANON_UUID: [u8; 16] = [
+    0x85, 0xc0, 0xb6, 0x87, 0x82, 0x0f, 0x11, 0xe0, 0x81, 0x11, 0xf4, 0xce, 0x46, 0x2b, 0x6a, 0x48,
+];
+/// top level uuid box of cr3 files, that holds the PRVW box
+const PREVIEW_UUID: [u8; 16] = [
+    0xea, 0xf4, 0x2b, 0x5e, 0x1c, 0x98, 0x4b, 0x88, 0xb9, 0xfb, 0xb7, 0xdc, 0x40, 0x6e, 0x4d, 0x16,
+];
+
+const TAG_COMPRESSION: u16 = 0x0103;
+const TAG_STRIP_OFFSETS: u16 = 0x0111;
+const TAG_ORIENTATION: u16 = 0x0112;
+const TAG_STRIP_BYTE_COUNTS: u16 = 0x0117;
+const TAG_SUB_IFDS: u16 = 0x014a;
+const TAG_JPEG_OFFSET: u16 = 0x0201;
+const TAG_JPEG_LENGTH: u16 = 0x0202;
+
+/// tiff compression values of (old style and new style) jpeg
+const JPEG_COMPRESSIONS: &[u32] = &[6, 7];
+
+/// no more ifds are followed (protects against loops in broken files)
+const MAX_IFDS: usize = 64;
+/// arrays with more values are skipped (e.g. the strip offsets of the raw data itself)
+const MAX_IFD_VALUES: u32 = 64;
+/// larger CMT1 boxes are ignored
+const MAX_CMT1_LEN: u64 = 1024 * 1024;
+
+/// byte range of an embedded jpeg (relative to the start of the file)
+#[derive(Debug, Clone, Copy)]
+struct EmbeddedJpeg {
+    offset: u64,
+    len: u64,
+}
+
+/// everything that was found in the structure of a raw file
+#[derive(Default)]
+struct RawStructure {
+    previews: Vec<EmbeddedJpeg>,
+    orientation: Option<u32>,
+    /// only set for cr3 files (the exif data of tiff based raws is read by `ImageMetadata::from_path`)
+    exif: Option<exif::Exif>,
+}
+
+/// previews of camera raw files, using the largest jpeg that the camera embedded
+///
+/// the raw data itself is never decoded
+pub struct RawProvider;
+
+impl ThumbnailProvider for RawProvider {
+    fn name(&self) -> &'static str {
+        "raw"
+    }
+
+    fn can_handle(&self, path: &Path, header: &[u8]) -> bool {
+        let is_cr2 = header.starts_with(b"II*\0") && header.get(8..10) == Some(b"CR");
+
+        has_extension(path, RAW_EXTENSIONS) || is_cr2 || is_cr3(header)
+    }
+
+    fn provide(
+        &self,
+        path: &Path,
+        request: &ThumbnailRequest
+    ) -> Result<ProvidedThumbnail, Box<dyn Error>> {
+        // only the previews are read, so the (often large) file size doesn't matter here
+        let file = fs::File::open(path)?;
+        let file_len = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+
+        let mut header = [0u8; 12];
+        reader.read_exact(&mut header)?;
+        let structure = match is_cr3(&header) {
+            true => read_cr3_structure(&mut reader, file_len)?,
+            false => {
+                let mut structure = RawStructure::default();
+                read_tiff_structure(&mut reader, 0, &mut structure)?;
+                structure
+            }
+        };
+
+        let (preview, (width, height)) = structure.previews
+            .iter()
+            .filter_map(|preview| Some((*preview, jpeg_dimensions(&mut reader, *preview)?)))
+            .max_by_key(|(_, (width, height))| u64::from(*width) * u64::from(*height))
+            .ok_or("no embedded jpeg preview found")?;
+        request.decode_limits.check_dimensions(width, height)?;
+
+        let orientation = match request.apply_orientation {
+            true => structure.orientation,
+            false => None,
+        };
+        // orientations 5..=8 swap width and height after decoding
+        let (scaled_x, scaled_y) = match orientation {
+            Some(5..=8) => (request.max_y, request.max_x),
+            _ => (request.max_x, request.max_y),
+        };
+
+        reader.seek(SeekFrom::Start(preview.offset))?;
+        let mut dyn_image = decode_jpeg_scaled_from_reader(
+            reader.take(preview.len),
+            scaled_x,
+            scaled_y
+        )?;
+        if let Some(orientation) = orientation {
+            dyn_image = apply_exif_orientation(dyn_image, orientation);
+        }
+
+        let (image, quality) =
+            resample(dyn_image, (width, height), request.max_x, request.max_y, request.scaling);
+        let mut provided = ProvidedThumbnail::new(image);
+        provided.metadata = match &structure.exif {
+            Some(exif) => ImageMetadata::from_exif(Some(exif), None),
+            None => ImageMetadata::from_path(path),
+        };
+        if provided.metadata.width == 0 || provided.metadata.height == 0 {
+            // the largest preview usually has the size of the developed image
+            provided.metadata.width = width;
+            provided.metadata.height = height;
+        }
+        provided.metadata.orientation = structure.orientation;
+        provided.perceptual_hash = Some(dhash(&provided.image));
+        provided.quality = Some(quality);
+
+        Ok(provided)
+    }
+}
+
+/// iso-bmff with the canon raw brand
+fn is_cr3(header: &[u8]) -> bool {
+    header.get(4..8) == Some(b"ftyp") && header.get(8..12) == Some(b"crx ")
+}
+
+/// (width, height) of the embedded jpeg, None if it is missing, broken or lossless
+fn jpeg_dimensions<R>(reader: &mut R, jpeg: EmbeddedJpeg) -> Option<(u32, u32)> where R: Read + Seek {
+    reader.seek(SeekFrom::Start(jpeg.offset)).ok()?;
+    let mut decoder = jpeg_decoder::Decoder::new(BufReader::new(reader.by_ref().take(jpeg.len)));
+    decoder.read_info().ok()?;
+    let info = decoder.info()?;
+
+    // the raw data of cr2 and dng files is stored as lossless jpeg
+    if info.coding_process == jpeg_decoder::CodingProcess::Lossless {
+        return None;
+    }
+
+    Some((u32::from(info.width), u32::from(info.height)))
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// (tag, values) of one ifd
+type IfdEntries = Vec<(u16, Vec<u32>)>;
+
+/// tiff structure starting at `base` (all offsets inside of it are relative to `base`)
+struct Tiff<'a, R> {
+    reader: &'a mut R,
+    base: u64,
+    little_endian: bool,
+}
+
+impl<'a, R> Tiff<'a, R> where R: Read + Seek {
+    fn new(reader: &'a mut R, base: u64) -> io::Result<Self> {
+        reader.seek(SeekFrom::Start(base))?;
+        let mut byte_order = [0u8; 2];
+        reader.read_exact(&mut byte_order)?;
+
+        let little_endian = match &byte_order {
+            b"II" => true,
+            b"MM" => false,
+            _ => {
+                return Err(invalid_data("not a tiff structure"));
+            }
+        };
+
+        Ok(Self { reader, base, little_endian })
+    }
+
+    fn read_bytes(&mut self, offset: u64, len: u64) -> io::Result<Vec<u8>> {
+        let mut bytes = vec![0u8; len as usize];
+        self.reader.seek(SeekFrom::Start(self.base + offset))?;
+        self.reader.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn u16_from(&self, bytes: &[u8]) -> u16 {
+        let bytes = [bytes[0], bytes[1]];
+        match self.little_endian {
+            true => u16::from_le_bytes(bytes),
+            false => u16::from_be_bytes(bytes),
+        }
+    }
+
+    fn u32_from(&self, bytes: &[u8]) -> u32 {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        match self.little_endian {
+            true => u32::from_le_bytes(bytes),
+            false => u32::from_be_bytes(bytes),
+        }
+    }
+
+    /// the entries with (short / long / ifd) integer values and the offset of the next ifd
+    fn read_ifd(&mut self, offset: u64) -> io::Result<(IfdEntries, u32)> {
+        let entry_count = self.read_bytes(offset, 2).map(|bytes| self.u16_from(&bytes))?;
+        // all entries (12 bytes each) and the offset of the next ifd are read at once
+        let table = self.read_bytes(offset + 2, u64::from(entry_count) * 12 + 4)?;
+        let mut entries = IfdEntries::new();
+
+        for entry in table.chunks_exact(12) {
+            let tag = self.u16_from(&entry[0..2]);
+            let value_size: usize = match self.u16_from(&entry[2..4]) {
+                3 => 2,
+                4 | 13 => 4,
+                _ => {
+                    continue;
+                }
+            };
+            let value_count = self.u32_from(&entry[4..8]);
+            if value_count == 0 || value_count > MAX_IFD_VALUES {
+                continue;
+            }
+
+            // values, that fit into 4 bytes, are stored directly in the entry
+            let values_len = value_size * (value_count as usize);
+            let values_bytes = match values_len {
+                0..=4 => entry[8..12].to_vec(),
+                _ => {
+                    let values_offset = u64::from(self.u32_from(&entry[8..12]));
+                    self.read_bytes(values_offset, values_len as u64)?
+                }
+            };
+            let values = values_bytes
+                .chunks_exact(value_size)
+                .take(value_count as usize)
+                .map(|value| {
+                    match value_size {
+                        2 => u32::from(self.u16_from(value)),
+                        _ => self.u32_from(value),
+                    }
+                })
+                .collect();
+
+            entries.push((tag, values));
+        }
+
+        let next_ifd = self.u32_from(&table[table.len() - 4..]);
+
+        Ok((entries, next_ifd))
+    }
+}
+
+/// collects the jpeg previews of all ifds (ifd chain and sub ifds) and the orientation of ifd0
+///
+/// cr2 stores its large preview as the strip of ifd0, nef and dng in sub ifds and
+/// arw as "JPEGInterchangeFormat" of ifd0
+fn read_tiff_structure<R>(
+    reader: &mut R,
+    base: u64,
+    structure: &mut RawStructure
+) -> io::Result<()>
+    where R: Read + Seek
+{
+    let mut tiff = Tiff::new(reader, base)?;
+    let ifd0 = tiff.read_bytes(4, 4).map(|bytes| tiff.u32_from(&bytes))?;
+
+    let mut queue = VecDeque::from([ifd0]);
+    let mut visited = Vec::new();
+
+    while let Some(offset) = queue.pop_front() {
+        if offset == 0 || visited.contains(&offset) || visited.len() >= MAX_IFDS {
+            continue;
+        }
+        visited.push(offset);
+
+        let (entries, next_ifd) = match tiff.read_ifd(u64::from(offset)) {
+            Ok(ifd) => ifd,
+            Err(err) => {
+                log::trace!("failed to read ifd at {offset} ({err})");
+                continue;
+            }
+        };
+        let get = |tag: u16| {
+            entries
+                .iter()
+                .find(|(entry_tag, _)| *entry_tag == tag)
+                .map(|(_, values)| values.as_slice())
+        };
+
+        if offset == ifd0 {
+            structure.orientation = get(TAG_ORIENTATION).and_then(|values| values.first().copied());
+        }
+
+        if let (Some(&[jpeg_offset]), Some(&[jpeg_len])) = (get(TAG_JPEG_OFFSET), get(TAG_JPEG_LENGTH)) {
+            structure.previews.push(EmbeddedJpeg {
+                offset: base + u64::from(jpeg_offset),
+                len: u64::from(jpeg_len),
+            });
+        }
+
+        let is_jpeg = get(TAG_COMPRESSION).is_some_and(|values|
+            values.first().is_some_and(|compression| JPEG_COMPRESSIONS.contains(compression))
+        );
+        if let (true, Some(&[strip_offset]), Some(&[strip_len])) = (
+            is_jpeg,
+            get(TAG_STRIP_OFFSETS),
+            get(TAG_STRIP_BYTE_COUNTS),
+        ) {
+            structure.previews.push(EmbeddedJpeg {
+                offset: base + u64::from(strip_offset),
+                len: u64::from(strip_len),
+            });
+        }
+
+        queue.extend(get(TAG_SUB_IFDS).unwrap_or_default());
+        queue.push_back(next_ifd);
+    }
+
+    Ok(())
+}
+
+fn read_uuid<R>(reader: &mut R, uuid_box: &BmffBox) -> io::Result<[u8; 16]> where R: Read + Seek {
+    let mut uuid = [0u8; 16];
+    reader.seek(SeekFrom::Start(uuid_box.offset))?;
+    reader.read_exact(&mut uuid)?;
+    Ok(uuid)
+}
+
+fn read_be_u32<R>(reader: &mut R, offset: u64) -> io::Result<u32> where R: Read + Seek {
+    let mut bytes = [0u8; 4];
+    reader.seek(SeekFrom::Start(offset))?;
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+/// the first sample of a track (trak/mdia/minf/stbl)
+fn first_sample<R>(reader: &mut R, trak: &BmffBox) -> io::Result<Option<EmbeddedJpeg>>
+    where R: Read + Seek
+{
+    let mut parent = match find_box(reader, trak, b"mdia")? {
+        Some(mdia) => mdia,
+        None => {
+            return Ok(None);
+        }
+    };
+    for kind in [b"minf", b"stbl"] {
+        parent = match find_box(reader, &parent, kind)? {
+            Some(child) => child,
+            None => {
+                return Ok(None);
+            }
+        };
+    }
+
+    // stsz: version / flags, common sample size (0 if the sizes follow), sample count, sizes
+    let len = match find_box(reader, &parent, b"stsz")? {
+        Some(stsz) =>
+            match read_be_u32(reader, stsz.offset + 4)? {
+                0 => read_be_u32(reader, stsz.offset + 12)?,
+                len => len,
+            }
+        None => {
+            return Ok(None);
+        }
+    };
+
+    // co64 / stco: version / flags, entry count, offsets
+    let offset = if let Some(co64) = find_box(reader, &parent, b"co64")? {
+        let high = read_be_u32(reader, co64.offset + 8)?;
+        let low = read_be_u32(reader, co64.offset + 12)?;
+        (u64::from(high) << 32) | u64::from(low)
+    } else if let Some(stco) = find_box(reader, &parent, b"stco")? {
+        u64::from(read_be_u32(reader, stco.offset + 8)?)
+    } else {
+        return Ok(None);
+    };
+
+    Ok(Some(EmbeddedJpeg { offset, len: u64::from(len) }))
+}
+
+/// cr3 stores a full size jpeg as first track, a small one in the PRVW box
+/// and the orientation / camera info as tiff structure in the CMT1 box
+fn read_cr3_structure<R>(reader: &mut R, file_len: u64) -> io::Result<RawStructure>
+    where R: Read + Seek
+{
+    let mut structure = RawStructure::default();
+
+    for top in read_boxes(reader, 0, file_len)? {
+        match &top.kind {
+            b"moov" => {
+                let mut is_first_trak = true;
+
+                for child in read_boxes(reader, top.offset, top.end())? {
+                    match &child.kind {
+                        b"trak" if is_first_trak => {
+                            is_first_trak = false;
+                            structure.previews.extend(first_sample(reader, &child)?);
+                        }
+                        b"uuid" if read_uuid(reader, &child)? == CANON_UUID => {
+                            let cmt1 = read_boxes(reader, child.offset + 16, child.end())?
+                                .into_iter()
+                                .find(|canon_box| &canon_box.kind == b"CMT1" && canon_box.len <= MAX_CMT1_LEN);
+                            if let Some(cmt1) = cmt1 {
+                                let mut data = vec![0u8; cmt1.len as usize];
+                                reader.seek(SeekFrom::Start(cmt1.offset))?;
+                                reader.read_exact(&mut data)?;
+
+                                let mut ifd0 = RawStructure::default();
+                                read_tiff_structure(&mut Cursor::new(&data), 0, &mut ifd0)?;
+                                structure.orientation = ifd0.orientation;
+                                structure.exif = exif::Reader::new().read_raw(data).ok();
+                            }
+                        }
+                        _ => (),
+                    }
+                }
+            }
+            // the uuid is followed by 8 bytes before the PRVW box
+            b"uuid" if read_uuid(reader, &top)? == PREVIEW_UUID => {
+                let prvw = read_boxes(reader, top.offset + 24, top.end())?
+                    .into_iter()
+                    .find(|preview_box| &preview_box.kind == b"PRVW");
+                if let Some(prvw) = prvw {
+                    // a small header (dimensions, jpeg size) precedes the jpeg
+                    let mut prvw_header = Vec::new();
+                    reader.seek(SeekFrom::Start(prvw.offset))?;
+                    reader.by_ref().take(prvw.len.min(32)).read_to_end(&mut prvw_header)?;
+
+                    let start = prvw_header.windows(3).position(|bytes| bytes == [0xff, 0xd8, 0xff]);
+                    if let Some(start) = start {
+                        structure.previews.push(EmbeddedJpeg {
+                            offset: prvw.offset + start as u64,
+                            len: prvw.len - start as u64,
+                        });
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    Ok(structure)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHORT: u16 = 3;
+    const LONG: u16 = 4;
+
+    /// (tag, field type, value count, value or offset of the values)
+    type Entry = (u16, u16, u32, u32);
+
+    /// start of ifd `i` in the tiffs of `tiff` (every ifd has a slot of 128 bytes)
+    fn ifd_offset(i: usize) -> u32 {
+        8 + 128 * (i as u32)
+    }
+
+    /// little endian tiff with the (entries, offset of the next ifd) of each ifd, followed by `extra`
+    fn tiff(ifds: &[(Vec<Entry>, u32)], extra: &[u8]) -> Vec<u8> {
+        let mut data = Vec::from(b"II*\0".as_slice());
+        data.extend(ifd_offset(0).to_le_bytes());
+
+        for (i, (entries, next_ifd)) in ifds.iter().enumerate() {
+            data.extend((entries.len() as u16).to_le_bytes());
+            for (tag, field_type, count, value) in entries {
+                data.extend(tag.to_le_bytes());
+                data.extend(field_type.to_le_bytes());
+                data.extend(count.to_le_bytes());
+                data.extend(value.to_le_bytes());
+            }
+            data.extend(next_ifd.to_le_bytes());
+            data.resize(ifd_offset(i + 1) as usize, 0);
+        }
+
+        data.extend_from_slice(extra);
+        data
+    }
+
+    fn jpeg_entries(offset: u32, len: u32) -> Vec<Entry> {
+        vec![(TAG_JPEG_OFFSET, LONG, 1, offset), (TAG_JPEG_LENGTH, LONG, 1, len)]
+    }
+
+    fn tiff_structure(data: &[u8], base: u64) -> io::Result<RawStructure> {
+        let mut structure = RawStructure::default();
+        read_tiff_structure(&mut Cursor::new(data), base, &mut structure)?;
+        Ok(structure)
+    }
+
+    fn preview_offsets(structure: &RawStructure) -> Vec<u64> {
+        let mut offsets: Vec<u64> = structure.previews.iter().map(|preview| preview.offset).collect();
+        offsets.sort_unstable();
+        offsets
+    }
+
+    #[test]
+    fn collects_previews_of_the_ifd_chain_and_sub_ifds() {
+        let sub_ifds: Vec<u8> = [ifd_offset(2), ifd_offset(3)]
+            .iter()
+            .flat_map(|offset| offset.to_le_bytes())
+            .collect();
+        let data = tiff(
+            &[
+                // cr2 style: jpeg strip in ifd0
+                (
+                    vec![
+                        (TAG_COMPRESSION, SHORT, 1, 6),
+                        (TAG_STRIP_OFFSETS, LONG, 1, 1000),
+                        (TAG_ORIENTATION, SHORT, 1, 6),
+                        (TAG_STRIP_BYTE_COUNTS, LONG, 1, 500),
+                        (TAG_SUB_IFDS, LONG, 2, ifd_offset(4))
+                    ],
+                    ifd_offset(1),
+                ),
+                // arw style: "JPEGInterchangeFormat", the orientation of other ifds is ignored
+                ([jpeg_entries(2000, 200), vec![(TAG_ORIENTATION, SHORT, 1, 3)]].concat(), 0),
+                // nef / dng style: new style jpeg in a sub ifd
+                (
+                    vec![
+                        (TAG_COMPRESSION, SHORT, 1, 7),
+                        (TAG_STRIP_OFFSETS, LONG, 1, 3000),
+                        (TAG_STRIP_BYTE_COUNTS, LONG, 1, 300)
+                    ],
+                    0,
+                ),
+                // uncompressed strip
+                (
+                    vec![
+                        (TAG_COMPRESSION, SHORT, 1, 1),
+                        (TAG_STRIP_OFFSETS, LONG, 1, 4000),
+                        (TAG_STRIP_BYTE_COUNTS, LONG, 1, 400)
+                    ],
+                    0,
+                ),
+            ],
+            &sub_ifds
+        );
+
+        let structure = tiff_structure(&data, 0).unwrap();
+        assert_eq!(preview_offsets(&structure), vec![1000, 2000, 3000]);
+        assert_eq!(structure.orientation, Some(6));
+        let lens: u64 = structure.previews.iter().map(|preview| preview.len).sum();
+        assert_eq!(lens, 500 + 200 + 300);
+
+        // offsets inside of an embedded tiff are relative to its start
+        let embedded = [vec![0u8; 100], data].concat();
+        let structure = tiff_structure(&embedded, 100).unwrap();
+        assert_eq!(preview_offsets(&structure), vec![1100, 2100, 3100]);
+    }
+
+    #[test]
+    fn follows_looping_ifds_only_once() {
+        let data = tiff(
+            &[
+                (jpeg_entries(1000, 10), ifd_offset(1)),
+                // points back to ifd0 and to itself as sub ifd
+                (
+                    [jpeg_entries(2000, 10), vec![(TAG_SUB_IFDS, LONG, 1, ifd_offset(1))]].concat(),
+                    ifd_offset(0),
+                ),
+            ],
+            &[]
+        );
+
+        assert_eq!(preview_offsets(&tiff_structure(&data, 0).unwrap()), vec![1000, 2000]);
+    }
+
+    #[test]
+    fn stops_after_max_ifds() {
+        let ifds: Vec<(Vec<Entry>, u32)> = (0..MAX_IFDS + 10)
+            .map(|i| (jpeg_entries(100_000 + (i as u32), 10), ifd_offset(i + 1)))
+            .collect();
+
+        let structure = tiff_structure(&tiff(&ifds, &[]), 0).unwrap();
+        assert_eq!(structure.previews.len(), MAX_IFDS);
+    }
+
+    #[test]
+    fn skips_arrays_with_too_many_values() {
+        let data = tiff(
+            &[
+                (
+                    vec![
+                        (TAG_SUB_IFDS, LONG, MAX_IFD_VALUES + 1, ifd_offset(2)),
+                        // a single strip is expected
+                        (TAG_COMPRESSION, SHORT, 1, 6),
+                        (TAG_STRIP_OFFSETS, SHORT, 2, 1000 | (2000 << 16)),
+                        (TAG_STRIP_BYTE_COUNTS, SHORT, 2, 10 | (10 << 16))
+                    ],
+                    0,
+                ),
+                (jpeg_entries(3000, 10), 0),
+            ],
+            // the sub ifd offsets, that would be read, if the array wasn't skipped
+            &ifd_offset(1).to_le_bytes().repeat(MAX_IFD_VALUES as usize + 1)
+        );
+
+        assert!(tiff_structure(&data, 0).unwrap().previews.is_empty());
+    }
+
+    #[test]
+    fn survives_truncated_ifds() {
+        let data = tiff(&[(jpeg_entries(1000, 10), ifd_offset(1))], &[]);
+
+        // the table of the second ifd is missing
+        let mut truncated = data.clone();
+        truncated.extend(20u16.to_le_bytes());
+        assert_eq!(preview_offsets(&tiff_structure(&truncated, 0).unwrap()), vec![1000]);
+
+        // values outside of the file
+        let data = tiff(&[(vec![(TAG_SUB_IFDS, LONG, 4, 1_000_000)], 0)], &[]);
+        assert!(tiff_structure(&data, 0).unwrap().previews.is_empty());
+
+        // the table of ifd0 ends within the entries
+        assert!(tiff_structure(&data[..20], 0).unwrap().previews.is_empty());
+        assert!(tiff_structure(&data[..6], 0).is_err());
+        assert!(tiff_structure(b"GIF89a", 0).is_err());
+    }
+
+    fn bmff_box(kind: &[u8; 4], content: &[u8]) -> Vec<u8> {
+        [&(8 + content.len() as u32).to_be_bytes(), kind.as_slice(), content].concat()
+    }
+
+    /// the first track has one sample of `len` bytes at `offset`
+    fn trak(offset: u32, len: u32) -> Vec<u8> {
+        let stsz = [[0u8; 8].as_slice(), &1u32.to_be_bytes(), &len.to_be_bytes()].concat();
+        let stco = [[0u8; 4].as_slice(), &1u32.to_be_bytes(), &offset.to_be_bytes()].concat();
+        let stbl = [bmff_box(b"stsz", &stsz), bmff_box(b"stco", &stco)].concat();
+        let minf = bmff_box(b"stbl", &stbl);
+        bmff_box(b"trak", &bmff_box(b"mdia", &bmff_box(b"minf", &minf)))
+    }
+
+    fn cr3(prvw: &[u8]) -> Vec<u8> {
+        let cmt1 = tiff(&[(vec![(TAG_ORIENTATION, SHORT, 1, 8)], 0)], &[]);
+        let canon = [CANON_UUID.as_slice(), &bmff_box(b"CMT1", &cmt1)].concat();
+        let moov = [trak(5000, 1234), trak(6000, 10), bmff_box(b"uuid", &canon)].concat();
+        let preview = [PREVIEW_UUID.as_slice(), &[0; 8], &bmff_box(b"PRVW", prvw)].concat();
+
+        [
+            bmff_box(b"ftyp", b"crx \0\0\0\x01"),
+            bmff_box(b"moov", &moov),
+            bmff_box(b"uuid", &preview),
+        ].concat()
+    }
+
+    #[test]
+    fn reads_previews_and_orientation_of_cr3_boxes() {
+        let data = cr3(b"\0\0\0\x01\x01\x60\0\xf0\xff\xd8\xff\xdbjpeg data");
+        assert!(is_cr3(&data));
+
+        let structure = read_cr3_structure(&mut Cursor::new(&data), data.len() as u64).unwrap();
+        // only the first track is the full size jpeg
+        let jpeg_start = data.windows(3).position(|bytes| bytes == [0xff, 0xd8, 0xff]).unwrap();
+        assert_eq!(preview_offsets(&structure), vec![jpeg_start as u64, 5000]);
+        assert_eq!(structure.previews[0].len, 1234);
+        assert_eq!(structure.previews[1].len, 13);
+        assert_eq!(structure.orientation, Some(8));
+    }
+
+    #[test]
+    fn ignores_broken_cr3_boxes() {
+        // no jpeg in the PRVW box
+        let data = cr3(b"no jpeg");
+        let structure = read_cr3_structure(&mut Cursor::new(&data), data.len() as u64).unwrap();
+        assert_eq!(preview_offsets(&structure), vec![5000]);
+
+        // boxes, that are larger than the file, end the walk
+        let mut oversized = bmff_box(b"ftyp", b"crx \0\0\0\x01");
+        oversized.extend(u32::MAX.to_be_bytes());
+        oversized.extend(b"moov");
+        oversized.extend(trak(5000, 1234));
+        let structure =
+            read_cr3_structure(&mut Cursor::new(&oversized), oversized.len() as u64).unwrap();
+        assert!(structure.previews.is_empty());
+    }
+}
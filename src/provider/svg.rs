@@ -0,0 +1,334 @@
+use std::{ fs, io::Read, path::Path };
+
+use resvg::{ tiny_skia, usvg::{ self, TreeParsing } };
+
+use crate::*;
+use crate::provider::{ has_extension, ProvidedThumbnail, ThumbnailProvider, ThumbnailRequest };
+
+/// rasterizes svgs (and gzip compressed svgzs) with resvg
+///
+/// `<text>` elements are not rendered, because no fonts are loaded. `<image>` elements aren't
+/// either, neither referenced files nor embedded data
+pub struct SvgProvider {
+    /// documents with more xml nodes are skipped for safety
+    pub max_nodes: usize,
+    /// documents with deeper nested elements are skipped (usvg converts them recursively)
+    pub max_depth: usize,
+}
+
+impl Default for SvgProvider {
+    fn default() -> Self {
+        Self { max_nodes: 100_000, max_depth: 256 }
+    }
+}
+
+impl SvgProvider {
+    /// parses the (gzip compressed) document, the limits are checked before usvg converts it
+    fn parse(
+        &self,
+        mut data: Vec<u8>,
+        limits: &DecodeLimits
+    ) -> Result<usvg::Tree, Box<dyn Error>> {
+        // svgz, the decompressed document is limited like a file (decompression bombs)
+        if data.starts_with(&[0x1f, 0x8b]) {
+            let max_len = limits.max_file_size.unwrap_or(u64::MAX);
+            let mut decompressed = Vec::new();
+            flate2::read::GzDecoder
+                ::new(data.as_slice())
+                .take(max_len.saturating_add(1))
+                .read_to_end(&mut decompressed)?;
+            limits.check_file_size(decompressed.len() as u64)?;
+            data = decompressed;
+        }
+
+        let parsing_options = roxmltree::ParsingOptions {
+            allow_dtd: true,
+            // one more, so that parsing only fails if there are too many
+            nodes_limit: u32::try_from(self.max_nodes.saturating_add(1)).unwrap_or(u32::MAX),
+        };
+        let document = match
+            roxmltree::Document::parse_with_options(std::str::from_utf8(&data)?, parsing_options)
+        {
+            Ok(document) => document,
+            Err(roxmltree::Error::NodesLimitReached) => {
+                return Err(
+                    LimitExceeded::Complexity {
+                        nodes: self.max_nodes.saturating_add(1),
+                        max_nodes: self.max_nodes,
+                    }.into()
+                );
+            }
+            Err(err) => {
+                return Err(err.into());
+            }
+        };
+
+        // usvg copies the referenced content into every `<use>`, so a few nested references
+        // can expand to billions of elements (and nest them as deep as the chain is long)
+        let (nodes, depth) = expanded_size(&document, self.max_nodes);
+        if nodes > self.max_nodes {
+            return Err(LimitExceeded::Complexity { nodes, max_nodes: self.max_nodes }.into());
+        }
+        if depth > self.max_depth {
+            return Err(LimitExceeded::Nesting { depth, max_depth: self.max_depth }.into());
+        }
+
+        let options = usvg::Options {
+            // no images at all: local files would be read without any limits, resvg is built
+            // without raster image support and nested documents would skip the checks above
+            image_href_resolver: usvg::ImageHrefResolver {
+                resolve_data: Box::new(|_, _, _| None),
+                resolve_string: Box::new(|_, _| None),
+            },
+            ..Default::default()
+        };
+
+        Ok(usvg::Tree::from_xmltree(&document, &options)?)
+    }
+}
+
+impl ThumbnailProvider for SvgProvider {
+    fn name(&self) -> &'static str {
+        "svg"
+    }
+
+    fn can_handle(&self, path: &Path, header: &[u8]) -> bool {
+        let header = String::from_utf8_lossy(header);
+        let header = header.trim_start_matches('\u{feff}').trim_start();
+        let is_svg_document =
+            header.starts_with("<svg") || (header.starts_with("<?xml") && header.contains("<svg"));
+
+        has_extension(path, &["svg", "svgz"]) || is_svg_document
+    }
+
+    fn provide(
+        &self,
+        path: &Path,
+        request: &ThumbnailRequest
+    ) -> Result<ProvidedThumbnail, Box<dyn Error>> {
+        request.decode_limits.check_file_size(fs::metadata(path)?.len())?;
+
+        let tree = self.parse(fs::read(path)?, &request.decode_limits)?;
+
+        let (width, height) = (tree.size.width(), tree.size.height());
+        let scale = f32::min((request.max_x as f32) / width, (request.max_y as f32) / height);
+        let target_x = ((width * scale).round() as u32).clamp(1, request.max_x);
+        let target_y = ((height * scale).round() as u32).clamp(1, request.max_y);
+
+        let mut pixmap = tiny_skia::Pixmap
+            ::new(target_x, target_y)
+            .ok_or("failed to allocate pixmap for svg")?;
+        resvg::Tree
+            ::from_usvg(&tree)
+            .render(tiny_skia::Transform::from_scale(scale, scale), &mut pixmap.as_mut());
+
+        // tiny-skia uses premultiplied alpha
+        let pixels: Vec<u8> = pixmap
+            .pixels()
+            .iter()
+            .flat_map(|pixel| {
+                let color = pixel.demultiply();
+                [color.red(), color.green(), color.blue(), color.alpha()]
+            })
+            .collect();
+        let image = image::RgbaImage
+            ::from_raw(target_x, target_y, pixels)
+            .ok_or("rendered svg doesn't match its dimensions")?;
+
+        let mut provided = ProvidedThumbnail::new(image);
+        provided.metadata.width = width.round() as u32;
+        provided.metadata.height = height.round() as u32;
+
+        Ok(provided)
+    }
+}
+
+/// (elements, nesting depth) of the document after every `<use>` was replaced by the element
+/// it references, the counting stops above `max_nodes`
+///
+/// walks the elements and references iteratively (depth first), so that neither deep documents
+/// nor long reference chains can overflow the stack. cyclic references count as empty,
+/// usvg skips them as well
+fn expanded_size<'a, 'input>(
+    document: &'a roxmltree::Document<'input>,
+    max_nodes: usize
+) -> (usize, usize) {
+    const XLINK_NS: &str = "http://www.w3.org/1999/xlink";
+
+    let elements_by_id: HBHashMap<&str, roxmltree::Node<'a, 'input>> = document
+        .descendants()
+        .filter_map(|node| Some((node.attribute("id")?, node)))
+        .collect();
+    // child elements plus the referenced element of a `<use>`
+    let expands_to = |node: roxmltree::Node<'a, 'input>| {
+        let referenced = match node.tag_name().name() == "use" {
+            true =>
+                node
+                    .attribute((XLINK_NS, "href"))
+                    .or_else(|| node.attribute("href"))
+                    .and_then(|href| href.strip_prefix('#'))
+                    .and_then(|id| elements_by_id.get(id).copied()),
+            false => None,
+        };
+        node.children()
+            .filter(|child| child.is_element())
+            .chain(referenced)
+    };
+
+    // per node id: None = not visited, Some(None) = being expanded, Some(Some((elements, depth)))
+    let mut sizes: Vec<Option<Option<(usize, usize)>>> =
+        vec![None; document.descendants().count()];
+    let index = |node: roxmltree::Node| node.id().get() as usize;
+
+    // (node, whether its children were visited already)
+    let mut stack = vec![(document.root_element(), false)];
+    while let Some((node, visited)) = stack.pop() {
+        match visited {
+            false => {
+                if sizes[index(node)].is_some() {
+                    continue;
+                }
+                sizes[index(node)] = Some(None);
+                stack.push((node, true));
+                stack.extend(
+                    expands_to(node)
+                        .filter(|child| sizes[index(*child)].is_none())
+                        .map(|child| (child, false))
+                );
+            }
+            true => {
+                let (elements, depth) = expands_to(node)
+                    .filter_map(|child| sizes[index(child)].flatten())
+                    .fold((1usize, 0usize), |(elements, depth), (child_elements, child_depth)| {
+                        (elements.saturating_add(child_elements), depth.max(child_depth))
+                    });
+                let elements = elements.min(max_nodes.saturating_add(1));
+                sizes[index(node)] = Some(Some((elements, depth + 1)));
+            }
+        }
+    }
+
+    sizes[index(document.root_element())].flatten().unwrap_or((0, 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SVG_START: &str = concat!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink""#,
+        r#" width="10" height="10">"#
+    );
+
+    fn parse(provider: &SvgProvider, body: &str) -> Result<usvg::Tree, Box<dyn Error>> {
+        provider.parse(format!("{SVG_START}{body}</svg>").into_bytes(), &DecodeLimits::default())
+    }
+
+    fn limit_exceeded(result: Result<usvg::Tree, Box<dyn Error>>) -> LimitExceeded {
+        match result {
+            Ok(_) => panic!("document was accepted"),
+            Err(err) => err.downcast_ref::<LimitExceeded>().expect("not a limit").clone(),
+        }
+    }
+
+    #[test]
+    fn accepts_simple_documents() {
+        let tree = parse(&SvgProvider::default(), r#"<rect width="10" height="10"/>"#).unwrap();
+        assert_eq!(tree.size.width(), 10.0);
+    }
+
+    #[test]
+    fn rejects_too_many_nodes() {
+        let provider = SvgProvider { max_nodes: 100, ..Default::default() };
+
+        assert!(parse(&provider, &"<rect/>".repeat(50)).is_ok());
+        assert_eq!(
+            limit_exceeded(parse(&provider, &"<rect/>".repeat(200))),
+            LimitExceeded::Complexity { nodes: 101, max_nodes: 100 }
+        );
+    }
+
+    #[test]
+    fn rejects_deep_nesting() {
+        let provider = SvgProvider { max_depth: 64, ..Default::default() };
+        let nested = |depth: usize| format!("{}{}", "<g>".repeat(depth), "</g>".repeat(depth));
+
+        assert!(parse(&provider, &nested(32)).is_ok());
+        assert!(matches!(
+            limit_exceeded(parse(&provider, &nested(100))),
+            LimitExceeded::Nesting { .. }
+        ));
+    }
+
+    /// "billion laughs": every level references the previous one ten times
+    #[test]
+    fn rejects_exponential_use_expansion() {
+        let mut body = String::from(r#"<defs><rect id="l0" width="1" height="1"/>"#);
+        for level in 1..=9 {
+            body.push_str(&format!(r#"<g id="l{level}">"#));
+            for _ in 0..10 {
+                body.push_str(&format!(r##"<use xlink:href="#l{}"/>"##, level - 1));
+            }
+            body.push_str("</g>");
+        }
+        body.push_str(r##"</defs><use href="#l9"/>"##);
+
+        // the document itself has only ~100 elements
+        assert!(matches!(
+            limit_exceeded(parse(&SvgProvider::default(), &body)),
+            LimitExceeded::Complexity { .. }
+        ));
+    }
+
+    #[test]
+    fn rejects_deep_use_chains() {
+        let provider = SvgProvider { max_depth: 64, ..Default::default() };
+        let mut body = String::from(r#"<defs><rect id="u0"/>"#);
+        for i in 1..100 {
+            body.push_str(&format!(r##"<use id="u{i}" href="#u{}"/>"##, i - 1));
+        }
+        body.push_str(r##"</defs><use href="#u99"/>"##);
+
+        assert!(matches!(limit_exceeded(parse(&provider, &body)), LimitExceeded::Nesting { .. }));
+    }
+
+    #[test]
+    fn terminates_on_cyclic_use() {
+        let body = r##"<g id="a"><use href="#b"/></g><g id="b"><use href="#a"/></g>"##;
+        let text = format!("{SVG_START}{body}</svg>");
+        let document = roxmltree::Document::parse(&text).unwrap();
+
+        // svg, 2 x (g, use) and one expansion of the other group, the cycle itself counts as empty
+        assert_eq!(expanded_size(&document, 100).0, 7);
+        // usvg rejects the cycle itself
+        assert!(parse(&SvgProvider::default(), body).is_err());
+    }
+
+    #[test]
+    fn ignores_image_references() {
+        let body = r#"<image href="/etc/hostname" width="10" height="10"/>
+            <image href="data:image/svg+xml;utf8,&lt;svg xmlns='http://www.w3.org/2000/svg'/&gt;"
+                width="10" height="10"/>"#;
+        let tree = parse(&SvgProvider::default(), body).unwrap();
+
+        let is_image = |node: usvg::Node| matches!(*node.borrow(), usvg::NodeKind::Image(_));
+        assert!(!tree.root.descendants().any(is_image));
+    }
+
+    #[test]
+    fn limits_decompressed_svgz() {
+        use std::io::Write;
+
+        let document = format!("{SVG_START}{}</svg>", " ".repeat(1024 * 1024));
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+        encoder.write_all(document.as_bytes()).unwrap();
+        let svgz = encoder.finish().unwrap();
+
+        let limits = DecodeLimits { max_file_size: Some(64 * 1024), ..Default::default() };
+        assert!(matches!(
+            limit_exceeded(SvgProvider::default().parse(svgz.clone(), &limits)),
+            LimitExceeded::FileSize { .. }
+        ));
+        assert!(SvgProvider::default().parse(svgz, &DecodeLimits::default()).is_ok());
+    }
+}
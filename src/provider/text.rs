@@ -0,0 +1,315 @@
+use std::{ fs, io::Read, path::Path };
+
+use ab_glyph::{ Font, FontVec, PxScale, ScaleFont };
+
+use crate::*;
+use crate::provider::{ has_extension, ProvidedThumbnail, ThumbnailProvider, ThumbnailRequest };
+
+/// "ts" is left out, it's at least as often an mpeg transport stream (video) as typescript
+const TEXT_EXTENSIONS: &[&str] = &[
+    "txt", "text", "md", "markdown", "rst", "log", "csv", "tsv", "json", "jsonc", "toml", "yaml",
+    "yml", "xml", "ini", "cfg", "conf", "env", "rs", "c", "h", "cc", "cpp", "cxx", "hpp", "cs",
+    "java", "kt", "go", "py", "rb", "php", "js", "mjs", "jsx", "tsx", "html", "htm", "css",
+    "scss", "sh", "bash", "zsh", "ps1", "bat", "cmd", "sql", "lua", "swift", "zig", "hs", "lock",
+];
+
+const BACKGROUND: [u8; 3] = [250, 250, 250];
+const FOREGROUND: [u8; 3] = [30, 30, 30];
+
+/// previews of text and source files (the first lines, rendered with a monospace font)
+///
+/// the output only depends on the file content and the requested size, so it can be snapshot tested
+pub struct TextProvider {
+    /// only the start of the file is read
+    pub max_bytes: u64,
+    pub tab_width: usize,
+    /// lines that fit into the tile (the font size is derived from it)
+    pub lines: usize,
+    font: FontVec,
+}
+
+impl Default for TextProvider {
+    fn default() -> Self {
+        // "Hack", the monospace font that is bundled with egui
+        let font_data = eframe::egui::FontDefinitions::default().font_data.remove("Hack").expect(
+            "egui doesn't bundle the \"Hack\" font anymore"
+        );
+
+        Self {
+            max_bytes: 16 * 1024,
+            tab_width: 4,
+            lines: 16,
+            font: FontVec::try_from_vec_and_index(font_data.font.into_owned(), font_data.index).expect(
+                "bundled font is invalid"
+            ),
+        }
+    }
+}
+
+impl TextProvider {
+    /// detects the encoding (bom, utf-8 or guessed), normalizes line endings and expands tabs
+    pub fn decode_text(&self, data: &[u8], is_complete: bool) -> Result<String, Box<dyn Error>> {
+        let encoding = match encoding_rs::Encoding::for_bom(data) {
+            Some((encoding, _)) => encoding,
+            None => {
+                if data.contains(&0) {
+                    return Err("file looks binary (contains null bytes)".into());
+                }
+                match std::str::from_utf8(data) {
+                    Ok(_) => encoding_rs::UTF_8,
+                    // only cut off in the middle of the last character
+                    Err(err) if err.error_len().is_none() && !is_complete => encoding_rs::UTF_8,
+                    Err(_) => {
+                        let mut detector = chardetng::EncodingDetector::new();
+                        detector.feed(data, is_complete);
+                        detector.guess(None, true)
+                    }
+                }
+            }
+        };
+        let (text, _, _) = encoding.decode(data);
+
+        let mut expanded = String::with_capacity(text.len());
+        for (i, line) in text.replace("\r\n", "\n").replace('\r', "\n").split('\n').enumerate() {
+            if i > 0 {
+                expanded.push('\n');
+            }
+            let mut column = 0;
+            for c in line.chars() {
+                match c {
+                    '\t' => {
+                        let spaces = self.tab_width - (column % self.tab_width.max(1));
+                        expanded.push_str(&" ".repeat(spaces));
+                        column += spaces;
+                    }
+                    c if c.is_control() => {
+                        expanded.push(' ');
+                        column += 1;
+                    }
+                    c => {
+                        expanded.push(c);
+                        column += 1;
+                    }
+                }
+            }
+        }
+
+        Ok(expanded)
+    }
+
+    /// renders the first lines of `text` (lines that are too long are cut off with an ellipsis)
+    pub fn render_text(&self, text: &str, max_x: u32, max_y: u32) -> image::RgbaImage {
+        let mut image = image::RgbaImage::from_pixel(
+            max_x,
+            max_y,
+            image::Rgba([BACKGROUND[0], BACKGROUND[1], BACKGROUND[2], 255])
+        );
+
+        let padding = ((max_x.min(max_y) as f32) * 0.04).max(1.0);
+        let line_height = ((max_y as f32) - 2.0 * padding) / (self.lines.max(1) as f32);
+        if line_height < 2.0 {
+            return image;
+        }
+
+        let font = self.font.as_scaled(PxScale::from(line_height));
+        // monospace, so every glyph has the same advance
+        let advance = font.h_advance(font.glyph_id('M')).max(1.0);
+        let columns = (((max_x as f32) - 2.0 * padding) / advance).floor() as usize;
+
+        for (row, line) in text.lines().take(self.lines).enumerate() {
+            let baseline = padding + (row as f32) * line_height + font.ascent();
+
+            let mut chars: Vec<char> = line.chars().take(columns + 1).collect();
+            if chars.len() > columns {
+                chars.truncate(columns.saturating_sub(1));
+                chars.push('…');
+            }
+
+            let line: String = chars.into_iter().collect();
+            draw_line(&mut image, &self.font, font.scale(), padding, baseline, &line, FOREGROUND);
+        }
+
+        image
+    }
+}
+
+impl ThumbnailProvider for TextProvider {
+    fn name(&self) -> &'static str {
+        "text"
+    }
+
+    fn can_handle(&self, path: &Path, _header: &[u8]) -> bool {
+        has_extension(path, TEXT_EXTENSIONS)
+    }
+
+    fn provide(
+        &self,
+        path: &Path,
+        request: &ThumbnailRequest
+    ) -> Result<ProvidedThumbnail, Box<dyn Error>> {
+        let file = fs::File::open(path)?;
+        let file_len = file.metadata()?.len();
+
+        let mut data = Vec::new();
+        file.take(self.max_bytes).read_to_end(&mut data)?;
+
+        let text = self.decode_text(&data, file_len <= self.max_bytes)?;
+
+        Ok(ProvidedThumbnail::new(self.render_text(&text, request.max_x, request.max_y)))
+    }
+}
+
+/// width of the line in pixels (including kerning)
+pub(crate) fn line_width<F>(font: &F, scale: PxScale, line: &str) -> f32 where F: Font {
+    let font = font.as_scaled(scale);
+    let mut width = 0.0;
+    let mut previous = None;
+
+    for c in line.chars() {
+        let id = font.glyph_id(c);
+        if let Some(previous) = previous {
+            width += font.kern(previous, id);
+        }
+        width += font.h_advance(id);
+        previous = Some(id);
+    }
+
+    width
+}
+
+/// draws one line of text starting at `x` with its baseline at `baseline` (glyphs are clipped to the image)
+pub(crate) fn draw_line<F>(
+    image: &mut image::RgbaImage,
+    font: &F,
+    scale: PxScale,
+    x: f32,
+    baseline: f32,
+    line: &str,
+    color: [u8; 3]
+)
+    where F: Font
+{
+    let scaled = font.as_scaled(scale);
+    let (width, height) = (i64::from(image.width()), i64::from(image.height()));
+    let mut caret = x;
+    let mut previous = None;
+
+    for c in line.chars() {
+        let id = scaled.glyph_id(c);
+        if let Some(previous) = previous {
+            caret += scaled.kern(previous, id);
+        }
+        previous = Some(id);
+
+        let glyph = id.with_scale_and_position(scale, ab_glyph::point(caret, baseline));
+        caret += scaled.h_advance(id);
+
+        let outline = match font.outline_glyph(glyph) {
+            Some(outline) => outline,
+            None => {
+                continue;
+            }
+        };
+
+        let bounds = outline.px_bounds();
+        outline.draw(|glyph_x, glyph_y, coverage| {
+            let x = (bounds.min.x as i64) + i64::from(glyph_x);
+            let y = (bounds.min.y as i64) + i64::from(glyph_y);
+            if x < 0 || y < 0 || x >= width || y >= height {
+                return;
+            }
+
+            let pixel = image.get_pixel_mut(x as u32, y as u32);
+            let coverage = coverage.clamp(0.0, 1.0);
+            for i in 0..3 {
+                let blended = f32::from(pixel[i]) * (1.0 - coverage) + f32::from(color[i]) * coverage;
+                pixel[i] = blended.round() as u8;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_text_with_bom() {
+        let provider = TextProvider::default();
+
+        let utf8 = b"\xef\xbb\xbfcaf\xc3\xa9";
+        assert_eq!(provider.decode_text(utf8, true).unwrap(), "café");
+
+        // utf-16 contains null bytes, but the bom wins over the binary check
+        let mut utf16 = vec![0xff, 0xfe];
+        utf16.extend("café".encode_utf16().flat_map(|unit| unit.to_le_bytes()));
+        assert_eq!(provider.decode_text(&utf16, true).unwrap(), "café");
+    }
+
+    #[test]
+    fn falls_back_to_legacy_encoding() {
+        let provider = TextProvider::default();
+
+        // windows-1252 / latin-1, not valid utf-8
+        let latin1 = b"Le caf\xe9 est tr\xe8s bon, la cr\xe8me br\xfbl\xe9e aussi.";
+        assert_eq!(
+            provider.decode_text(latin1, true).unwrap(),
+            "Le café est très bon, la crème brûlée aussi."
+        );
+    }
+
+    #[test]
+    fn keeps_utf8_cut_off_in_the_middle_of_a_character() {
+        let provider = TextProvider::default();
+
+        // "é" without its second byte, because only the start of the file was read
+        let text = provider.decode_text(b"caf\xc3", false).unwrap();
+        assert!(text.starts_with("caf"));
+        assert!(!text.contains('Ã'));
+    }
+
+    #[test]
+    fn expands_tabs_and_normalizes_line_endings() {
+        let provider = TextProvider { tab_width: 4, ..Default::default() };
+
+        assert_eq!(
+            provider.decode_text(b"a\tb\r\n\tc\rab\td", true).unwrap(),
+            "a   b\n    c\nab  d"
+        );
+        // other control characters become spaces
+        assert_eq!(provider.decode_text(b"a\x1bb", true).unwrap(), "a b");
+    }
+
+    #[test]
+    fn rejects_binary_data() {
+        let provider = TextProvider::default();
+
+        assert!(provider.decode_text(b"\x7fELF\x02\x01\x01\x00\x00", true).is_err());
+    }
+
+    #[test]
+    fn renders_empty_text_as_background() {
+        let image = TextProvider::default().render_text("", 64, 48);
+
+        assert_eq!(image.dimensions(), (64, 48));
+        let background = [BACKGROUND[0], BACKGROUND[1], BACKGROUND[2], 255];
+        assert!(image.pixels().all(|pixel| pixel.0 == background));
+    }
+
+    /// snapshot of the rendering, has to be updated if the font or layout changes on purpose
+    #[test]
+    fn renders_text_snapshot() {
+        let provider = TextProvider::default();
+        let text = provider
+            .decode_text(b"fn main() {\n\tprintln!(\"hello\");\n}\n", true)
+            .unwrap();
+        let image = provider.render_text(&text, 128, 96);
+
+        assert_eq!(image.dimensions(), (128, 96));
+        assert!(image.pixels().any(|pixel| pixel[0] < 128));
+        assert_eq!(
+            blake3::hash(image.as_raw()).to_hex().as_str(),
+            "00cfc5e3397e314eb0fe6640d106269c8a994084c3363cce760f2b7c2c4a2cc9"
+        );
+    }
+}
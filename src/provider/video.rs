@@ -0,0 +1,546 @@
+use std::{
+    ffi::OsString,
+    fmt::Display,
+    io::{ self, Read },
+    path::{ Path, PathBuf },
+    process::{ Command, Stdio },
+    thread,
+    time::{ Duration, Instant },
+};
+
+use crate::*;
+use crate::provider::{ has_extension, ProvidedThumbnail, ThumbnailProvider, ThumbnailRequest };
+
+const VIDEO_EXTENSIONS: &[&str] = &[
+    "mp4", "m4v", "mov", "mkv", "webm", "avi", "wmv", "flv", "mpg", "mpeg", "3gp", "mts", "m2ts",
+];
+
+/// iso-bmff brands of video files (image brands like "heic" / "avif" are left out on purpose)
+const VIDEO_BRANDS: &[&[u8; 4]] = &[
+    b"isom", b"iso2", b"iso4", b"iso5", b"iso6", b"mp41", b"mp42", b"avc1", b"M4V ", b"qt  ",
+    b"3gp4", b"3gp5", b"3gp6", b"3g2a", b"dash",
+];
+
+#[derive(Debug)]
+pub enum VideoError {
+    /// the external command couldn't be found (not installed / not on PATH)
+    ToolMissing(PathBuf),
+    Timeout(Duration),
+    /// exited without producing a frame (contains stderr of the command)
+    NoFrame(String),
+}
+
+impl Display for VideoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VideoError::ToolMissing(command) =>
+                write!(f, "\"{}\" is not installed or not on PATH", command.display()),
+            VideoError::Timeout(timeout) => write!(f, "extracting a frame took longer than {timeout:?}"),
+            VideoError::NoFrame(stderr) =>
+                write!(f, "no frame could be extracted ({})", stderr.lines().last().unwrap_or("")),
+        }
+    }
+}
+
+impl Error for VideoError {}
+
+/// arguments of an ffmpeg run, that writes one png of the frame at `{offset}` to stdout
+pub const FFMPEG_ARGS: &[&str] = &[
+    "-hide_banner", "-nostdin", "-ss", "{offset}", "-i", "{input}", "-frames:v", "1",
+    "-vf", "scale=w={max_x}:h={max_y}:force_original_aspect_ratio=decrease",
+    "-f", "image2pipe", "-c:v", "png", "-",
+];
+
+/// arguments for ffmpegthumbnailer (which doesn't print the duration / resolution)
+pub const FFMPEGTHUMBNAILER_ARGS: &[&str] = &[
+    "-i", "{input}", "-o", "-", "-c", "png", "-t", "{offset}", "-s", "{size}",
+];
+
+/// upper bound for the png of one frame, the rest of stdout is discarded
+const MAX_FRAME_SIZE: u64 = 64 * 1024 * 1024;
+
+/// output of one ffmpeg run
+struct ExtractedFrame {
+    /// encoded png (empty, if ffmpeg didn't output a frame)
+    png: Vec<u8>,
+    duration: Option<Duration>,
+    resolution: Option<(u32, u32)>,
+    stderr: String,
+}
+
+/// thumbnails of videos, created by an external ffmpeg (compatible) command
+pub struct VideoProvider {
+    pub command: PathBuf,
+    /// the command has to write a png to stdout, placeholders: `{input}` (path of the video),
+    /// `{offset}` (seconds), `{max_x}`, `{max_y}` and `{size}` (the larger of both)
+    pub args: Vec<String>,
+    /// position of the representative frame (the first frame is used for shorter videos)
+    pub offset: Duration,
+    /// per run of the command for the representative frame
+    pub timeout: Duration,
+    /// for all storyboard frames together (each frame is a separate run)
+    pub storyboard_timeout: Duration,
+}
+
+impl Default for VideoProvider {
+    fn default() -> Self {
+        Self {
+            command: PathBuf::from("ffmpeg"),
+            args: FFMPEG_ARGS.iter().map(|arg| arg.to_string()).collect(),
+            offset: Duration::from_secs(3),
+            timeout: Duration::from_secs(20),
+            storyboard_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl VideoProvider {
+    pub fn new<P>(command: P) -> Self where P: Into<PathBuf> {
+        Self { command: command.into(), ..Default::default() }
+    }
+
+    pub fn ffmpegthumbnailer() -> Self {
+        Self {
+            args: FFMPEGTHUMBNAILER_ARGS.iter().map(|arg| arg.to_string()).collect(),
+            ..Self::new("ffmpegthumbnailer")
+        }
+    }
+
+    fn extract_frame(
+        &self,
+        path: &Path,
+        offset: Duration,
+        max_x: u32,
+        max_y: u32,
+        timeout: Duration
+    ) -> Result<ExtractedFrame, Box<dyn Error>> {
+        let mut command = Command::new(&self.command);
+        command.args(self.args.iter().map(|arg| expand_arg(arg, path, offset, max_x, max_y)));
+
+        let (png, stderr) = run_with_timeout(command, &self.command, timeout, MAX_FRAME_SIZE)?;
+        let stderr = String::from_utf8_lossy(&stderr).into_owned();
+
+        Ok(ExtractedFrame {
+            png,
+            duration: parse_duration(&stderr),
+            resolution: parse_resolution(&stderr),
+            stderr,
+        })
+    }
+}
+
+impl VideoProvider {
+    /// extracts `request.storyboard_frames` frames from the middle of evenly sized sections
+    ///
+    /// returns no frames if any extraction fails, a storyboard with gaps would be misleading
+    fn storyboard_frames(
+        &self,
+        path: &Path,
+        duration: Duration,
+        request: &ThumbnailRequest
+    ) -> Vec<image::RgbaImage> {
+        let frame_count = request.storyboard_frames;
+        let mut frames = Vec::with_capacity(frame_count as usize);
+        let deadline = Instant::now() + self.storyboard_timeout;
+
+        for i in 0..frame_count {
+            let offset = duration.mul_f64((f64::from(i) + 0.5) / f64::from(frame_count));
+            // every frame gets the time, that is left of the whole storyboard
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if timeout.is_zero() {
+                log::debug!(
+                    "storyboard of {} took longer than {:?}",
+                    path.display(),
+                    self.storyboard_timeout
+                );
+                return Vec::new();
+            }
+            let frame = self
+                .extract_frame(path, offset, request.max_x, request.max_y, timeout)
+                .and_then(|extracted| {
+                    Ok(image::load_from_memory_with_format(&extracted.png, image::ImageFormat::Png)?)
+                });
+
+            match frame {
+                Ok(frame) => frames.push(frame.thumbnail(request.max_x, request.max_y).into_rgba8()),
+                Err(err) => {
+                    log::debug!(
+                        "failed to extract storyboard frame {i} of {} ({err})",
+                        path.display()
+                    );
+                    return Vec::new();
+                }
+            }
+        }
+
+        frames
+    }
+}
+
+impl ThumbnailProvider for VideoProvider {
+    fn name(&self) -> &'static str {
+        "video"
+    }
+
+    fn can_handle(&self, path: &Path, header: &[u8]) -> bool {
+        let is_iso_bmff_video =
+            header.get(4..8) == Some(b"ftyp") &&
+            header.get(8..12).is_some_and(|brand| VIDEO_BRANDS.iter().any(|video| &video[..] == brand));
+        // matroska / webm (ebml)
+        let is_ebml = header.starts_with(&[0x1a, 0x45, 0xdf, 0xa3]);
+        let is_avi = header.starts_with(b"RIFF") && header.get(8..12) == Some(b"AVI ");
+
+        has_extension(path, VIDEO_EXTENSIONS) || is_iso_bmff_video || is_ebml || is_avi
+    }
+
+    fn provide(
+        &self,
+        path: &Path,
+        request: &ThumbnailRequest
+    ) -> Result<ProvidedThumbnail, Box<dyn Error>> {
+        let (max_x, max_y) = (request.max_x, request.max_y);
+        let mut extracted = self.extract_frame(path, self.offset, max_x, max_y, self.timeout)?;
+        if extracted.png.is_empty() && !self.offset.is_zero() {
+            // video is shorter than the offset
+            extracted = self.extract_frame(path, Duration::ZERO, max_x, max_y, self.timeout)?;
+        }
+        if extracted.png.is_empty() {
+            return Err(VideoError::NoFrame(extracted.stderr).into());
+        }
+
+        let frame = image::load_from_memory_with_format(&extracted.png, image::ImageFormat::Png)?;
+        let mut provided = ProvidedThumbnail::new(
+            frame.thumbnail(request.max_x, request.max_y).into_rgba8()
+        );
+
+        if let Some((width, height)) = extracted.resolution {
+            provided.metadata.width = width;
+            provided.metadata.height = height;
+        }
+        provided.metadata.duration = extracted.duration;
+        // ffmpeg scales without sharpening, so the frame can be measured as it is
+        provided.quality = Some(quality_metrics(&provided.image));
+
+        if let Some(duration) = extracted.duration {
+            provided.storyboard_frames = self.storyboard_frames(path, duration, request);
+        }
+
+        Ok(provided)
+    }
+}
+
+/// replaces the placeholders of `VideoProvider::args`
+fn expand_arg(arg: &str, path: &Path, offset: Duration, max_x: u32, max_y: u32) -> OsString {
+    let arg = arg
+        .replace("{offset}", &format!("{:.3}", offset.as_secs_f64()))
+        .replace("{max_x}", &max_x.to_string())
+        .replace("{max_y}", &max_y.to_string())
+        .replace("{size}", &max_x.max(max_y).to_string());
+
+    // the path is inserted as it is, it doesn't have to be valid unicode
+    let mut parts = arg.split("{input}");
+    let mut expanded = OsString::from(parts.next().unwrap_or_default());
+    for part in parts {
+        expanded.push(path);
+        expanded.push(part);
+    }
+    expanded
+}
+
+/// runs the command and returns (stdout, stderr), kills it after `timeout`
+///
+/// only the first `max_output` bytes of each pipe are kept
+pub(crate) fn run_with_timeout(
+    mut command: Command,
+    program: &Path,
+    timeout: Duration,
+    max_output: u64
+) -> Result<(Vec<u8>, Vec<u8>), Box<dyn Error>> {
+    command.stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped());
+
+    #[cfg(target_os = "windows")]
+    {
+        // don't open a console window for every video
+        use std::os::windows::process::CommandExt;
+        const CREATE_NO_WINDOW: u32 = 0x08000000;
+        command.creation_flags(CREATE_NO_WINDOW);
+    }
+
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            return Err(VideoError::ToolMissing(program.to_path_buf()).into());
+        }
+        Err(err) => {
+            return Err(err.into());
+        }
+    };
+
+    // both pipes have to be drained while waiting, otherwise the child may block on a full pipe
+    let read_pipe = |pipe: Option<Box<dyn Read + Send>>| {
+        thread::spawn(move || {
+            let mut buf = Vec::new();
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.by_ref().take(max_output).read_to_end(&mut buf);
+                // the rest is still drained, so that the child doesn't block on it
+                let _ = io::copy(&mut pipe, &mut io::sink());
+            }
+            buf
+        })
+    };
+    let stdout_reader = read_pipe(child.stdout.take().map(|pipe| Box::new(pipe) as _));
+    let stderr_reader = read_pipe(child.stderr.take().map(|pipe| Box::new(pipe) as _));
+
+    let start = Instant::now();
+    loop {
+        if child.try_wait()?.is_some() {
+            break;
+        }
+        if start.elapsed() > timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(VideoError::Timeout(timeout).into());
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    let stdout = stdout_reader.join().unwrap_or_default();
+    let stderr = stderr_reader.join().unwrap_or_default();
+
+    Ok((stdout, stderr))
+}
+
+/// "  Duration: 00:01:23.45, start: ..." -> 83.45s
+fn parse_duration(stderr: &str) -> Option<Duration> {
+    let line = stderr.lines().find(|line| line.trim_start().starts_with("Duration:"))?;
+    let timestamp = line.trim_start().strip_prefix("Duration:")?.split(',').next()?.trim();
+
+    let mut secs = 0.0;
+    for part in timestamp.split(':') {
+        secs = secs * 60.0 + part.parse::<f64>().ok()?;
+    }
+
+    Some(Duration::from_secs_f64(secs))
+}
+
+/// first "<width>x<height>" of the first video stream (the input stream is listed first)
+fn parse_resolution(stderr: &str) -> Option<(u32, u32)> {
+    let line = stderr.lines().find(|line| line.contains("Video:"))?;
+
+    line.split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|token| !token.starts_with('0')) // codec tags like "0x31637661"
+        .find_map(|token| {
+            let (width, height) = token.split_once('x')?;
+            Some((width.parse().ok()?, height.parse().ok()?))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STDERR: &str = "\
+Input #0, mov,mp4,m4a,3gp,3g2,mj2, from 'clip.mp4':
+  Metadata:
+    major_brand     : isom
+  Duration: 00:01:23.45, start: 0.000000, bitrate: 1205 kb/s
+  Stream #0:0[0x1](und): Video: h264 (High) (avc1 / 0x31637661), yuv420p(progressive), 1920x1080 [SAR 1:1 DAR 16:9], 1070 kb/s, 25 fps
+  Stream #0:1[0x2](und): Audio: aac (LC) (mp4a / 0x6134706D), 48000 Hz, stereo, fltp, 128 kb/s
+Stream mapping:
+  Stream #0:0 -> #0:0 (h264 (native) -> png (native))
+Output #0, image2pipe, to 'pipe:':
+  Stream #0:0(und): Video: png, rgb24(pc, progressive), 256x144 [SAR 1:1 DAR 16:9], q=2-31
+";
+
+    fn request(storyboard_frames: u32) -> ThumbnailRequest {
+        ThumbnailRequest {
+            max_x: 64,
+            max_y: 64,
+            apply_orientation: true,
+            convert_to_srgb: false,
+            embed_srgb_tag: false,
+            scaling: ScalingOptions::default(),
+            decode_limits: DecodeLimits::default(),
+            animated_previews: false,
+            storyboard_frames,
+        }
+    }
+
+    /// empty directory for the files of one test
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("thumbnailed-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn parses_duration() {
+        assert_eq!(parse_duration(STDERR), Some(Duration::from_secs_f64(83.45)));
+        assert_eq!(
+            parse_duration("  Duration: 01:00:00.50, start: 0.0"),
+            Some(Duration::from_secs_f64(3600.5))
+        );
+        // live streams / images
+        assert_eq!(parse_duration("  Duration: N/A, bitrate: N/A"), None);
+        assert_eq!(parse_duration(""), None);
+    }
+
+    #[test]
+    fn parses_resolution_of_the_input_stream() {
+        assert_eq!(parse_resolution(STDERR), Some((1920, 1080)));
+        assert_eq!(
+            parse_resolution("    Stream #0:0: Video: vp9, yuv420p, 640x360, 30 fps"),
+            Some((640, 360))
+        );
+        // only the codec tag looks like a resolution
+        assert_eq!(parse_resolution("    Stream #0:0: Video: h264 (avc1 / 0x31637661)"), None);
+        assert_eq!(parse_resolution("    Stream #0:1: Audio: aac, 48000 Hz"), None);
+    }
+
+    #[test]
+    fn missing_command_is_reported() {
+        let provider = VideoProvider::new("thumbnailed-test-no-such-command");
+        let err = provider.provide(Path::new("clip.mp4"), &request(0)).map(|_| ()).unwrap_err();
+
+        assert!(matches!(err.downcast_ref::<VideoError>(), Some(VideoError::ToolMissing(_))));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn returns_output_of_the_command() {
+        let mut command = Command::new("sh");
+        command.args(["-c", "printf stdout; printf stderr >&2"]);
+
+        let (stdout, stderr) = run_with_timeout(
+            command,
+            Path::new("sh"),
+            Duration::from_secs(10),
+            MAX_FRAME_SIZE
+        ).unwrap();
+        assert_eq!(stdout, b"stdout");
+        assert_eq!(stderr, b"stderr");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn caps_output_of_the_command() {
+        let mut command = Command::new("sh");
+        command.args(["-c", "head -c 1000000 /dev/zero; head -c 1000000 /dev/zero >&2"]);
+
+        // finishes, although the output is larger than the limit (and the pipe buffers)
+        let (stdout, stderr) = run_with_timeout(
+            command,
+            Path::new("sh"),
+            Duration::from_secs(10),
+            1000
+        ).unwrap();
+        assert_eq!(stdout.len(), 1000);
+        assert_eq!(stderr.len(), 1000);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn kills_command_after_timeout() {
+        let mut command = Command::new("sh");
+        command.args(["-c", "sleep 10"]);
+
+        let start = Instant::now();
+        let err = run_with_timeout(
+            command,
+            Path::new("sh"),
+            Duration::from_millis(100),
+            MAX_FRAME_SIZE
+        ).unwrap_err();
+
+        assert!(matches!(err.downcast_ref::<VideoError>(), Some(VideoError::Timeout(_))));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn expands_argument_template() {
+        let path = Path::new("/videos/clip {1}.mp4");
+        let expand = |arg| expand_arg(arg, path, Duration::from_millis(2500), 256, 144);
+
+        assert_eq!(expand("{input}"), path.as_os_str());
+        assert_eq!(expand("file:{input}"), "file:/videos/clip {1}.mp4");
+        assert_eq!(expand("{offset}"), "2.500");
+        assert_eq!(expand("scale=w={max_x}:h={max_y}"), "scale=w=256:h=144");
+        assert_eq!(expand("{size}"), "256");
+        assert_eq!(expand("-frames:v"), "-frames:v");
+
+        let thumbnailer = VideoProvider::ffmpegthumbnailer();
+        assert_eq!(thumbnailer.command, Path::new("ffmpegthumbnailer"));
+        assert!(thumbnailer.args.iter().any(|arg| arg == "{input}"));
+    }
+
+    /// a fake ffmpeg, that prints the stderr of a real run and writes the same png for every frame
+    /// (after sleeping `delay` seconds), every run appends a line to `runs.txt`
+    #[cfg(unix)]
+    fn write_stub_command(dir: &Path, delay: f32) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let png_path = dir.join("frame.png");
+        image::RgbaImage
+            ::from_pixel(32, 18, image::Rgba([255, 0, 0, 255]))
+            .save(&png_path)
+            .unwrap();
+        std::fs::write(dir.join("stderr.txt"), STDERR).unwrap();
+
+        let script = dir.join("ffmpeg");
+        std::fs::write(
+            &script,
+            format!(
+                "#!/bin/sh\necho run >> '{}'\nsleep {delay}\ncat '{}' >&2\ncat '{}'\n",
+                dir.join("runs.txt").display(),
+                dir.join("stderr.txt").display(),
+                png_path.display()
+            )
+        ).unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        script
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn provides_frame_and_storyboard_from_stub_command() {
+        let dir = test_dir("video-stub");
+        let script = write_stub_command(&dir, 0.0);
+
+        let provided = VideoProvider
+            ::new(&script)
+            .provide(Path::new("clip.mp4"), &request(3))
+            .unwrap();
+
+        // fitted into the requested 64 x 64
+        assert_eq!(provided.image.dimensions(), (64, 36));
+        assert_eq!((provided.metadata.width, provided.metadata.height), (1920, 1080));
+        assert_eq!(provided.metadata.duration, Some(Duration::from_secs_f64(83.45)));
+        assert_eq!(provided.storyboard_frames.len(), 3);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn storyboard_is_dropped_after_its_timeout() {
+        let dir = test_dir("video-storyboard-timeout");
+        let script = write_stub_command(&dir, 0.2);
+        let provider = VideoProvider {
+            storyboard_timeout: Duration::from_millis(500),
+            ..VideoProvider::new(&script)
+        };
+
+        let provided = provider.provide(Path::new("clip.mp4"), &request(10)).unwrap();
+
+        // the representative frame has its own timeout
+        assert_eq!(provided.image.dimensions(), (64, 36));
+        assert!(provided.storyboard_frames.is_empty());
+        // every run takes at least 0.2s, so at most 3 storyboard frames fit into 0.5s
+        let runs = std::fs::read_to_string(dir.join("runs.txt")).unwrap().lines().count();
+        assert!(runs <= 1 + 3, "{runs} runs");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,202 @@
+/// simple quality metrics of a thumbnail, to hide obviously bad frames while culling
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub struct QualityMetrics {
+    /// variance of the laplacian of the gray image (low = blurry)
+    pub sharpness: f32,
+    /// fraction of the pixels, that are (nearly) black
+    pub dark_fraction: f32,
+    /// fraction of the pixels, that are (nearly) clipped to white
+    pub clipped_fraction: f32,
+    /// mean brightness (0..=255)
+    pub brightness: f32,
+    /// standard deviation of the brightness (0..=255)
+    pub contrast: f32,
+}
+
+/// brightness (0..=255) up to which a pixel counts as black
+const DARK_LEVEL: u8 = 16;
+/// brightness (0..=255) from which on a pixel counts as clipped
+const CLIPPED_LEVEL: u8 = 245;
+
+/// more than this fraction of black pixels is underexposed
+const MAX_DARK_FRACTION: f32 = 0.5;
+/// more than this fraction of clipped pixels is overexposed
+const MAX_CLIPPED_FRACTION: f32 = 0.25;
+/// high contrast images (black text on white) have lots of black or white pixels, but aren't
+/// badly exposed unless the whole image is dark / bright
+const MAX_UNDEREXPOSED_BRIGHTNESS: f32 = 60.0;
+const MIN_OVEREXPOSED_BRIGHTNESS: f32 = 195.0;
+/// frames with less contrast are (nearly) uniform
+const BLANK_CONTRAST: f32 = 4.0;
+
+impl QualityMetrics {
+    /// the sharpness depends on the thumbnail size, so the threshold is up to the user
+    pub fn is_blurry(&self, threshold: f32) -> bool {
+        // blank frames have no detail at all, but aren't blurry
+        self.sharpness < threshold && !self.is_blank()
+    }
+
+    pub fn is_underexposed(&self) -> bool {
+        self.dark_fraction > MAX_DARK_FRACTION &&
+            self.brightness < MAX_UNDEREXPOSED_BRIGHTNESS &&
+            !self.is_blank()
+    }
+
+    pub fn is_overexposed(&self) -> bool {
+        self.clipped_fraction > MAX_CLIPPED_FRACTION &&
+            self.brightness > MIN_OVEREXPOSED_BRIGHTNESS &&
+            !self.is_blank()
+    }
+
+    pub fn is_badly_exposed(&self) -> bool {
+        self.is_underexposed() || self.is_overexposed()
+    }
+
+    /// (nearly) one color, e.g. lens cap on, blank pages or black video frames
+    pub fn is_blank(&self) -> bool {
+        self.contrast < BLANK_CONTRAST
+    }
+}
+
+/// sharpness, exposure and contrast of the thumbnail
+pub fn quality_metrics(image: &image::RgbaImage) -> QualityMetrics {
+    let gray = image::imageops::grayscale(image);
+    let (width, height) = gray.dimensions();
+
+    // (mostly) transparent pixels don't count for the exposure
+    let mut histogram = [0u32; 256];
+    for (pixel, gray_pixel) in image.pixels().zip(gray.pixels()) {
+        if pixel[3] >= 128 {
+            histogram[usize::from(gray_pixel[0])] += 1;
+        }
+    }
+    let total: u32 = histogram.iter().sum();
+    if total == 0 {
+        return QualityMetrics::default();
+    }
+
+    let fraction = |levels: std::ops::RangeInclusive<u8>| {
+        let count: u32 = levels.map(|level| histogram[usize::from(level)]).sum();
+        (count as f32) / (total as f32)
+    };
+
+    let mean = histogram
+        .iter()
+        .enumerate()
+        .map(|(level, count)| (level as f32) * (*count as f32))
+        .sum::<f32>() / (total as f32);
+    let variance = histogram
+        .iter()
+        .enumerate()
+        .map(|(level, count)| ((level as f32) - mean).powi(2) * (*count as f32))
+        .sum::<f32>() / (total as f32);
+
+    QualityMetrics {
+        sharpness: laplacian_variance(&gray, width, height),
+        dark_fraction: fraction(0..=DARK_LEVEL),
+        clipped_fraction: fraction(CLIPPED_LEVEL..=255),
+        brightness: mean,
+        contrast: variance.sqrt(),
+    }
+}
+
+/// variance of the 4-neighbour laplacian (the border pixels are left out)
+fn laplacian_variance(gray: &image::GrayImage, width: u32, height: u32) -> f32 {
+    if width < 3 || height < 3 {
+        return 0.0;
+    }
+
+    let value = |x: u32, y: u32| f32::from(gray.get_pixel(x, y)[0]);
+
+    let mut sum = 0.0f64;
+    let mut square_sum = 0.0f64;
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let laplacian =
+                value(x - 1, y) + value(x + 1, y) + value(x, y - 1) + value(x, y + 1) -
+                4.0 * value(x, y);
+            sum += f64::from(laplacian);
+            square_sum += f64::from(laplacian).powi(2);
+        }
+    }
+
+    let count = f64::from((width - 2) * (height - 2));
+    let mean = sum / count;
+    (square_sum / count - mean * mean).max(0.0) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// black and white squares of `size` pixels
+    fn checkerboard(size: u32) -> image::RgbaImage {
+        image::RgbaImage::from_fn(64, 64, |x, y| {
+            match ((x / size) + (y / size)) % 2 {
+                0 => image::Rgba([0, 0, 0, 255]),
+                _ => image::Rgba([255, 255, 255, 255]),
+            }
+        })
+    }
+
+    fn blurred(image: &image::RgbaImage) -> image::RgbaImage {
+        image::imageops::blur(image, 4.0)
+    }
+
+    #[test]
+    fn blurred_image_is_less_sharp() {
+        let sharp = quality_metrics(&checkerboard(8));
+        let blurry = quality_metrics(&blurred(&checkerboard(8)));
+
+        assert!(blurry.sharpness * 10.0 < sharp.sharpness);
+        let threshold = sharp.sharpness / 2.0;
+        assert!(blurry.is_blurry(threshold));
+        assert!(!sharp.is_blurry(threshold));
+        // black text on white isn't badly exposed
+        assert!(!sharp.is_badly_exposed() && !sharp.is_blank());
+    }
+
+    #[test]
+    fn uniform_frames_are_blank_but_not_blurry() {
+        for level in [0, 128, 255] {
+            let metrics = quality_metrics(
+                &image::RgbaImage::from_pixel(32, 32, image::Rgba([level, level, level, 255]))
+            );
+
+            assert!(metrics.is_blank());
+            assert_eq!(metrics.sharpness, 0.0);
+            assert!(!metrics.is_blurry(f32::MAX));
+            assert!(!metrics.is_badly_exposed());
+        }
+    }
+
+    #[test]
+    fn classifies_dark_and_clipped_images() {
+        // a little detail, so that the frames aren't blank
+        let noisy = |base: u8| {
+            image::RgbaImage::from_fn(64, 64, |x, y| {
+                let value = base.saturating_add(((x * 13 + y * 7) % 24) as u8);
+                image::Rgba([value, value, value, 255])
+            })
+        };
+        let dark = quality_metrics(&noisy(0));
+        let clipped = quality_metrics(&noisy(232));
+        let mid = quality_metrics(&noisy(100));
+
+        assert!(dark.is_underexposed() && !dark.is_overexposed() && !dark.is_blank());
+        assert!(clipped.is_overexposed() && !clipped.is_underexposed() && !clipped.is_blank());
+        assert!(!mid.is_badly_exposed());
+        assert!(dark.dark_fraction > MAX_DARK_FRACTION);
+        assert!(clipped.clipped_fraction > MAX_CLIPPED_FRACTION);
+    }
+
+    #[test]
+    fn transparent_pixels_are_ignored() {
+        let mut image = image::RgbaImage::from_pixel(16, 16, image::Rgba([255, 255, 255, 0]));
+        assert_eq!(quality_metrics(&image), QualityMetrics::default());
+
+        image.put_pixel(0, 0, image::Rgba([0, 0, 0, 255]));
+        let metrics = quality_metrics(&image);
+        assert_eq!((metrics.dark_fraction, metrics.clipped_fraction), (1.0, 0.0));
+    }
+}
@@ -0,0 +1,212 @@
+use image::imageops::FilterType;
+
+/// levels per channel of the color histogram (4 x 4 x 4 bins)
+const COLOR_LEVELS: usize = 4;
+const COLOR_BINS: usize = COLOR_LEVELS * COLOR_LEVELS * COLOR_LEVELS;
+/// bins of the gradient orientation histogram (0..180°, edges have no direction)
+const ORIENTATION_BINS: usize = 8;
+/// color bins, orientation bins, edge density
+pub const FEATURE_LEN: usize = COLOR_BINS + ORIENTATION_BINS + 1;
+
+/// side length of the gray image, that the edges are detected on
+const EDGE_SIZE: u32 = 64;
+
+const COLOR_WEIGHT: f32 = 0.6;
+const ORIENTATION_WEIGHT: f32 = 0.3;
+const DENSITY_WEIGHT: f32 = 0.1;
+
+/// cheap descriptor of a thumbnail for "more like this" searches
+///
+/// a color histogram (where the colors are) plus a histogram of the edge orientations and the
+/// edge density (what the texture looks like). both histograms are normalized, so the size of
+/// the thumbnail doesn't matter
+pub fn feature_vector(image: &image::RgbaImage) -> Vec<f32> {
+    let mut features = vec![0.0f32; FEATURE_LEN];
+
+    // (mostly) transparent pixels don't count
+    let mut opaque = 0;
+    for pixel in image.pixels().filter(|pixel| pixel[3] >= 128) {
+        let [r, g, b] = [pixel[0], pixel[1], pixel[2]].map(|value| {
+            (usize::from(value) * COLOR_LEVELS) / 256
+        });
+        features[(r * COLOR_LEVELS + g) * COLOR_LEVELS + b] += 1.0;
+        opaque += 1;
+    }
+    if opaque > 0 {
+        features[..COLOR_BINS].iter_mut().for_each(|bin| {
+            *bin /= opaque as f32;
+        });
+    }
+
+    let gray = image::imageops::grayscale(image);
+    let gray = image::imageops::resize(&gray, EDGE_SIZE, EDGE_SIZE, FilterType::Triangle);
+    let value = |x: u32, y: u32| f32::from(gray.get_pixel(x, y)[0]) / 255.0;
+
+    let orientations = &mut features[COLOR_BINS..COLOR_BINS + ORIENTATION_BINS];
+    let mut magnitude_sum = 0.0;
+    for y in 1..EDGE_SIZE - 1 {
+        for x in 1..EDGE_SIZE - 1 {
+            // sobel
+            let gx =
+                value(x + 1, y - 1) + 2.0 * value(x + 1, y) + value(x + 1, y + 1) -
+                value(x - 1, y - 1) - 2.0 * value(x - 1, y) - value(x - 1, y + 1);
+            let gy =
+                value(x - 1, y + 1) + 2.0 * value(x, y + 1) + value(x + 1, y + 1) -
+                value(x - 1, y - 1) - 2.0 * value(x, y - 1) - value(x + 1, y - 1);
+
+            let magnitude = (gx * gx + gy * gy).sqrt();
+            let angle = gy.atan2(gx).rem_euclid(std::f32::consts::PI);
+            let bin = ((angle / std::f32::consts::PI) * (ORIENTATION_BINS as f32)) as usize;
+            orientations[bin.min(ORIENTATION_BINS - 1)] += magnitude;
+            magnitude_sum += magnitude;
+        }
+    }
+    if magnitude_sum > 0.0 {
+        orientations.iter_mut().for_each(|bin| {
+            *bin /= magnitude_sum;
+        });
+    }
+
+    // the sobel magnitude of a hard black / white edge is 4, ~1 is already a very busy image
+    let inner_pixels = ((EDGE_SIZE - 2) * (EDGE_SIZE - 2)) as f32;
+    features[FEATURE_LEN - 1] = (magnitude_sum / inner_pixels).min(1.0);
+
+    features
+}
+
+/// 0.0 for identical feature vectors up to 1.0, infinite if one of them is missing
+pub fn feature_distance(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != FEATURE_LEN || b.len() != FEATURE_LEN {
+        return f32::INFINITY;
+    }
+
+    // histogram intersection
+    let color = 1.0 -
+        a[..COLOR_BINS]
+            .iter()
+            .zip(&b[..COLOR_BINS])
+            .map(|(a, b)| a.min(*b))
+            .sum::<f32>();
+    let orientation =
+        a[COLOR_BINS..COLOR_BINS + ORIENTATION_BINS]
+            .iter()
+            .zip(&b[COLOR_BINS..COLOR_BINS + ORIENTATION_BINS])
+            .map(|(a, b)| (a - b).abs())
+            .sum::<f32>() / 2.0;
+    let density = (a[FEATURE_LEN - 1] - b[FEATURE_LEN - 1]).abs();
+
+    COLOR_WEIGHT * color.max(0.0) + ORIENTATION_WEIGHT * orientation + DENSITY_WEIGHT * density
+}
+
+/// (index, distance) of the `count` nearest neighbours of `features[query]`, nearest first
+///
+/// a linear scan, fast enough for a few ten thousand images. images without a feature vector
+/// (and the query itself) are left out
+pub fn most_similar(features: &[&[f32]], query: usize, count: usize) -> Vec<(usize, f32)> {
+    let query_features = match features.get(query) {
+        Some(query_features) => *query_features,
+        None => {
+            return Vec::new();
+        }
+    };
+
+    let mut neighbours: Vec<(usize, f32)> = features
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| *i != query)
+        .map(|(i, candidate)| (i, feature_distance(query_features, candidate)))
+        .filter(|(_, distance)| distance.is_finite())
+        .collect();
+
+    if neighbours.len() > count {
+        neighbours.select_nth_unstable_by(count, |a, b| a.1.total_cmp(&b.1));
+        neighbours.truncate(count);
+    }
+    neighbours.sort_by(|a, b| a.1.total_cmp(&b.1).then(a.0.cmp(&b.0)));
+
+    neighbours
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stripes(width: u32, height: u32, vertical: bool, colors: [[u8; 3]; 2]) -> image::RgbaImage {
+        image::RgbaImage::from_fn(width, height, |x, y| {
+            let position = match vertical {
+                true => x,
+                false => y,
+            };
+            let [r, g, b] = colors[((position / 4) % 2) as usize];
+            image::Rgba([r, g, b, 255])
+        })
+    }
+
+    const RED_BLUE: [[u8; 3]; 2] = [[220, 20, 20], [20, 20, 220]];
+    const GREEN_WHITE: [[u8; 3]; 2] = [[20, 200, 20], [250, 250, 250]];
+
+    #[test]
+    fn identical_images_have_no_distance() {
+        let features = feature_vector(&stripes(96, 64, true, RED_BLUE));
+
+        assert_eq!(features.len(), FEATURE_LEN);
+        assert!(feature_distance(&features, &features) < 1e-5);
+        // the histograms are normalized, so a rescaled copy is still (almost) identical
+        let larger = feature_vector(&stripes(192, 128, true, RED_BLUE));
+        assert!(feature_distance(&features, &larger) < 0.05);
+    }
+
+    #[test]
+    fn distance_is_symmetric_and_bounded() {
+        let images = [
+            stripes(64, 64, true, RED_BLUE),
+            stripes(64, 64, false, RED_BLUE),
+            stripes(64, 64, true, GREEN_WHITE),
+            image::RgbaImage::from_pixel(64, 64, image::Rgba([0, 0, 0, 255])),
+        ];
+        let features: Vec<Vec<f32>> = images.iter().map(feature_vector).collect();
+
+        for a in &features {
+            for b in &features {
+                let distance = feature_distance(a, b);
+                assert_eq!(distance, feature_distance(b, a));
+                assert!((0.0..=1.0).contains(&distance), "distance {distance}");
+            }
+        }
+        // same colors, other direction: closer than other colors in the same direction
+        assert!(
+            feature_distance(&features[0], &features[1]) <
+                feature_distance(&features[0], &features[2])
+        );
+    }
+
+    #[test]
+    fn missing_features_are_infinitely_far_away() {
+        let features = feature_vector(&stripes(64, 64, true, RED_BLUE));
+
+        assert_eq!(feature_distance(&features, &[]), f32::INFINITY);
+        assert_eq!(feature_distance(&[], &features), f32::INFINITY);
+    }
+
+    #[test]
+    fn finds_nearest_neighbours_first() {
+        let features: Vec<Vec<f32>> = [
+            stripes(64, 64, true, RED_BLUE),
+            stripes(64, 64, true, GREEN_WHITE),
+            stripes(128, 128, true, RED_BLUE),
+            stripes(64, 64, false, RED_BLUE),
+        ]
+            .iter()
+            .map(feature_vector)
+            .collect();
+        let mut features: Vec<&[f32]> = features.iter().map(Vec::as_slice).collect();
+        features.push(&[]);
+
+        let neighbours = most_similar(&features, 0, 2);
+        assert_eq!(neighbours.iter().map(|(i, _)| *i).collect::<Vec<_>>(), vec![2, 3]);
+        assert!(neighbours[0].1 <= neighbours[1].1);
+        // the image without features is never returned
+        assert_eq!(most_similar(&features, 0, 10).len(), 3);
+        assert!(most_similar(&features, 10, 2).is_empty());
+    }
+}
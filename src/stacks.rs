@@ -0,0 +1,123 @@
+use crate::*;
+
+/// groups of indices into `timestamps`, that were shot in quick succession and look alike (bursts)
+///
+/// the images are walked in time order, every image joins the stack of its predecessor, if it was
+/// taken at most `max_gap` seconds later (on the same clock, see `Timestamp`) and their feature
+/// distance is at most `max_distance`.
+/// the chaining keeps slowly panning bursts together. images without a timestamp or feature
+/// vector and images without a similar neighbour are left out. stacks are in time order
+pub fn group_stacks(
+    timestamps: &[Option<Timestamp>],
+    features: &[&[f32]],
+    max_gap: i64,
+    max_distance: f32
+) -> Vec<Vec<usize>> {
+    // (local clock, seconds, index)
+    let mut shots: Vec<(bool, i64, usize)> = timestamps
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| features.get(*i).is_some_and(|features| !features.is_empty()))
+        .filter_map(|(i, timestamp)| {
+            timestamp.map(|timestamp| (timestamp.local, timestamp.seconds, i))
+        })
+        .collect();
+    shots.sort_unstable();
+
+    let mut stacks = Vec::new();
+    let mut current: Vec<usize> = Vec::new();
+    let mut previous: Option<(bool, i64, usize)> = None;
+
+    for (local, seconds, i) in shots {
+        let joins = match previous {
+            Some((previous_local, previous_seconds, previous_i)) =>
+                local == previous_local &&
+                    seconds - previous_seconds <= max_gap &&
+                    feature_distance(features[previous_i], features[i]) <= max_distance,
+            None => false,
+        };
+
+        if !joins {
+            match current.len() > 1 {
+                true => stacks.push(std::mem::take(&mut current)),
+                false => current.clear(),
+            }
+        }
+
+        current.push(i);
+        previous = Some((local, seconds, i));
+    }
+    if current.len() > 1 {
+        stacks.push(current);
+    }
+
+    stacks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local(seconds: i64) -> Option<Timestamp> {
+        Some(Timestamp { seconds, local: true })
+    }
+
+    fn utc(seconds: i64) -> Option<Timestamp> {
+        Some(Timestamp { seconds, local: false })
+    }
+
+    /// feature vector with all colors in one bin, different bins are far apart
+    fn features(bin: usize) -> Vec<f32> {
+        let mut features = vec![0.0; FEATURE_LEN];
+        features[bin] = 1.0;
+        features
+    }
+
+    fn group(timestamps: &[Option<Timestamp>], bins: &[usize]) -> Vec<Vec<usize>> {
+        let features: Vec<Vec<f32>> = bins.iter().map(|bin| features(*bin)).collect();
+        let features: Vec<&[f32]> = features.iter().map(Vec::as_slice).collect();
+        group_stacks(timestamps, &features, 2, 0.1)
+    }
+
+    #[test]
+    fn splits_stacks_at_gaps_longer_than_the_maximum() {
+        // 2s apart joins, 3s apart doesn't
+        let timestamps = [local(10), local(0), local(2), local(5), local(12)];
+
+        assert_eq!(group(&timestamps, &[0; 5]), vec![vec![1, 2], vec![0, 4]]);
+    }
+
+    #[test]
+    fn chains_slowly_panning_bursts() {
+        // 6s from the first to the last shot, but never more than 2s between two of them
+        let timestamps = [local(0), local(2), local(4), local(6)];
+
+        assert_eq!(group(&timestamps, &[0; 4]), vec![vec![0, 1, 2, 3]]);
+    }
+
+    #[test]
+    fn never_chains_local_and_utc_clocks() {
+        let timestamps = [local(100), utc(101), local(102), utc(103), utc(100)];
+
+        let stacks = group(&timestamps, &[0; 5]);
+        assert_eq!(stacks, vec![vec![4, 1, 3], vec![0, 2]]);
+        // alternating clocks within the gap don't form a stack at all
+        assert!(group(&[local(0), utc(1), local(3)], &[0; 3]).is_empty());
+    }
+
+    #[test]
+    fn leaves_out_dissimilar_and_incomplete_shots() {
+        let timestamps = [local(0), local(1), local(2), None, local(3)];
+
+        // the second shot looks different, the fourth has no timestamp
+        assert!(group(&timestamps, &[0, 1, 0, 0, 2]).is_empty());
+
+        let features = [features(0), Vec::new(), features(0)];
+        let features: Vec<&[f32]> = features.iter().map(Vec::as_slice).collect();
+        // the shot without features doesn't break the stack
+        assert_eq!(
+            group_stacks(&[local(0), local(1), local(2)], &features, 2, 0.1),
+            vec![vec![0, 2]]
+        );
+    }
+}
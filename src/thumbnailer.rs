@@ -2,7 +2,7 @@ use std::{
     collections::VecDeque,
     fs::{ self, DirEntry },
     path::Path,
-    sync::mpsc::{ self, Sender },
+    sync::{ atomic::{ AtomicUsize, Ordering }, mpsc::{ self, Sender }, Arc, Mutex, OnceLock },
     thread::{ self, JoinHandle },
     time::{ Duration, Instant },
 };
@@ -124,25 +124,578 @@ pub fn search_and_send<P>(path: P, sender: mpsc::Sender<PathBuf>) -> Result<(),
     Ok(())
 }
 
+/// rotates / flips the image, so that it is displayed upright (see exif orientation tag)
+pub fn apply_exif_orientation(image: image::DynamicImage, orientation: u32) -> image::DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        // 1 = already upright, everything else is invalid
+        _ => image,
+    }
+}
+
+fn cmyk_to_rgb(cmyk: &[u8]) -> Vec<u8> {
+    cmyk.chunks_exact(4)
+        .flat_map(|pixel| {
+            // R = 255 * (1 - C/255) * (1 - K/255) (same as the jpeg decoder of `image`)
+            let k = 255 - u16::from(pixel[3]);
+            [0, 1, 2].map(|i| (((255 - u16::from(pixel[i])) * k) / 255) as u8)
+        })
+        .collect()
+}
+
+/// decodes a jpeg that is already downscaled in the dct domain (1/2, 1/4 or 1/8 of its size)
+///
+/// uses the smallest of these scales, that still covers max_x or max_y, so that
+/// `thumbnail()` only has to do the remaining (small) downscaling step
+pub fn decode_jpeg_scaled<P>(
+    path: P,
+    max_x: u32,
+    max_y: u32
+) -> Result<image::DynamicImage, Box<dyn Error>>
+    where P: AsRef<Path>
+{
+    let file = fs::File::open(path)?;
+    decode_jpeg_scaled_from_reader(std::io::BufReader::new(file), max_x, max_y)
+}
+
+/// like `decode_jpeg_scaled`, but for jpegs that aren't files on their own (e.g. raw previews)
+pub(crate) fn decode_jpeg_scaled_from_reader<R>(
+    reader: R,
+    max_x: u32,
+    max_y: u32
+) -> Result<image::DynamicImage, Box<dyn Error>>
+    where R: std::io::Read
+{
+    decode_jpeg_scaled_with(&mut jpeg_decoder::Decoder::new(reader), max_x, max_y)
+}
+
+/// like `decode_jpeg_scaled_from_reader`, but the header may already have been read
+fn decode_jpeg_scaled_with<R>(
+    decoder: &mut jpeg_decoder::Decoder<R>,
+    max_x: u32,
+    max_y: u32
+) -> Result<image::DynamicImage, Box<dyn Error>>
+    where R: std::io::Read
+{
+    let to_u16 = |value: u32| value.min(u32::from(u16::MAX)) as u16;
+    let (width, height) = decoder.scale(to_u16(max_x), to_u16(max_y))?;
+    let (width, height) = (u32::from(width), u32::from(height));
+
+    let pixels = decoder.decode()?;
+    let info = decoder.info().ok_or("missing jpeg frame info after decoding")?;
+
+    let dyn_image = match info.pixel_format {
+        jpeg_decoder::PixelFormat::L8 =>
+            image::GrayImage::from_raw(width, height, pixels).map(image::DynamicImage::ImageLuma8),
+        jpeg_decoder::PixelFormat::L16 => {
+            let pixels: Vec<u16> = pixels
+                .chunks_exact(2)
+                .map(|bytes| u16::from_ne_bytes([bytes[0], bytes[1]]))
+                .collect();
+            image::ImageBuffer
+                ::from_raw(width, height, pixels)
+                .map(image::DynamicImage::ImageLuma16)
+        }
+        jpeg_decoder::PixelFormat::RGB24 =>
+            image::RgbImage::from_raw(width, height, pixels).map(image::DynamicImage::ImageRgb8),
+        jpeg_decoder::PixelFormat::CMYK32 =>
+            image::RgbImage
+                ::from_raw(width, height, cmyk_to_rgb(&pixels))
+                .map(image::DynamicImage::ImageRgb8),
+    };
+
+    Ok(dyn_image.ok_or("decoded jpeg doesn't match its dimensions")?)
+}
+
+/// reports hit decoder limits as `LimitExceeded`
+fn limits_error(err: image::ImageError) -> Box<dyn Error> {
+    match err {
+        image::ImageError::Limits(err) => LimitExceeded::Decoder(err.to_string()).into(),
+        err => err.into(),
+    }
+}
+
+/// decoded image + everything else, that was read from the same reader
+struct DecodedImage {
+    image: image::DynamicImage,
+    /// size of the original image (jpegs are already downscaled while decoding)
+    source_size: (u32, u32),
+    metadata: ImageMetadata,
+    icc_profile: Option<Vec<u8>>,
+}
+
+/// decodes an image within the decoder limits and reads its metadata and icc profile on the way
+fn decode_with_metadata<R>(
+    mut source: R,
+    format: Option<image::ImageFormat>,
+    limits: &DecodeLimits
+) -> Result<DecodedImage, Box<dyn Error>>
+    where R: std::io::BufRead + std::io::Seek
+{
+    use image::ImageDecoder;
+
+    // the tiff decoder doesn't return exif data, as the whole file is a tiff structure
+    let container_exif = match format {
+        Some(image::ImageFormat::Tiff) => {
+            let exif = exif::Reader::new().read_from_container(&mut source);
+            std::io::Seek::rewind(&mut source)?;
+            exif.ok()
+        }
+        _ => None,
+    };
+
+    let mut reader = image::ImageReader::new(source);
+    if let Some(format) = format {
+        reader.set_format(format);
+    }
+    reader.limits(limits.to_image_limits());
+    // only reads the header, so that bombs are rejected before anything gets allocated
+    let mut decoder = reader.into_decoder().map_err(limits_error)?;
+    let source_size = decoder.dimensions();
+    limits.check_dimensions(source_size.0, source_size.1)?;
+
+    let xmp = decoder.xmp_metadata().ok().flatten();
+    let metadata = match &container_exif {
+        Some(exif) => ImageMetadata::from_exif(Some(exif), xmp.as_deref()),
+        None =>
+            ImageMetadata::from_raw(
+                decoder.exif_metadata().ok().flatten().as_deref(),
+                xmp.as_deref()
+            ),
+    };
+    let icc_profile = decoder.icc_profile().ok().flatten();
+    let image = image::DynamicImage::from_decoder(decoder).map_err(limits_error)?;
+
+    Ok(DecodedImage { image, source_size, metadata, icc_profile })
+}
+
+/// like `decode_with_metadata`, but the jpeg is already downscaled while decoding
+fn decode_jpeg_with_metadata<R>(
+    reader: R,
+    apply_orientation: bool,
+    max_x: u32,
+    max_y: u32,
+    limits: &DecodeLimits
+) -> Result<DecodedImage, Box<dyn Error>>
+    where R: std::io::Read
+{
+    let mut decoder = jpeg_decoder::Decoder::new(reader);
+    // the header check below covers the image, this also bounds the decoder's own buffers
+    decoder.set_max_decoding_buffer_size(
+        limits.max_alloc.map_or(usize::MAX, |max| usize::try_from(max).unwrap_or(usize::MAX))
+    );
+    decoder.read_info()?;
+    let info = decoder.info().ok_or("missing jpeg frame info after reading the header")?;
+    let source_size = (u32::from(info.width), u32::from(info.height));
+    limits.check_dimensions(source_size.0, source_size.1)?;
+
+    let metadata = ImageMetadata::from_raw(decoder.exif_data(), decoder.xmp_data());
+    let icc_profile = decoder.icc_profile();
+
+    // orientations 5..=8 swap width and height after decoding
+    let (scaled_x, scaled_y) = match (apply_orientation, metadata.orientation) {
+        (true, Some(5..=8)) => (max_y, max_x),
+        _ => (max_x, max_y),
+    };
+    let image = decode_jpeg_scaled_with(&mut decoder, scaled_x, scaled_y)?;
+
+    Ok(DecodedImage { image, source_size, metadata, icc_profile })
+}
+
 pub fn generate_thumbnail_from_image(
     path: PathBuf,
     max_x: u32,
-    max_y: u32
-) -> Result<image::RgbaImage, Box<dyn Error>> {
-    let reader = image::io::Reader::open(&path)?;
-    let dyn_image = reader.decode()?;
-    let thumbnail = dyn_image.thumbnail(max_x, max_y);
+    max_y: u32,
+    apply_orientation: bool,
+    convert_to_srgb: bool,
+    scaling: ScalingOptions,
+    limits: &DecodeLimits
+) -> Result<ProvidedThumbnail, Box<dyn Error>> {
+    limits.check_file_size(fs::metadata(&path)?.len())?;
+
+    thumbnail_from_reader(
+        image::ImageReader::open(&path)?.with_guessed_format()?,
+        apply_orientation,
+        convert_to_srgb,
+        max_x,
+        max_y,
+        scaling,
+        limits
+    )
+}
 
-    Ok(thumbnail.into_rgba8())
+/// like `generate_thumbnail_from_image`, but for images that aren't files on their own (e.g. archive entries)
+pub(crate) fn generate_thumbnail_from_memory(
+    data: &[u8],
+    max_x: u32,
+    max_y: u32,
+    apply_orientation: bool,
+    convert_to_srgb: bool,
+    scaling: ScalingOptions,
+    limits: &DecodeLimits
+) -> Result<ProvidedThumbnail, Box<dyn Error>> {
+    limits.check_file_size(data.len() as u64)?;
+
+    thumbnail_from_reader(
+        image::ImageReader::new(std::io::Cursor::new(data)).with_guessed_format()?,
+        apply_orientation,
+        convert_to_srgb,
+        max_x,
+        max_y,
+        scaling,
+        limits
+    )
+}
+
+/// converts the pixels from the color space of the icc profile to srgb
+///
+/// only rgb profiles are converted, the image is returned unchanged for other or broken profiles.
+/// 16 bit images stay 16 bit, so that they're only quantized after downscaling
+pub fn icc_to_srgb(dyn_image: image::DynamicImage, icc_profile: &[u8]) -> image::DynamicImage {
+    use moxcms::Layout;
+
+    // color space signature of the profile header (float images are tone mapped later,
+    // converting them to 8 bit here would clamp them)
+    if icc_profile.get(16..20) != Some(b"RGB ") || is_float(&dyn_image) {
+        return dyn_image;
+    }
+
+    let profile = match moxcms::ColorProfile::new_from_slice(icc_profile) {
+        Ok(profile) => profile,
+        Err(err) => {
+            log::trace!("failed to parse icc profile ({err}), keeping the colors as they are");
+            return dyn_image;
+        }
+    };
+    let srgb = moxcms::ColorProfile::new_srgb();
+    let options = moxcms::TransformOptions {
+        rendering_intent: moxcms::RenderingIntent::Perceptual,
+        ..Default::default()
+    };
+
+    let color = dyn_image.color();
+    match (color.bytes_per_pixel() > color.channel_count(), color.has_alpha()) {
+        (false, false) => {
+            let transform = profile.create_transform_8bit(Layout::Rgb, &srgb, Layout::Rgb, options);
+            image::DynamicImage::ImageRgb8(transform_pixels(dyn_image.into_rgb8(), transform))
+        }
+        (false, true) => {
+            let transform = profile.create_transform_8bit(Layout::Rgba, &srgb, Layout::Rgba, options);
+            image::DynamicImage::ImageRgba8(transform_pixels(dyn_image.into_rgba8(), transform))
+        }
+        (true, false) => {
+            let transform = profile.create_transform_16bit(Layout::Rgb, &srgb, Layout::Rgb, options);
+            image::DynamicImage::ImageRgb16(transform_pixels(dyn_image.into_rgb16(), transform))
+        }
+        (true, true) => {
+            let transform = profile.create_transform_16bit(
+                Layout::Rgba,
+                &srgb,
+                Layout::Rgba,
+                options
+            );
+            image::DynamicImage::ImageRgba16(transform_pixels(dyn_image.into_rgba16(), transform))
+        }
+    }
+}
+
+/// applies the transform (if it could be created) to all pixels
+fn transform_pixels<P>(
+    image: image::ImageBuffer<P, Vec<P::Subpixel>>,
+    transform: Result<
+        Arc<dyn moxcms::TransformExecutor<P::Subpixel> + Send + Sync>,
+        moxcms::CmsError
+    >
+) -> image::ImageBuffer<P, Vec<P::Subpixel>>
+    where P: image::Pixel, P::Subpixel: Default
+{
+    let transform = match transform {
+        Ok(transform) => transform,
+        Err(err) => {
+            log::trace!("failed to create srgb transform for icc profile ({err})");
+            return image;
+        }
+    };
+
+    let mut converted = vec![P::Subpixel::default(); image.as_raw().len()];
+    if let Err(err) = transform.transform(image.as_raw(), &mut converted) {
+        log::trace!("failed to convert the pixels to srgb ({err})");
+        return image;
+    }
+
+    image::ImageBuffer::from_raw(image.width(), image.height(), converted).unwrap_or(image)
+}
+
+/// the metadata (exif, xmp, icc profile and the stored dimensions) is read from the same reader
+/// as the pixels, so the source is opened once (and only rewound if the scaled jpeg decoding fails)
+fn thumbnail_from_reader<R>(
+    reader: image::ImageReader<R>,
+    apply_orientation: bool,
+    convert_to_srgb: bool,
+    max_x: u32,
+    max_y: u32,
+    scaling: ScalingOptions,
+    limits: &DecodeLimits
+) -> Result<ProvidedThumbnail, Box<dyn Error>>
+    where R: std::io::BufRead + std::io::Seek
+{
+    let format = reader.format();
+    let mut source = reader.into_inner();
+
+    let decoded = match format {
+        Some(image::ImageFormat::Jpeg) => {
+            let decoded = decode_jpeg_with_metadata(
+                &mut source,
+                apply_orientation,
+                max_x,
+                max_y,
+                limits
+            );
+            match decoded {
+                Ok(decoded) => Some(decoded),
+                Err(err) if err.is::<LimitExceeded>() => {
+                    return Err(err);
+                }
+                Err(err) => {
+                    log::trace!("scaled jpeg decoding failed ({err}), decoding at full size");
+                    std::io::Seek::rewind(&mut source)?;
+                    None
+                }
+            }
+        }
+        _ => None,
+    };
+    let DecodedImage { image: mut dyn_image, source_size, mut metadata, icc_profile } = match
+        decoded
+    {
+        Some(decoded) => decoded,
+        None => decode_with_metadata(source, format, limits)?,
+    };
+    (metadata.width, metadata.height) = source_size;
+
+    // before downscaling, so that the filter works on the converted pixels
+    if let (true, Some(icc_profile)) = (convert_to_srgb, icc_profile) {
+        dyn_image = icc_to_srgb(dyn_image, &icc_profile);
+    }
+
+    if let (true, Some(orientation)) = (apply_orientation, metadata.orientation) {
+        dyn_image = apply_exif_orientation(dyn_image, orientation);
+    }
+
+    // the sharpening is based on the source size as well
+    let tone_mapped = is_float(&dyn_image);
+    let (image, quality) = resample(dyn_image, source_size, max_x, max_y, scaling);
+
+    Ok(ProvidedThumbnail {
+        metadata,
+        tone_mapped,
+        quality: Some(quality),
+        ..ProvidedThumbnail::new(image)
+    })
+}
+
+/// downscales the image to fit into max_x / max_y and converts it to 8 bit srgb
+///
+/// float sources are tone mapped, `source_size` is the size of the original image.
+/// also returns the quality metrics of the thumbnail before it was sharpened
+pub(crate) fn resample(
+    dyn_image: image::DynamicImage,
+    source_size: (u32, u32),
+    max_x: u32,
+    max_y: u32,
+    scaling: ScalingOptions
+) -> (image::RgbaImage, QualityMetrics) {
+    let mut thumbnail = match (is_float(&dyn_image), scaling.quality) {
+        // `resize()` clamps the samples to 0.0..=1.0, so the exposure is detected and applied on
+        // the full image first (clamping before would cut off every highlight above 1.0)
+        (true, _) => {
+            let tone_mapped = image::DynamicImage::ImageRgba32F(tone_map(dyn_image.into_rgba32f()));
+            let resized = tone_mapped.resize(max_x, max_y, image::imageops::FilterType::Triangle);
+            linear_to_srgb_image(&resized.into_rgba32f())
+        }
+        // 16 bit samples are rounded to the nearest 8 bit value (v * 255 / 65535), not truncated
+        (false, ScalingQuality::Fast) => dyn_image.thumbnail(max_x, max_y).into_rgba8(),
+        (false, ScalingQuality::LinearLight) => {
+            let linear = image::DynamicImage::ImageRgba32F(srgb_to_linear_image(dyn_image));
+            let resized = linear.resize(max_x, max_y, image::imageops::FilterType::Triangle);
+            linear_to_srgb_image(&resized.into_rgba32f())
+        }
+    };
+
+    // sharpening would raise the sharpness of blurry sources as well
+    let quality = quality_metrics(&thumbnail);
+
+    if scaling.sharpen {
+        let reduction =
+            (source_size.0.max(source_size.1) as f32) /
+            (thumbnail.width().max(thumbnail.height()).max(1) as f32);
+        sharpen(&mut thumbnail, reduction);
+    }
+
+    (thumbnail, quality)
+}
+
+/// decodes the srgb transfer function (8 bit sources use a lookup table)
+fn srgb_to_linear_image(dyn_image: image::DynamicImage) -> image::Rgba32FImage {
+    let color = dyn_image.color();
+    match color.bytes_per_pixel() == color.channel_count() {
+        true => {
+            let table: Vec<f32> = (0..=255u8)
+                .map(|value| srgb_to_linear(f32::from(value) / 255.0))
+                .collect();
+            let rgba = dyn_image.into_rgba8();
+            image::Rgba32FImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+                let [r, g, b, a] = rgba.get_pixel(x, y).0;
+                image::Rgba([
+                    table[usize::from(r)],
+                    table[usize::from(g)],
+                    table[usize::from(b)],
+                    f32::from(a) / 255.0,
+                ])
+            })
+        }
+        false => {
+            let mut linear = dyn_image.into_rgba32f();
+            for pixel in linear.pixels_mut() {
+                for sample in &mut pixel.0[..3] {
+                    *sample = srgb_to_linear(*sample);
+                }
+            }
+            linear
+        }
+    }
+}
+
+fn linear_to_srgb_image(linear: &image::Rgba32FImage) -> image::RgbaImage {
+    image::RgbaImage::from_fn(linear.width(), linear.height(), |x, y| {
+        let [r, g, b, a] = linear.get_pixel(x, y).0;
+        image::Rgba([
+            linear_to_srgb(r),
+            linear_to_srgb(g),
+            linear_to_srgb(b),
+            (a.clamp(0.0, 1.0) * 255.0).round() as u8,
+        ])
+    })
+}
+
+/// unsharp mask, that is stronger the more the source was reduced
+/// (none at 1x, full strength from 16x on)
+fn sharpen(thumbnail: &mut image::RgbaImage, reduction: f32) {
+    const SIGMA: f32 = 0.6;
+    const MAX_AMOUNT: f32 = 0.8;
+
+    let amount = (reduction.max(1.0).log2() / 4.0).min(1.0) * MAX_AMOUNT;
+    if amount <= 0.0 {
+        return;
+    }
+
+    let blurred = image::imageops::blur(thumbnail, SIGMA);
+    for (pixel, blurred) in thumbnail.pixels_mut().zip(blurred.pixels()) {
+        for i in 0..3 {
+            let value = f32::from(pixel[i]);
+            let sharpened = value + (value - f32::from(blurred[i])) * amount;
+            pixel[i] = sharpened.round().clamp(0.0, 255.0) as u8;
+        }
+    }
+}
+
+/// exr, hdr and float tiffs hold linear values, that can exceed 1.0
+fn is_float(dyn_image: &image::DynamicImage) -> bool {
+    matches!(dyn_image, image::DynamicImage::ImageRgb32F(_) | image::DynamicImage::ImageRgba32F(_))
+}
+
+/// maps linear float pixels to linear display values in 0.0..=1.0 (extended reinhard operator)
+///
+/// the exposure is chosen so that the log-average luminance becomes middle gray,
+/// the brightest pixel is mapped to white. NaN, infinite and negative samples become black
+fn tone_map(mut image: image::Rgba32FImage) -> image::Rgba32FImage {
+    const MIDDLE_GRAY: f32 = 0.18;
+    // keeps ln() finite for black pixels
+    const EPSILON: f32 = 1e-4;
+    // very dark images (all samples far below EPSILON) use a fraction of their brightest pixel
+    const RELATIVE_EPSILON: f32 = 1e-9;
+
+    for pixel in image.pixels_mut() {
+        for sample in &mut pixel.0[..3] {
+            if !sample.is_finite() || *sample < 0.0 {
+                *sample = 0.0;
+            }
+        }
+    }
+
+    // rec. 709 coefficients
+    let luminance = |pixel: &image::Rgba<f32>| {
+        0.2126 * pixel[0] + 0.7152 * pixel[1] + 0.0722 * pixel[2]
+    };
+
+    let max_luminance = image.pixels().map(luminance).fold(0.0, f32::max);
+    if max_luminance <= 0.0 || !max_luminance.is_finite() {
+        // black (or the sum overflowed)
+        for pixel in image.pixels_mut() {
+            pixel.0[..3].fill(0.0);
+        }
+        return image;
+    }
+    let epsilon = EPSILON.min(max_luminance * RELATIVE_EPSILON).max(f32::MIN_POSITIVE);
+
+    let pixel_count = (f64::from(image.width()) * f64::from(image.height())).max(1.0);
+    let log_sum: f64 = image
+        .pixels()
+        .map(|pixel| f64::from((luminance(pixel) + epsilon).ln()))
+        .sum();
+    let log_average = (log_sum / pixel_count).exp() as f32;
+
+    let exposure = MIDDLE_GRAY / log_average.max(epsilon);
+    let white = (max_luminance * exposure).max(EPSILON);
+
+    for pixel in image.pixels_mut() {
+        let exposed = luminance(pixel) * exposure;
+        let display = (exposed * (1.0 + exposed / (white * white))) / (1.0 + exposed);
+        // the colors are scaled by the luminance ratio, so that hues are kept
+        let scale = match exposed > 0.0 {
+            true => (display / exposed) * exposure,
+            false => 0.0,
+        };
+
+        for sample in &mut pixel.0[..3] {
+            *sample = (*sample * scale).min(1.0);
+        }
+        pixel[3] = pixel[3].clamp(0.0, 1.0);
+    }
+
+    image
+}
+
+/// inverse of the srgb transfer function (both 0.0..=1.0)
+fn srgb_to_linear(value: f32) -> f32 {
+    match value <= 0.04045 {
+        true => value / 12.92,
+        false => ((value + 0.055) / 1.055).powf(2.4),
+    }
+}
+
+/// srgb transfer function (linear 0.0..=1.0 to an 8 bit value)
+fn linear_to_srgb(value: f32) -> u8 {
+    let value = value.clamp(0.0, 1.0);
+    let encoded = match value <= 0.0031308 {
+        true => value * 12.92,
+        false => 1.055 * value.powf(1.0 / 2.4) - 0.055,
+    };
+    (encoded * 255.0).round() as u8
 }
 
 pub fn write_thumbnail(
     path: PathBuf,
     thumbs_dir: PathBuf,
-    max_x: u32,
-    max_y: u32
+    thumbnail: &image::RgbaImage,
+    embed_srgb_tag: bool
 ) -> Result<PathBuf, Box<dyn Error>> {
-    let thumbnail = generate_thumbnail_from_image(path.clone(), max_x, max_y)?;
     let img_name: String = {
         if let Some(name) = path.file_name() {
             if let Some(str) = name.to_str() { String::from(str) } else { String::from("no_name") }
@@ -164,12 +717,372 @@ pub fn write_thumbnail(
         }
     };
 
-    let format = image::ImageFormat::Png;
-    thumbnail.save_with_format(&thumb_path, format)?;
+    match embed_srgb_tag {
+        true => {
+            let mut png = Vec::new();
+            thumbnail.write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)?;
+            fs::write(&thumb_path, insert_srgb_chunk(png)?)?;
+        }
+        false => {
+            let format = image::ImageFormat::Png;
+            thumbnail.save_with_format(&thumb_path, format)?;
+        }
+    }
 
     Ok(thumb_path)
 }
 
+/// adds an `sRGB` chunk (perceptual rendering intent) directly behind the `IHDR` chunk
+fn insert_srgb_chunk(mut png: Vec<u8>) -> Result<Vec<u8>, Box<dyn Error>> {
+    // signature (8) + length, type, 13 bytes of data and crc of the IHDR chunk
+    const IHDR_END: usize = 8 + 4 + 4 + 13 + 4;
+    if png.get(12..16) != Some(b"IHDR") || png.len() < IHDR_END {
+        return Err("encoded png doesn't start with an IHDR chunk".into());
+    }
+
+    let mut crc = flate2::Crc::new();
+    crc.update(b"sRGB\x00");
+
+    let mut chunk = Vec::with_capacity(13);
+    chunk.extend(1u32.to_be_bytes());
+    chunk.extend_from_slice(b"sRGB\x00");
+    chunk.extend(crc.sum().to_be_bytes());
+
+    png.splice(IHDR_END..IHDR_END, chunk);
+    Ok(png)
+}
+
+/// upper bound for the frames of an animated preview (see `read_animation`)
+const ANIMATED_PREVIEW_MAX_FRAMES: usize = 24;
+/// every frame is decoded at the full canvas size, so decoding stops after this many frames
+const ANIMATION_MAX_DECODED_FRAMES: u32 = 1000;
+
+/// info about an animation + the frames of its preview (empty, if no preview was requested)
+pub type AnimationWithPreview = (AnimationInfo, Vec<image::Frame>);
+
+/// returns the frames of animated gifs, apngs and webps (None for still images)
+fn animation_frames(
+    path: &Path,
+    limits: &DecodeLimits
+) -> Result<Option<image::Frames<'static>>, Box<dyn Error>> {
+    use image::{ AnimationDecoder, ImageDecoder };
+
+    let format = image::ImageReader::open(path)?.with_guessed_format()?.format();
+    let buf_reader = std::io::BufReader::new(fs::File::open(path)?);
+
+    let frames = match format {
+        Some(image::ImageFormat::Gif) => {
+            let mut decoder = image::codecs::gif::GifDecoder::new(buf_reader)?;
+            decoder.set_limits(limits.to_image_limits()).map_err(limits_error)?;
+            Some(decoder.into_frames())
+        }
+        Some(image::ImageFormat::Png) => {
+            let mut decoder = image::codecs::png::PngDecoder::new(buf_reader)?;
+            decoder.set_limits(limits.to_image_limits()).map_err(limits_error)?;
+            match decoder.is_apng()? {
+                true => Some(decoder.apng()?.into_frames()),
+                false => None,
+            }
+        }
+        Some(image::ImageFormat::WebP) => {
+            let mut decoder = image::codecs::webp::WebPDecoder::new(buf_reader)?;
+            decoder.set_limits(limits.to_image_limits()).map_err(limits_error)?;
+            match decoder.has_animation() {
+                true => Some(decoder.into_frames()),
+                false => None,
+            }
+        }
+        _ => None,
+    };
+
+    Ok(frames)
+}
+
+/// counts the frames and the duration of an animated image
+///
+/// with `preview` it also returns thumbnails of every nth frame (at most
+/// 2 * `ANIMATED_PREVIEW_MAX_FRAMES`), skipped frames extend the delay of the kept ones.
+/// at most `ANIMATION_MAX_DECODED_FRAMES` are decoded
+pub fn read_animation<P>(
+    path: P,
+    max_x: u32,
+    max_y: u32,
+    preview: bool,
+    limits: &DecodeLimits
+) -> Result<Option<AnimationWithPreview>, Box<dyn Error>>
+    where P: AsRef<Path>
+{
+    let frames = match animation_frames(path.as_ref(), limits)? {
+        Some(frames) => frames,
+        None => {
+            return Ok(None);
+        }
+    };
+
+    let mut frame_count: u32 = 0;
+    let mut duration = Duration::ZERO;
+
+    // always a power of two
+    let mut step: u32 = 1;
+    let mut preview_frames = Vec::<(image::RgbaImage, Duration)>::new();
+
+    // the iterator decodes every frame, so the limit is applied before
+    for frame in frames.take(ANIMATION_MAX_DECODED_FRAMES as usize) {
+        let frame = frame?;
+        let delay = Duration::from(frame.delay());
+
+        if preview {
+            if frame_count & (step - 1) == 0 {
+                let thumbnail = image::DynamicImage
+                    ::ImageRgba8(frame.into_buffer())
+                    .thumbnail(max_x, max_y)
+                    .into_rgba8();
+                preview_frames.push((thumbnail, delay));
+            } else if let Some(last) = preview_frames.last_mut() {
+                last.1 += delay;
+            }
+
+            // too many frames -> only keep every other one
+            if preview_frames.len() > 2 * ANIMATED_PREVIEW_MAX_FRAMES {
+                preview_frames = preview_frames
+                    .chunks(2)
+                    .map(|pair| (pair[0].0.clone(), pair.iter().map(|frame| frame.1).sum()))
+                    .collect();
+                step *= 2;
+            }
+        }
+
+        frame_count += 1;
+        duration += delay;
+    }
+
+    if frame_count < 2 {
+        return Ok(None);
+    }
+    // (an animation with exactly the maximum number of frames counts as truncated as well)
+    let truncated = frame_count >= ANIMATION_MAX_DECODED_FRAMES;
+
+    let preview_frames = preview_frames
+        .into_iter()
+        .map(|(buffer, delay)| {
+            image::Frame::from_parts(buffer, 0, 0, image::Delay::from_saturating_duration(delay))
+        })
+        .collect();
+
+    Ok(Some((AnimationInfo { frame_count, duration, preview: None, truncated }, preview_frames)))
+}
+
+/// writes the preview frames as looping gif next to the thumbnail
+pub fn write_animated_preview(
+    frames: Vec<image::Frame>,
+    thumb_path: &Path
+) -> Result<PathBuf, Box<dyn Error>> {
+    // thumbnails always end with ".png", so this can't collide with another thumbnail
+    let preview_path = thumb_path.with_extension("gif");
+
+    let file = fs::File::create(&preview_path)?;
+    let mut encoder = image::codecs::gif::GifEncoder::new(std::io::BufWriter::new(file));
+    encoder.set_repeat(image::codecs::gif::Repeat::Infinite)?;
+    encoder.encode_frames(frames)?;
+
+    Ok(preview_path)
+}
+
+/// creates the thumbnail (+ extra files) for one source file with the first fitting provider
+pub fn process_file(
+    path: &Path,
+    thumbs_dir: &Path,
+    registry: &ProviderRegistry,
+    request: &ThumbnailRequest
+) -> Result<ThumbnailPaths, Box<dyn Error>> {
+    let provided = registry.provide(path, request)?;
+    let thumb_path = write_thumbnail(
+        path.to_path_buf(),
+        thumbs_dir.to_path_buf(),
+        &provided.image,
+        request.embed_srgb_tag
+    )?;
+
+    let animation = provided.animation.map(|(mut animation, frames)| {
+        if !frames.is_empty() {
+            match write_animated_preview(frames, &thumb_path) {
+                Ok(preview_path) => {
+                    animation.preview = Some(preview_path);
+                }
+                Err(err) =>
+                    log::debug!(
+                        "failed to write animated preview for {} ({err})",
+                        path.display()
+                    ),
+            }
+        }
+        animation
+    });
+
+    let storyboard = match provided.storyboard_frames.is_empty() {
+        true => None,
+        false =>
+            match write_storyboard(&provided.storyboard_frames, &thumb_path) {
+                Ok(storyboard) => Some(storyboard),
+                Err(err) => {
+                    log::debug!("failed to write storyboard for {} ({err})", path.display());
+                    None
+                }
+            }
+    };
+
+    let mut metadata = provided.metadata;
+    metadata.modified = modified_time(path);
+
+    Ok(ThumbnailPaths {
+        thumbnail: thumb_path,
+        original: path.to_path_buf(),
+        thumbnail_size: Some(provided.image.dimensions()),
+        metadata,
+        animation,
+        storyboard,
+        archive_entry: provided.archive_entry,
+        tone_mapped: provided.tone_mapped,
+        blurhash: compute_blurhash(&provided.image),
+        perceptual_hash: provided.perceptual_hash,
+        content_hash: None,
+        palette: extract_palette(&provided.image, PALETTE_SIZE),
+        feature_vector: feature_vector(&provided.image),
+        quality: provided.quality,
+    })
+}
+
+/// modification time of the file in seconds since 1970
+fn modified_time(path: &Path) -> Option<u64> {
+    fs::metadata(path)
+        .and_then(|file_metadata| file_metadata.modified())
+        .ok()
+        .and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|since_epoch| since_epoch.as_secs())
+}
+
+/// blake3 hash of the file content (streamed, so large files are never loaded at once)
+pub fn hash_file(path: &Path) -> Result<blake3::Hash, Box<dyn Error>> {
+    let mut hasher = blake3::Hasher::new();
+    std::io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize())
+}
+
+/// thumbnails of an order by content hash, files with the same content wait for (and reuse) the first one
+///
+/// failures keep the exceeded limit (if that was the reason), copies are skipped for the same one
+type ContentIndex = Mutex<
+    HBHashMap<blake3::Hash, Arc<OnceLock<Result<ThumbnailPaths, Option<LimitExceeded>>>>>
+>;
+
+/// like `process_file`, but byte-identical files (within the order) are only processed once
+///
+/// returns the thumbnail paths and whether they were taken from an identical file
+fn process_file_once(
+    path: &Path,
+    thumbs_dir: &Path,
+    registry: &ProviderRegistry,
+    request: &ThumbnailRequest,
+    content_index: &ContentIndex
+) -> Result<(ThumbnailPaths, bool), Box<dyn Error>> {
+    // files above the size limit aren't read for hashing, their provider decides
+    // whether to skip them (videos e.g. are only seeked, not read)
+    let file_size = fs::metadata(path)?.len();
+    if request.decode_limits.check_file_size(file_size).is_err() {
+        log::trace!("{} exceeds the file size limit, not hashing it", path.display());
+        return Ok((process_file(path, thumbs_dir, registry, request)?, false));
+    }
+
+    let content_hash = match hash_file(path) {
+        Ok(content_hash) => content_hash,
+        Err(err) => {
+            log::trace!("failed to hash {} ({err}), processing it anyway", path.display());
+            return Ok((process_file(path, thumbs_dir, registry, request)?, false));
+        }
+    };
+
+    let cell = match content_index.lock() {
+        Ok(mut content_index) => Arc::clone(content_index.entry(content_hash).or_default()),
+        Err(_) => {
+            return Err("content index is poisoned".into());
+        }
+    };
+
+    let mut created = None;
+    let shared = cell.get_or_init(|| {
+        let result = process_file(path, thumbs_dir, registry, request).map(|mut thumbnail_paths| {
+            thumbnail_paths.content_hash = Some(content_hash.to_hex().to_string());
+            thumbnail_paths
+        });
+        let shared = match &result {
+            Ok(thumbnail_paths) => Ok(thumbnail_paths.clone()),
+            Err(err) => Err(err.downcast_ref::<LimitExceeded>().cloned()),
+        };
+        created = Some(result);
+        shared
+    });
+
+    match (created, shared) {
+        (Some(result), _) => Ok((result?, false)),
+        (None, Ok(shared)) => {
+            let mut copy = ThumbnailPaths { original: path.to_path_buf(), ..shared.clone() };
+            // the content is shared, the file itself is not
+            copy.metadata.modified = modified_time(path);
+            Ok((copy, true))
+        }
+        (None, Err(Some(limit_exceeded))) => Err(limit_exceeded.clone().into()),
+        (None, Err(None)) => Err("an identical file failed already".into()),
+    }
+}
+
+/// compact placeholder string of the thumbnail (4 x 3 components, 3 x 4 for portrait images)
+pub fn compute_blurhash(thumbnail: &image::RgbaImage) -> Option<String> {
+    let (width, height) = thumbnail.dimensions();
+    if width == 0 || height == 0 {
+        return None;
+    }
+
+    let (components_x, components_y) = match width >= height {
+        true => (4, 3),
+        false => (3, 4),
+    };
+
+    match blurhash::encode(components_x, components_y, width, height, thumbnail.as_raw()) {
+        Ok(blurhash) => Some(blurhash),
+        Err(err) => {
+            log::debug!("failed to compute blurhash ({err})");
+            None
+        }
+    }
+}
+
+/// puts the frames next to each other (every cell has the size of the largest frame)
+pub fn compose_storyboard(frames: &[image::RgbaImage]) -> image::RgbaImage {
+    let cell_x = frames.iter().map(|frame| frame.width()).max().unwrap_or(0);
+    let cell_y = frames.iter().map(|frame| frame.height()).max().unwrap_or(0);
+
+    let mut storyboard = image::RgbaImage::new(cell_x * (frames.len() as u32), cell_y);
+    for (i, frame) in frames.iter().enumerate() {
+        let x = cell_x * (i as u32) + (cell_x - frame.width()) / 2;
+        let y = (cell_y - frame.height()) / 2;
+        image::imageops::replace(&mut storyboard, frame, i64::from(x), i64::from(y));
+    }
+
+    storyboard
+}
+
+/// writes the storyboard next to the thumbnail (as png, but without a ".png" extension,
+/// so that it can't collide with another thumbnail)
+pub fn write_storyboard(
+    frames: &[image::RgbaImage],
+    thumb_path: &Path
+) -> Result<Storyboard, Box<dyn Error>> {
+    let storyboard_path = thumb_path.with_extension("storyboard");
+    compose_storyboard(frames).save_with_format(&storyboard_path, image::ImageFormat::Png)?;
+
+    Ok(Storyboard { path: storyboard_path, frame_count: frames.len() as u32 })
+}
+
 pub struct SpawnedThumbnailer {
     pub handle: Option<thread::JoinHandle<()>>,
     pub sender: mpsc::Sender<AppToThumbnailer>,
@@ -200,7 +1113,8 @@ impl SpawnedThumbnailer {
 pub fn process_order(
     order: LoadData,
     thumb_data_tx: mpsc::Sender<ThumbnailerToApp>,
-    order_id: usize
+    order_id: usize,
+    registry: Arc<ProviderRegistry>
 ) {
     let thread_name = String::from(
         thread::current().name().unwrap_or(&format!("thumbnailer-thread_{order_id}"))
@@ -214,10 +1128,15 @@ pub fn process_order(
 
     fs::create_dir_all(&order.target_path).unwrap();
 
+    let request = ThumbnailRequest::from(&order);
+
     let (file_tx, file_rx) = mpsc::channel::<PathBuf>(); // searcher -> filter / distributor
     let mut file_senders = Vec::<mpsc::Sender<PathBuf>>::with_capacity(order.thread_count.get()); // filter / distributor -> processor_threads[]
     let (timing_tx, timing_rx) = mpsc::channel::<TimingData>(); // all threads -BENCHMARKS-> main thread
 
+    let content_index = Arc::new(ContentIndex::default());
+    let identical_count = Arc::new(AtomicUsize::new(0));
+
     // wlc message:
     log::debug!(
         "[{thread_name}]: generating thumbnails for all images in \"{}\" to \"{}\" with {} processing-threads...",
@@ -283,7 +1202,9 @@ pub fn process_order(
         let thumb_data_tx = thumb_data_tx.clone();
 
         let target_path = order.target_path.clone();
-        let (max_x, max_y) = (order.max_x, order.max_y);
+        let registry = Arc::clone(&registry);
+        let content_index = Arc::clone(&content_index);
+        let identical_count = Arc::clone(&identical_count);
 
         match
             builder.spawn(move || {
@@ -302,20 +1223,36 @@ pub fn process_order(
 
                             log::trace!("[{thread_name}]: rcvd {}", path.display());
 
-                            match write_thumbnail(path.clone(), target_path.clone(), max_x, max_y) {
-                                Ok(target_path) => {
-                                    log::debug!(
-                                        "[{thread_name}]: created thumbnail for {} at {}",
-                                        path.display(),
-                                        target_path.display()
-                                    );
+                            match
+                                process_file_once(
+                                    &path,
+                                    &target_path,
+                                    &registry,
+                                    &request,
+                                    &content_index
+                                )
+                            {
+                                Ok((thumbnail_paths, identical)) => {
+                                    match identical {
+                                        true => {
+                                            identical_count.fetch_add(1, Ordering::Relaxed);
+                                            log::debug!(
+                                                "[{thread_name}]: {} is identical to an already processed file, reusing {}",
+                                                path.display(),
+                                                thumbnail_paths.thumbnail.display()
+                                            );
+                                        }
+                                        false =>
+                                            log::debug!(
+                                                "[{thread_name}]: created thumbnail for {} at {}",
+                                                path.display(),
+                                                thumbnail_paths.thumbnail.display()
+                                            ),
+                                    }
 
                                     match
                                         thumb_data_tx.send(
-                                            ThumbnailerToApp::CreatedThumbnail(ThumbnailPaths {
-                                                thumbnail: target_path.clone(),
-                                                original: path.clone(),
-                                            })
+                                            ThumbnailerToApp::CreatedThumbnail(Box::new(thumbnail_paths))
                                         )
                                     {
                                         Ok(_) => (),
@@ -328,9 +1265,34 @@ pub fn process_order(
                                     };
                                 }
                                 Err(err) => {
-                                    log::trace!(
-                                        "[{thread_name}]: failed to open file / decode image ({err})"
-                                    );
+                                    match err.downcast::<LimitExceeded>() {
+                                        Ok(limit_exceeded) => {
+                                            log::warn!(
+                                                "[{thread_name}]: skipped {} for safety ({limit_exceeded})",
+                                                path.display()
+                                            );
+
+                                            if
+                                                let Err(err) = thumb_data_tx.send(
+                                                    ThumbnailerToApp::SkippedForSafety {
+                                                        path: path.clone(),
+                                                        reason: *limit_exceeded,
+                                                    }
+                                                )
+                                            {
+                                                log::warn!(
+                                                    "[{thread_name}]: failed to send SkippedForSafety on channel ({err})"
+                                                );
+                                                break 'recv_loop;
+                                            }
+                                        }
+                                        Err(err) => {
+                                            log::trace!(
+                                                "[{thread_name}]: failed to create thumbnail for {} ({err})",
+                                                path.display()
+                                            );
+                                        }
+                                    }
                                 }
                             }
 
@@ -484,6 +1446,10 @@ pub fn process_order(
     while let Ok(timing_data) = timing_rx.recv() {
         log::debug!("[{thread_name}]: {}", timing_data.to_string());
     }
+    log::info!(
+        "[{thread_name}]: {} files were identical copies of already processed files",
+        identical_count.load(Ordering::Relaxed)
+    );
 
     match thumb_data_tx.send(ThumbnailerToApp::Status(ThumbnailerStatus::Finished)) {
         Ok(_) => (),
@@ -494,7 +1460,9 @@ pub fn process_order(
     }
 }
 
-pub fn spawn_thumbnailer_thread() -> Result<SpawnedThumbnailer, Box<dyn Error>> {
+pub fn spawn_thumbnailer_thread(
+    registry: Arc<ProviderRegistry>
+) -> Result<SpawnedThumbnailer, Box<dyn Error>> {
     let (client_tx, thumbnailer_rx) = mpsc::channel::<AppToThumbnailer>();
     let (thumbnailer_tx, client_rx) = mpsc::channel::<ThumbnailerToApp>();
 
@@ -524,13 +1492,14 @@ pub fn spawn_thumbnailer_thread() -> Result<SpawnedThumbnailer, Box<dyn Error>>
                     let sender = Sender::clone(&thumbnailer_tx);
 
                     let order_id = orp_counter;
+                    let registry = Arc::clone(&registry);
 
                     handles.push(
                         thread::Builder
                             ::new()
                             .name(format!("order-processor-{order_id}"))
                             .spawn(move || {
-                                process_order(order, sender, order_id);
+                                process_order(*order, sender, order_id, registry);
                             })
                             .unwrap()
                     );
@@ -549,3 +1518,368 @@ pub fn spawn_thumbnailer_thread() -> Result<SpawnedThumbnailer, Box<dyn Error>>
 
     return Ok(SpawnedThumbnailer::new(handle, client_tx, client_rx));
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// srgb value of middle gray (0.18 linear)
+    const MIDDLE_GRAY_SRGB: u8 = 118;
+
+    /// an evenly lit scene at `level` with a small sun, that is 50 000 times brighter
+    fn scene_with_sun(level: f32) -> image::Rgb32FImage {
+        image::Rgb32FImage::from_fn(256, 256, |x, y| {
+            match x < 8 && y < 8 {
+                true => image::Rgb([level * 50_000.0; 3]),
+                false => image::Rgb([level; 3]),
+            }
+        })
+    }
+
+    #[test]
+    fn tone_maps_mid_tones_next_to_bright_highlights_to_middle_gray() {
+        let hdr = scene_with_sun(1.0);
+
+        let (thumbnail, _) = resample(
+            image::DynamicImage::ImageRgb32F(hdr),
+            (256, 256),
+            64,
+            64,
+            ScalingOptions::default()
+        );
+
+        let mid_tone = thumbnail.get_pixel(48, 48)[0];
+        assert!(mid_tone.abs_diff(MIDDLE_GRAY_SRGB) <= 20, "mid tone is {mid_tone}");
+        // the sun stays the brightest part
+        assert_eq!(thumbnail.get_pixel(0, 0)[0], 255);
+    }
+
+    #[test]
+    fn tone_maps_dark_scenes_by_their_own_exposure() {
+        // linear values far below the epsilon of ln()
+        let hdr = scene_with_sun(1e-7);
+
+        let (thumbnail, _) = resample(
+            image::DynamicImage::ImageRgb32F(hdr),
+            (256, 256),
+            64,
+            64,
+            ScalingOptions::default()
+        );
+
+        let mid_tone = thumbnail.get_pixel(48, 48)[0];
+        assert!(mid_tone.abs_diff(MIDDLE_GRAY_SRGB) <= 20, "mid tone is {mid_tone}");
+    }
+
+    #[test]
+    fn tone_maps_invalid_samples_to_black() {
+        let mut hdr = image::Rgba32FImage::from_pixel(4, 4, image::Rgba([0.0, 0.0, 0.0, 1.0]));
+        hdr.put_pixel(0, 0, image::Rgba([f32::NAN, f32::INFINITY, -1.0, 1.0]));
+
+        let tone_mapped = tone_map(hdr);
+        assert!(tone_mapped.pixels().all(|pixel| pixel.0 == [0.0, 0.0, 0.0, 1.0]));
+    }
+
+    fn display_p3() -> Vec<u8> {
+        moxcms::ColorProfile::new_display_p3().encode().unwrap()
+    }
+
+    #[test]
+    fn converts_16_bit_images_without_quantizing() {
+        let ramp = image::ImageBuffer::from_fn(4096, 1, |x, _| image::Rgb([(x * 16) as u16; 3]));
+
+        let converted = match icc_to_srgb(image::DynamicImage::ImageRgb16(ramp), &display_p3()) {
+            image::DynamicImage::ImageRgb16(converted) => converted,
+            converted => panic!("converted to {:?}", converted.color()),
+        };
+
+        let mut reds: Vec<u16> = converted.pixels().map(|pixel| pixel[0]).collect();
+        reds.dedup();
+        assert!(reds.len() > 1024, "only {} distinct values", reds.len());
+        // display p3 has the white point and transfer function of srgb
+        let [r, g, b] = converted.get_pixel(2048, 0).0;
+        assert!(r.abs_diff(32768) < 256 && r.abs_diff(g) < 64 && r.abs_diff(b) < 64);
+    }
+
+    #[test]
+    fn converts_wide_gamut_colors_to_srgb() {
+        let p3 = image::RgbaImage::from_pixel(2, 2, image::Rgba([200, 100, 100, 77]));
+
+        let converted = icc_to_srgb(image::DynamicImage::ImageRgba8(p3), &display_p3());
+        let [r, g, b, a] = converted.as_rgba8().unwrap().get_pixel(1, 1).0;
+
+        // the same color is more saturated in the smaller srgb gamut
+        assert!(r > 200 && g < 100 && b < 100, "converted to {:?}", [r, g, b]);
+        assert_eq!(a, 77);
+    }
+
+    #[test]
+    fn keeps_colors_with_other_or_broken_profiles() {
+        let image = image::DynamicImage::ImageRgb16(
+            image::ImageBuffer::from_pixel(2, 2, image::Rgb([1000, 20000, 40000]))
+        );
+        let gray = moxcms::ColorProfile::new_gray_with_gamma(2.2).encode().unwrap();
+        let mut broken = display_p3();
+        broken.truncate(200);
+
+        for profile in [gray, broken, Vec::new()] {
+            assert_eq!(icc_to_srgb(image.clone(), &profile), image);
+        }
+    }
+
+    #[test]
+    fn inserts_srgb_chunk_with_valid_crc() {
+        let mut png = Vec::new();
+        image::RgbImage
+            ::from_pixel(3, 2, image::Rgb([10, 20, 30]))
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+
+        let tagged = insert_srgb_chunk(png.clone()).unwrap();
+
+        // length, type, rendering intent (perceptual) and the crc of type + data
+        assert_eq!(&tagged[33..46], b"\0\0\0\x01sRGB\0\xae\xce\x1c\xe9");
+        assert_eq!(tagged.len(), png.len() + 13);
+        // the png decoder verifies the crcs
+        let decoded = image::load_from_memory_with_format(&tagged, image::ImageFormat::Png).unwrap();
+        assert_eq!(decoded.to_rgb8().get_pixel(2, 1).0, [10, 20, 30]);
+
+        assert!(insert_srgb_chunk(b"GIF89a".to_vec()).is_err());
+    }
+
+    /// counts its calls, files starting with "bomb" exceed the allocation limit
+    struct CountingProvider(Arc<AtomicUsize>);
+
+    impl ThumbnailProvider for CountingProvider {
+        fn name(&self) -> &'static str {
+            "counting"
+        }
+
+        fn can_handle(&self, _path: &Path, _header: &[u8]) -> bool {
+            true
+        }
+
+        fn provide(
+            &self,
+            path: &Path,
+            _request: &ThumbnailRequest
+        ) -> Result<ProvidedThumbnail, Box<dyn Error>> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            match fs::read(path)?.starts_with(b"bomb") {
+                true => Err(LimitExceeded::Allocation { required: 2, max: 1 }.into()),
+                false => Ok(ProvidedThumbnail::new(image::RgbaImage::new(4, 4))),
+            }
+        }
+    }
+
+    fn request() -> ThumbnailRequest {
+        ThumbnailRequest {
+            max_x: 64,
+            max_y: 64,
+            apply_orientation: true,
+            convert_to_srgb: false,
+            embed_srgb_tag: false,
+            scaling: ScalingOptions::default(),
+            decode_limits: DecodeLimits::default(),
+            animated_previews: false,
+            storyboard_frames: 0,
+        }
+    }
+
+    /// empty directory for the files of one test
+    fn test_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("thumbnailed-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("thumbs")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn processes_identical_files_once() {
+        let dir = test_dir("content-index");
+        for (name, content) in [("a", "same"), ("b", "same"), ("c", "other")] {
+            fs::write(dir.join(name), content).unwrap();
+        }
+        let calls = Arc::new(AtomicUsize::new(0));
+        let registry = ProviderRegistry::empty().with(CountingProvider(Arc::clone(&calls)));
+        let content_index = ContentIndex::default();
+
+        let process = |name| {
+            let thumbs_dir = dir.join("thumbs");
+            process_file_once(&dir.join(name), &thumbs_dir, &registry, &request(), &content_index)
+                .unwrap()
+        };
+        let (a, a_shared) = process("a");
+        let (b, b_shared) = process("b");
+        let (c, c_shared) = process("c");
+
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!((a_shared, b_shared, c_shared), (false, true, false));
+        // the copy points to the same thumbnail, but keeps its own path
+        assert_eq!(b.thumbnail, a.thumbnail);
+        assert_eq!(b.original, dir.join("b"));
+        assert_eq!(b.content_hash, a.content_hash);
+        assert_ne!(c.content_hash, a.content_hash);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn copies_of_a_bomb_are_skipped_for_the_same_limit() {
+        let dir = test_dir("content-index-bomb");
+        for name in ["a", "b"] {
+            fs::write(dir.join(name), "bomb").unwrap();
+        }
+        let calls = Arc::new(AtomicUsize::new(0));
+        let registry = ProviderRegistry::empty().with(CountingProvider(Arc::clone(&calls)));
+        let content_index = ContentIndex::default();
+
+        for name in ["a", "b"] {
+            let thumbs_dir = dir.join("thumbs");
+            let err = process_file_once(
+                &dir.join(name),
+                &thumbs_dir,
+                &registry,
+                &request(),
+                &content_index
+            ).err().unwrap();
+            assert!(matches!(err.downcast_ref(), Some(LimitExceeded::Allocation { .. })));
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn applies_all_exif_orientations() {
+        let (w, h) = (3, 2);
+        // every pixel is unique
+        let stored = image::GrayImage::from_fn(w, h, |x, y| image::Luma([(y * w + x) as u8]));
+        let at = |x: u32, y: u32| stored.get_pixel(x, y)[0];
+
+        // stored pixel, that is shown at (x, y) (the exif spec describes where row 0 / column 0 go)
+        type SourceOf = fn(u32, u32, u32, u32) -> (u32, u32);
+        let expected: [(u32, SourceOf); 8] = [
+            (1, |x, y, _, _| (x, y)),
+            (2, |x, y, w, _| (w - 1 - x, y)),
+            (3, |x, y, w, h| (w - 1 - x, h - 1 - y)),
+            (4, |x, y, _, h| (x, h - 1 - y)),
+            (5, |x, y, _, _| (y, x)),
+            (6, |x, y, _, h| (y, h - 1 - x)),
+            (7, |x, y, w, h| (w - 1 - y, h - 1 - x)),
+            (8, |x, y, w, _| (w - 1 - y, x)),
+        ];
+        for (orientation, source_of) in expected {
+            let shown = apply_exif_orientation(
+                image::DynamicImage::ImageLuma8(stored.clone()),
+                orientation
+            ).into_luma8();
+
+            let size = match orientation >= 5 {
+                true => (h, w),
+                false => (w, h),
+            };
+            assert_eq!(shown.dimensions(), size);
+            for (x, y, pixel) in shown.enumerate_pixels() {
+                let (source_x, source_y) = source_of(x, y, w, h);
+                assert_eq!(pixel[0], at(source_x, source_y), "orientation {orientation} at {x}, {y}");
+            }
+        }
+
+        // invalid orientations are ignored
+        for orientation in [0, 9] {
+            let shown = apply_exif_orientation(
+                image::DynamicImage::ImageLuma8(stored.clone()),
+                orientation
+            );
+            assert_eq!(shown.into_luma8(), stored);
+        }
+    }
+
+    /// a png with an IHDR declaring `width` x `height` and an empty IDAT chunk
+    fn png_header(width: u32, height: u32) -> Vec<u8> {
+        let mut ihdr = Vec::new();
+        ihdr.extend(width.to_be_bytes());
+        ihdr.extend(height.to_be_bytes());
+        // 8 bit rgba, deflate, adaptive filtering, no interlacing
+        ihdr.extend([8, 6, 0, 0, 0]);
+
+        let mut png = Vec::from(b"\x89PNG\r\n\x1a\n".as_slice());
+        for (chunk_type, data) in [(b"IHDR", ihdr.as_slice()), (b"IDAT", &[])] {
+            let mut crc = flate2::Crc::new();
+            crc.update(chunk_type);
+            crc.update(data);
+            png.extend((data.len() as u32).to_be_bytes());
+            png.extend_from_slice(chunk_type);
+            png.extend_from_slice(data);
+            png.extend(crc.sum().to_be_bytes());
+        }
+        png
+    }
+
+    /// a baseline jpeg, that ends after a SOF0 segment declaring `width` x `height`
+    fn jpeg_header(width: u16, height: u16) -> Vec<u8> {
+        let mut jpeg = vec![0xff, 0xd8, 0xff, 0xc0, 0x00, 0x11, 8];
+        jpeg.extend(height.to_be_bytes());
+        jpeg.extend(width.to_be_bytes());
+        // 3 components, no subsampling, all using quantization table 0
+        jpeg.extend([3, 1, 0x11, 0, 2, 0x11, 0, 3, 0x11, 0]);
+        jpeg
+    }
+
+    fn limit_exceeded(data: &[u8], limits: &DecodeLimits) -> LimitExceeded {
+        let err = generate_thumbnail_from_memory(
+            data,
+            64,
+            64,
+            true,
+            false,
+            ScalingOptions::default(),
+            limits
+        ).err().expect("decoding bomb was not rejected");
+        match err.downcast::<LimitExceeded>() {
+            Ok(exceeded) => *exceeded,
+            Err(err) => panic!("rejected with \"{err}\" instead of a limit"),
+        }
+    }
+
+    #[test]
+    fn rejects_dimension_bombs_from_the_header() {
+        let limits = DecodeLimits::default();
+
+        // the png decoder already checks the image limits, while it reads the header
+        assert!(matches!(
+            limit_exceeded(&png_header(100_000, 100_000), &limits),
+            LimitExceeded::Decoder(_)
+        ));
+        assert!(matches!(
+            limit_exceeded(&jpeg_header(60_000, 60_000), &limits),
+            LimitExceeded::Dimensions { width: 60_000, height: 60_000, .. }
+        ));
+    }
+
+    #[test]
+    fn rejects_allocation_bombs_within_the_dimensions() {
+        let limits = DecodeLimits::default();
+        let required = 16_000 * 16_000 * 4;
+
+        assert_eq!(
+            limit_exceeded(&png_header(16_000, 16_000), &limits),
+            LimitExceeded::Allocation { required, max: 512 * 1024 * 1024 }
+        );
+        assert_eq!(
+            limit_exceeded(&jpeg_header(16_000, 16_000), &limits),
+            LimitExceeded::Allocation { required, max: 512 * 1024 * 1024 }
+        );
+    }
+
+    #[test]
+    fn rejects_oversized_files_before_decoding() {
+        let limits = DecodeLimits { max_file_size: Some(16), ..DecodeLimits::default() };
+
+        assert!(matches!(
+            limit_exceeded(&png_header(1, 1), &limits),
+            LimitExceeded::FileSize { max: 16, .. }
+        ));
+    }
+}